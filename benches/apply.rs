@@ -0,0 +1,98 @@
+//! Depth updates apply throughput of Vec and BTreeMap backed books, run with `cargo bench`
+
+use binance_watcher::messages::{BookDepthUpdate, FullBook, LevelApi};
+use binance_watcher::order_book::{BTreeOrderBook, BookSide, OrderBook};
+use criterion::measurement::WallTime;
+use criterion::{
+    criterion_group, criterion_main, BatchSize, BenchmarkGroup, Criterion, Throughput,
+};
+use std::hint::black_box;
+
+const LEVELS: u32 = 1000;
+const UPDATES: u64 = 1000;
+
+fn level(price: f64, quantity: f64) -> LevelApi {
+    LevelApi {
+        price: price.to_string(),
+        quantity: quantity.to_string(),
+    }
+}
+
+fn full_book() -> FullBook {
+    FullBook {
+        last_update_id: 1,
+        bids: (0..LEVELS)
+            .map(|i| level(10_000.0 - i as f64, 1.0))
+            .collect(),
+        asks: (0..LEVELS)
+            .map(|i| level(10_001.0 + i as f64, 1.0))
+            .collect(),
+    }
+}
+
+/// updates spread over the whole book, like real ones
+fn updates() -> Vec<BookDepthUpdate> {
+    (1..=UPDATES)
+        .map(|id| {
+            let offset = ((id * 7919) % LEVELS as u64) as f64 + 0.5;
+            BookDepthUpdate {
+                e: "depthUpdate".to_string(),
+                E: id,
+                T: id,
+                s: "BTCUSDT".to_string(),
+                U: id,
+                u: id,
+                pu: Some(id - 1),
+                b: vec![level(10_000.0 - offset, id as f64)],
+                a: vec![level(10_001.0 + offset, id as f64)],
+            }
+        })
+        .collect()
+}
+
+/// applies the updates to synced books of the side, a fresh book per batch as update ids
+/// only grow, building it isn't measured
+fn bench_side<S: BookSide>(
+    group: &mut BenchmarkGroup<WallTime>,
+    name: &str,
+    new_book: fn() -> OrderBook<S>,
+    updates: &[BookDepthUpdate],
+) {
+    group.bench_function(name, |b| {
+        b.iter_batched(
+            || {
+                let mut book = new_book();
+                book.apply_full_book_from_http_api(&full_book()).unwrap();
+                book
+            },
+            |mut book| {
+                for update in updates {
+                    black_box(book.apply_depth_book_update_from_websocket(update).unwrap());
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn apply(c: &mut Criterion) {
+    let updates = updates();
+    let mut group = c.benchmark_group("apply");
+    group.throughput(Throughput::Elements(UPDATES));
+    bench_side(
+        &mut group,
+        "vec",
+        || OrderBook::new(LEVELS, "btcusdt".to_string()),
+        &updates,
+    );
+    bench_side(
+        &mut group,
+        "btree",
+        || BTreeOrderBook::with_side(LEVELS, "btcusdt".to_string()),
+        &updates,
+    );
+    group.finish();
+}
+
+criterion_group!(benches, apply);
+criterion_main!(benches);
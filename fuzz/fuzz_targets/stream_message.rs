@@ -0,0 +1,15 @@
+#![no_main]
+
+use binance_watcher::messages::{CombinedStreamMessage, StreamMessage};
+use libfuzzer_sys::fuzz_target;
+
+// any text frame is parsed, malformed ones must be errors, not panics
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = serde_json::from_slice::<StreamMessage>(data) {
+        let _ = message.symbol();
+    }
+    if let Ok(envelope) = serde_json::from_slice::<CombinedStreamMessage>(data) {
+        let _ = envelope.symbol();
+        let _ = serde_json::from_value::<StreamMessage>(envelope.data);
+    }
+});
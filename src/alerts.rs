@@ -0,0 +1,391 @@
+use crate::order_book::{BookEvent, OrderBook};
+use reqwest::header::CONTENT_TYPE;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// alerts kept for slow subscribers, the oldest are lost beyond it
+const ALERTS_CAPACITY: usize = 256;
+
+/// webhook posts longer than it are dropped, so alerting never piles up
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Conditions of a book worth alerting on
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    /// spread in bps is above the configured threshold
+    SpreadAboveThreshold,
+    /// best bid is at or above best ask
+    CrossedBook,
+    /// book hasn't been updated for the configured time
+    StaleBook,
+}
+
+/// Alert raised when a book condition starts, broadcast to the watcher alerts subscribers
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    pub symbol: String,
+    pub kind: AlertKind,
+    pub spread_bps: Option<f64>,
+    // the latest update of the book
+    pub book: BookEvent,
+    // epoch ms the alert is raised at
+    pub ts: u128,
+}
+
+impl Alert {
+    pub fn message(&self) -> String {
+        match self.kind {
+            AlertKind::SpreadAboveThreshold => format!(
+                "Spread {:.2} bps is above the threshold",
+                self.spread_bps.unwrap_or(f64::NAN)
+            ),
+            AlertKind::CrossedBook => format!(
+                "Book is crossed, best bid {:?} best ask {:?}",
+                self.book.best_bid, self.book.best_ask
+            ),
+            AlertKind::StaleBook => format!(
+                "Book hasn't been updated for {} ms",
+                self.ts.saturating_sub(self.book.ts)
+            ),
+        }
+    }
+}
+
+/// Checks books, an alert is raised once when its condition starts
+/// and re-armed when the condition ends, so it doesn't fire on every update
+pub struct BookAlerts {
+    spread_alert_bps: Option<f64>,
+    stale_after_ms: Option<u128>,
+    state: Mutex<AlertsState>,
+    events: broadcast::Sender<Alert>,
+}
+
+#[derive(Default)]
+struct AlertsState {
+    raised: HashSet<(String, AlertKind)>,
+    latest_updates: HashMap<String, (BookEvent, Option<f64>)>,
+}
+
+impl BookAlerts {
+    pub fn new(spread_alert_bps: Option<f64>, stale_after: Option<Duration>) -> Self {
+        let (events, _) = broadcast::channel(ALERTS_CAPACITY);
+        Self {
+            spread_alert_bps,
+            stale_after_ms: stale_after.map(|stale_after| stale_after.as_millis()),
+            state: Mutex::new(AlertsState::default()),
+            events,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Alert> {
+        self.events.subscribe()
+    }
+
+    /// alerts raised by the book state after an applied update, they are broadcast as well
+    pub fn check(&self, book: &OrderBook, ts: u128) -> Vec<Alert> {
+        let spread_bps = book.get_spread_bps();
+        let is_crossed = match (book.get_best_bid(), book.get_best_ask()) {
+            (Ok(bid), Ok(ask)) => bid.price >= ask.price,
+            _ => false,
+        };
+        let conditions = [
+            (
+                AlertKind::SpreadAboveThreshold,
+                matches!(
+                    (spread_bps, self.spread_alert_bps),
+                    (Some(bps), Some(threshold)) if bps > threshold
+                ),
+            ),
+            (AlertKind::CrossedBook, is_crossed),
+            // the book is just updated
+            (AlertKind::StaleBook, false),
+        ];
+
+        let event = book.event(ts);
+        let mut state = self.state.lock().unwrap();
+        state
+            .latest_updates
+            .insert(event.symbol.clone(), (event.clone(), spread_bps));
+        conditions
+            .into_iter()
+            .filter_map(|(kind, holds)| self.raise(&mut state, kind, holds, &event, spread_bps, ts))
+            .collect()
+    }
+
+    /// alerts on books not updated for the configured time, they are broadcast as well
+    pub fn check_stale(&self, now: u128) -> Vec<Alert> {
+        let Some(stale_after_ms) = self.stale_after_ms else {
+            return vec![];
+        };
+        let mut state = self.state.lock().unwrap();
+        let mut latest_updates: Vec<(BookEvent, Option<f64>)> =
+            state.latest_updates.values().cloned().collect();
+        latest_updates.sort_by(|(a, _), (b, _)| a.symbol.cmp(&b.symbol));
+        latest_updates
+            .into_iter()
+            .filter_map(|(event, spread_bps)| {
+                let is_stale = now.saturating_sub(event.ts) >= stale_after_ms;
+                self.raise(
+                    &mut state,
+                    AlertKind::StaleBook,
+                    is_stale,
+                    &event,
+                    spread_bps,
+                    now,
+                )
+            })
+            .collect()
+    }
+
+    /// the symbol isn't watched anymore, so it's neither stale nor alerted
+    pub fn forget(&self, symbol: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.latest_updates.remove(symbol);
+        state.raised.retain(|(raised, _)| raised != symbol);
+    }
+
+    /// the alert if its condition has just started, the condition ended re-arms the alert
+    fn raise(
+        &self,
+        state: &mut AlertsState,
+        kind: AlertKind,
+        holds: bool,
+        event: &BookEvent,
+        spread_bps: Option<f64>,
+        ts: u128,
+    ) -> Option<Alert> {
+        let key = (event.symbol.clone(), kind);
+        if !holds {
+            state.raised.remove(&key);
+            return None;
+        }
+        if !state.raised.insert(key) {
+            return None;
+        }
+        let alert = Alert {
+            symbol: event.symbol.clone(),
+            kind,
+            spread_bps,
+            book: event.clone(),
+            ts,
+        };
+        // nobody may be subscribed, it's fine
+        let _ = self.events.send(alert.clone());
+        Some(alert)
+    }
+}
+
+/// posts every alert as json to the url, fire and forget, so a slow webhook never blocks
+pub async fn post_alerts(
+    http_client: reqwest::Client,
+    url: String,
+    mut alerts: broadcast::Receiver<Alert>,
+) {
+    loop {
+        let alert = match alerts.recv().await {
+            Ok(alert) => alert,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+        let request = http_client
+            .post(&url)
+            .timeout(WEBHOOK_TIMEOUT)
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&alert).unwrap());
+        tokio::spawn(async move {
+            let result = request
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                warn!(
+                    symbol = alert.symbol.as_str(),
+                    "Failed to post alert to webhook ({e})"
+                );
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::order_book::test_book;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn spread_alert_test() {
+        let alerts = BookAlerts::new(Some(50.0), None);
+        let mut receiver = alerts.subscribe();
+        let kinds = |alerts: Vec<Alert>| -> Vec<AlertKind> {
+            alerts.iter().map(|alert| alert.kind).collect()
+        };
+
+        // spread of 20 bps
+        assert!(alerts
+            .check(
+                &test_book("btcusdt", &[["99.9", "1"]], &[["100.1", "1"]]),
+                1
+            )
+            .is_empty());
+        // spread of 200 bps raises the alert once while it holds
+        let raised = alerts.check(&test_book("btcusdt", &[["99", "1"]], &[["101", "1"]]), 2);
+        assert_eq!(kinds(raised.clone()), vec![AlertKind::SpreadAboveThreshold]);
+        assert_eq!(raised[0].spread_bps, Some(200.0));
+        assert_eq!(raised[0].symbol, "btcusdt");
+        assert!(alerts
+            .check(&test_book("btcusdt", &[["99", "1"]], &[["101", "1"]]), 3)
+            .is_empty());
+        // other symbols are alerted on their own
+        assert_eq!(
+            alerts
+                .check(&test_book("ethusdt", &[["9", "1"]], &[["11", "1"]]), 4)
+                .len(),
+            1
+        );
+
+        // spread recovers and re-arms the alert
+        assert!(alerts
+            .check(
+                &test_book("btcusdt", &[["99.9", "1"]], &[["100.1", "1"]]),
+                5
+            )
+            .is_empty());
+        assert_eq!(
+            alerts
+                .check(&test_book("btcusdt", &[["98", "1"]], &[["102", "1"]]), 6)
+                .len(),
+            1
+        );
+
+        let broadcast: Vec<(String, u128)> = std::iter::from_fn(|| receiver.try_recv().ok())
+            .map(|alert| (alert.symbol, alert.ts))
+            .collect();
+        assert_eq!(
+            broadcast,
+            vec![
+                ("btcusdt".to_string(), 2),
+                ("ethusdt".to_string(), 4),
+                ("btcusdt".to_string(), 6)
+            ]
+        );
+
+        // no threshold, no alerts
+        let alerts = BookAlerts::new(None, None);
+        assert!(alerts
+            .check(&test_book("btcusdt", &[["9", "1"]], &[["11", "1"]]), 1)
+            .is_empty());
+    }
+
+    #[test]
+    fn crossed_and_stale_book_alert_test() {
+        let alerts = BookAlerts::new(None, Some(Duration::from_secs(5)));
+
+        let raised = alerts.check(
+            &test_book("btcusdt", &[["101", "1"]], &[["100", "1"]]),
+            1_000,
+        );
+        assert_eq!(raised.len(), 1);
+        assert_eq!(raised[0].kind, AlertKind::CrossedBook);
+        assert!(alerts
+            .check(
+                &test_book("btcusdt", &[["100", "1"]], &[["100", "1"]]),
+                1_500
+            )
+            .is_empty());
+        alerts.check(&test_book("ethusdt", &[["9", "1"]], &[["11", "1"]]), 2_000);
+
+        assert!(alerts.check_stale(6_000).is_empty());
+        let raised = alerts.check_stale(6_500);
+        assert_eq!(raised.len(), 1);
+        assert_eq!(raised[0].kind, AlertKind::StaleBook);
+        assert_eq!(raised[0].message(), "Book hasn't been updated for 5000 ms");
+        assert_eq!(alerts.check_stale(7_000).len(), 1);
+        assert!(alerts.check_stale(8_000).is_empty());
+
+        // update re-arms the stale alert
+        alerts.check(
+            &test_book("btcusdt", &[["99", "1"]], &[["100", "1"]]),
+            9_000,
+        );
+        assert_eq!(alerts.check_stale(14_000).len(), 1);
+        assert!(BookAlerts::new(None, None)
+            .check_stale(u128::MAX)
+            .is_empty());
+
+        // removed symbols aren't stale, they are alerted again once watched again
+        alerts.forget("btcusdt");
+        alerts.forget("ethusdt");
+        assert!(alerts.check_stale(u128::MAX).is_empty());
+        let raised = alerts.check(
+            &test_book("btcusdt", &[["101", "1"]], &[["100", "1"]]),
+            20_000,
+        );
+        assert_eq!(raised[0].kind, AlertKind::CrossedBook);
+    }
+
+    #[tokio::test]
+    async fn post_alerts_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        let (requests, mut received) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let requests = requests.clone();
+                tokio::spawn(async move {
+                    let mut request = vec![];
+                    let mut buffer = [0u8; 4096];
+                    // body is complete once it's a json object
+                    while let Ok(read) = socket.read(&mut buffer).await {
+                        request.extend_from_slice(&buffer[..read]);
+                        let text = String::from_utf8_lossy(&request).to_string();
+                        if read == 0 || text.ends_with('}') {
+                            break;
+                        }
+                    }
+                    let response = "HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n";
+                    socket.write_all(response.as_bytes()).await.unwrap();
+                    requests.send(String::from_utf8(request).unwrap()).unwrap();
+                });
+            }
+        });
+
+        let alerts = BookAlerts::new(None, None);
+        tokio::spawn(post_alerts(reqwest::Client::new(), url, alerts.subscribe()));
+        // locked book, best bid equals best ask
+        alerts.check(
+            &test_book("btcusdt", &[["100", "1"]], &[["100", "1"]]),
+            1_000,
+        );
+
+        let request = received.recv().await.unwrap();
+        assert!(request.starts_with("POST /alerts HTTP/1.1\r\n"));
+        assert!(request.contains("content-type: application/json\r\n"));
+        let body = request.split("\r\n\r\n").nth(1).unwrap();
+        let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "symbol": "btcusdt",
+                "kind": "crossed_book",
+                "spread_bps": 0.0,
+                "book": {
+                    "symbol": "btcusdt",
+                    "mid": 100.0,
+                    "spread": 0.0,
+                    "best_bid": {"quantity": 1.0, "price": 100.0},
+                    "best_ask": {"quantity": 1.0, "price": 100.0},
+                    "ts": 1000
+                },
+                "ts": 1000
+            })
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// Commands changing watched symbols at runtime, e.g. `add ethusdt` or `remove btcusdt`
+#[derive(Clone, Debug, PartialEq)]
+pub enum SymbolCommand {
+    Add(String),
+    Remove(String),
+}
+
+impl FromStr for SymbolCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let (Some(command), Some(symbol), None) = (words.next(), words.next(), words.next()) else {
+            return Err("expected '<add|remove> <symbol>'".to_string());
+        };
+        let symbol = symbol.to_lowercase();
+        match command {
+            "add" => Ok(SymbolCommand::Add(symbol)),
+            "remove" => Ok(SymbolCommand::Remove(symbol)),
+            _ => Err(format!("unknown command '{command}'")),
+        }
+    }
+}
+
+/// Routes commands to the connections, added symbols go to the connection with the fewest ones
+#[derive(Default)]
+pub struct CommandRouter {
+    connections: Vec<RoutedConnection>,
+}
+
+struct RoutedConnection {
+    sender: UnboundedSender<SymbolCommand>,
+    symbols: HashSet<String>,
+}
+
+impl CommandRouter {
+    /// registers a connection watching the symbols, it receives its commands from the receiver
+    pub fn add_connection(&mut self, symbols: &[String]) -> UnboundedReceiver<SymbolCommand> {
+        let (sender, receiver) = unbounded_channel();
+        self.connections.push(RoutedConnection {
+            sender,
+            symbols: symbols.iter().cloned().collect(),
+        });
+        receiver
+    }
+
+    pub fn route(&mut self, command: SymbolCommand) -> Result<(), String> {
+        let connection = match &command {
+            SymbolCommand::Add(symbol) => {
+                if self.position(symbol).is_some() {
+                    return Err(format!("{symbol} is watched already"));
+                }
+                let connection = self
+                    .connections
+                    .iter_mut()
+                    .min_by_key(|connection| connection.symbols.len())
+                    .ok_or("no connections")?;
+                connection.symbols.insert(symbol.clone());
+                connection
+            }
+            SymbolCommand::Remove(symbol) => {
+                let position = self
+                    .position(symbol)
+                    .ok_or(format!("{symbol} isn't watched"))?;
+                let connection = &mut self.connections[position];
+                connection.symbols.remove(symbol);
+                connection
+            }
+        };
+        connection
+            .sender
+            .send(command)
+            .map_err(|_| "connection is closed".to_string())
+    }
+
+    /// the symbol isn't watched anymore without a command, e.g. binance doesn't know it
+    pub fn forget(&mut self, symbol: &str) {
+        for connection in self.connections.iter_mut() {
+            connection.symbols.remove(symbol);
+        }
+    }
+
+    fn position(&self, symbol: &str) -> Option<usize> {
+        self.connections
+            .iter()
+            .position(|connection| connection.symbols.contains(symbol))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_command_test() {
+        assert_eq!(
+            "add ETHUSDT".parse(),
+            Ok(SymbolCommand::Add("ethusdt".to_string()))
+        );
+        assert_eq!(
+            "  remove   btcusdt ".parse(),
+            Ok(SymbolCommand::Remove("btcusdt".to_string()))
+        );
+        assert!("add".parse::<SymbolCommand>().is_err());
+        assert!("add btcusdt ethusdt".parse::<SymbolCommand>().is_err());
+        assert_eq!(
+            "watch btcusdt".parse::<SymbolCommand>(),
+            Err("unknown command 'watch'".to_string())
+        );
+    }
+
+    #[test]
+    fn command_router_test() {
+        let mut router = CommandRouter::default();
+        let add = |symbol: &str| SymbolCommand::Add(symbol.to_string());
+        let remove = |symbol: &str| SymbolCommand::Remove(symbol.to_string());
+        assert!(router.route(add("btcusdt")).is_err());
+
+        let mut first = router.add_connection(&["btcusdt".to_string(), "ethusdt".to_string()]);
+        let mut second = router.add_connection(&["bnbusdt".to_string()]);
+
+        router.route(add("solusdt")).unwrap();
+        assert_eq!(second.try_recv(), Ok(add("solusdt")));
+        router.route(add("xrpusdt")).unwrap();
+        assert!(second.try_recv().is_err());
+        assert_eq!(first.try_recv(), Ok(add("xrpusdt")));
+        assert_eq!(
+            router.route(add("bnbusdt")),
+            Err("bnbusdt is watched already".to_string())
+        );
+
+        router.route(remove("bnbusdt")).unwrap();
+        assert_eq!(second.try_recv(), Ok(remove("bnbusdt")));
+        assert_eq!(
+            router.route(remove("bnbusdt")),
+            Err("bnbusdt isn't watched".to_string())
+        );
+
+        router.forget("btcusdt");
+        assert_eq!(
+            router.route(remove("btcusdt")),
+            Err("btcusdt isn't watched".to_string())
+        );
+        router.route(add("btcusdt")).unwrap();
+        assert_eq!(second.try_recv(), Ok(add("btcusdt")));
+    }
+}
@@ -1,70 +1,1545 @@
-use clap::Parser;
+pub use crate::order_book::Market;
+use crate::order_book::{DEFAULT_EMA_ALPHA, DEFAULT_LEVELS, DEFAULT_TRADE_WINDOW_SECS};
+use crate::recorder::record_path;
+use clap::builder::PossibleValue;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
 use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
 
 /// Help page configuration struct
 #[derive(Parser, Default)]
 #[command(version)]
 pub struct Config {
     /// number of active connections to binance allowed to open, can be less, if instruments.len < connections
-    #[arg(short, long, default_value_t = 1, value_parser=clap::value_parser!(u32).range(1..200))]
+    #[arg(short, long, default_value_t = 1, value_parser=clap::value_parser!(u32).range(1..200), env = "BW_CONNECTIONS")]
     pub connections: u32,
 
-    /// number of levels to display
-    #[arg(short, long, default_value_t = 20, value_parser=clap::value_parser!(u32).range(1..200))]
+    /// number of levels to display, full book is requested with the closest binance limit above
+    #[arg(short, long, default_value_t = DEFAULT_LEVELS, value_parser=clap::value_parser!(u32).range(1..200), env = "BW_LEVELS")]
     pub levels: u32,
 
-    /// delay between updates displayed in ms (not supported)
-    #[arg(short, long, default_value_t = 1000, value_parser=clap::value_parser!(u32).range(1..2000000))]
+    /// interval of book displays in ms, unchanged books are not displayed again
+    #[arg(short, long, default_value_t = 1000, value_parser=clap::value_parser!(u32).range(1..2000000), env = "BW_DELAY")]
     pub delay: u32,
 
-    /// instruments to watch
-    #[arg(short, long, default_values_t = ["btcusdt".to_string()])]
+    /// max silence on websocket in ms, after that connection is considered dead and reopened
+    #[arg(long, default_value_t = 30000, value_parser=clap::value_parser!(u32).range(1..), env = "BW_READ_TIMEOUT")]
+    pub read_timeout: u32,
+
+    /// frames read from a websocket waiting to be applied, frames are dropped while it's full,
+    /// books with dropped depth updates are synced again [default: 4096]
+    #[arg(long, value_parser=clap::value_parser!(u32).range(1..), env = "BW_FRAME_QUEUE_CAPACITY")]
+    pub frame_queue_capacity: Option<u32>,
+
+    /// pause in ms between connections openings and between full book requests of a connection,
+    /// to respect binance rate limits
+    #[arg(long, default_value_t = 200, env = "BW_STAGGER")]
+    pub stagger: u64,
+
+    /// full book requests failed by network or binance server errors are retried
+    /// after a fixed pause, the symbol is resynced later once they are exhausted,
+    /// after a pause doubled per failed resync in a row, up to a minute
+    #[arg(long, default_value_t = 2, env = "BW_SNAPSHOT_RETRIES")]
+    pub snapshot_retries: u32,
+
+    /// max full book requests via http api in flight over all connections
+    #[arg(long, default_value_t = 2, value_parser=clap::value_parser!(u32).range(1..), env = "BW_MAX_CONCURRENT_REQUESTS")]
+    pub max_concurrent_requests: u32,
+
+    /// max time in ms to open websocket connection, after that connection is attempted again
+    #[arg(long, default_value_t = 10000, value_parser=clap::value_parser!(u32).range(1..), env = "BW_CONNECT_TIMEOUT")]
+    pub connect_timeout: u32,
+
+    /// offer permessage-deflate compression of websocket frames, less bandwidth for some cpu
+    /// on high volume symbols, frames aren't compressed if binance declines it
+    #[arg(long, env = "BW_COMPRESS_WS")]
+    pub compress_ws: bool,
+
+    /// instruments to watch, btcusdt if neither instruments nor instruments file are given,
+    /// levels could be set per instrument as symbol:levels, e.g. btcusdt:100
+    #[arg(short, long, env = "BW_INSTRUMENTS", value_delimiter = ',')]
     pub instruments: Vec<String>,
 
-    /// websocket binance url
-    #[arg(long, default_value = "wss://fstream.binance.com/ws")]
-    pub ws_api_url: String,
+    /// levels of instruments given as symbol:levels, --levels for the others
+    #[arg(skip)]
+    pub instrument_levels: BTreeMap<String, u32>,
+
+    /// file with instruments to watch, one per line, blank lines and # comments are ignored,
+    /// merged with instruments
+    #[arg(long, env = "BW_INSTRUMENTS_FILE")]
+    pub instruments_file: Option<String>,
+
+    /// websocket binance url [default: depends on market and testnet]
+    #[arg(long, env = "BW_WS_API_URL")]
+    pub ws_api_url: Option<String>,
+
+    /// use binance combined streams endpoint, streams are passed in url instead of subscription
+    #[arg(long, default_value_t = false, env = "BW_COMBINED")]
+    pub combined: bool,
+
+    /// depth updates cadence
+    #[arg(long, value_enum, default_value_t = UpdateSpeed::Ms250, env = "BW_UPDATE_SPEED")]
+    pub update_speed: UpdateSpeed,
+
+    /// subscribe to top levels of the book (5, 10 or 20 fitting levels) replacing it on every update,
+    /// instead of depth updates synced with full books, spot requires --combined
+    #[arg(long, env = "BW_PARTIAL_DEPTH")]
+    pub partial_depth: bool,
+
+    /// streams to subscribe per instrument, comma separated
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [StreamType::Depth, StreamType::AggTrade, StreamType::BookTicker], env = "BW_STREAMS")]
+    pub streams: Vec<StreamType>,
+
+    /// subscribe to mark price and funding rate too, futures only
+    #[arg(long, env = "BW_MARK_PRICE")]
+    pub mark_price: bool,
+
+    /// subscribe to 24hr rolling window ticker too, displayed in the header
+    #[arg(long, env = "BW_TICKER24H")]
+    pub ticker24h: bool,
+
+    /// subscribe to candles of the interval too, the latest one is displayed
+    #[arg(long, value_parser = KLINE_INTERVALS, env = "BW_KLINE")]
+    pub kline: Option<String>,
+
+    /// subscribe to liquidation orders too, they are logged, futures only
+    #[arg(long, env = "BW_LIQUIDATIONS")]
+    pub liquidations: bool,
+
+    /// print connections, topics and urls to be used, then exit without connecting
+    #[arg(long, env = "BW_DRY_RUN")]
+    pub dry_run: bool,
+
+    /// exit if any instrument is unknown or not trading, they are skipped otherwise
+    #[arg(long, env = "BW_STRICT")]
+    pub strict: bool,
+
+    /// market of the instruments, defines depth updates sequence rules and default urls
+    #[arg(long, value_enum, default_value_t = Market::Futures, env = "BW_MARKET")]
+    pub market: Market,
+
+    /// use binance testnet urls by default
+    #[arg(long, default_value_t = false, env = "BW_TESTNET")]
+    pub testnet: bool,
+
+    /// books output format
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Table, env = "BW_FORMAT")]
+    pub format: OutputFormat,
+
+    /// displayed price decimals, tick size decimals from exchangeInfo by default
+    #[arg(long, env = "BW_PRICE_DECIMALS")]
+    pub price_decimals: Option<u32>,
+
+    /// displayed quantity decimals, step size decimals from exchangeInfo by default
+    #[arg(long, env = "BW_QTY_DECIMALS")]
+    pub qty_decimals: Option<u32>,
+
+    /// print a json line per applied depth update instead of rendered books
+    #[arg(long, env = "BW_NDJSON")]
+    pub ndjson: bool,
+
+    /// single line per book update, same as --format compact
+    #[arg(long, conflicts_with = "format", env = "BW_COMPACT")]
+    pub compact: bool,
+
+    /// clear the screen before each display, so books are updated in place
+    #[arg(long, env = "BW_LIVE")]
+    pub live: bool,
+
+    /// green bids, red asks and bold changed levels in the table, unless NO_COLOR or not a terminal
+    #[arg(long, env = "BW_COLOR")]
+    pub color: bool,
+
+    /// display the latest trades below the book
+    #[arg(long, env = "BW_TAPE")]
+    pub tape: bool,
+
+    /// number of the latest trades kept for --tape
+    #[arg(long, default_value_t = 20, value_parser=clap::value_parser!(u64).range(1..1000), env = "BW_TRADE_TAPE_SIZE")]
+    pub trade_tape_size: u64,
+
+    /// weight of the latest mid in its exponential moving average, in (0, 1]
+    #[arg(long, default_value_t = DEFAULT_EMA_ALPHA, value_parser = parse_ema_alpha, env = "BW_EMA_ALPHA")]
+    pub ema_alpha: f64,
+
+    /// seconds of the latest trades averaged in VWAP
+    #[arg(long, default_value_t = DEFAULT_TRADE_WINDOW_SECS, value_parser=clap::value_parser!(u64).range(1..), env = "BW_TRADE_WINDOW_SECS")]
+    pub trade_window_secs: u64,
 
-    /// api binance url
-    #[arg(long, default_value = " https://fapi.binance.com/fapi/v1")]
-    pub api_url: String,
+    /// spread in bps to warn about, once until the spread is back within it, disabled if not set
+    #[arg(long, env = "BW_SPREAD_ALERT_BPS")]
+    pub spread_alert_bps: Option<f64>,
+
+    /// seconds without updates a book is warned about as stale, disabled if not set
+    #[arg(long, value_parser=clap::value_parser!(u64).range(1..), env = "BW_STALE_BOOK_SECS")]
+    pub stale_book_secs: Option<u64>,
+
+    /// url alerts are posted to as json, disabled if not set
+    #[arg(long, env = "BW_WEBHOOK_URL")]
+    pub webhook_url: Option<String>,
+
+    /// port to serve prometheus metrics on /metrics, disabled if not set
+    #[arg(long, env = "BW_METRICS_PORT")]
+    pub metrics_port: Option<u16>,
+
+    /// local port to serve books json on /book/{symbol}, disabled if not set
+    #[arg(long, env = "BW_HTTP_PORT")]
+    pub http_port: Option<u16>,
+
+    /// address metrics and books are served on [default: 127.0.0.1]
+    #[arg(long, env = "BW_HTTP_BIND")]
+    pub http_bind: Option<IpAddr>,
+
+    /// file to record received websocket text frames to, one per line with receive epoch ms
+    #[arg(long, env = "BW_RECORD")]
+    pub record: Option<String>,
+
+    /// directory to append books snapshots to, as json lines in {symbol}.ndjson files
+    #[arg(long, env = "BW_SNAPSHOT_DIR")]
+    pub snapshot_dir: Option<String>,
+
+    /// interval in seconds of books snapshots written to --snapshot-dir
+    #[arg(long, default_value_t = 60, value_parser=clap::value_parser!(u64).range(1..), env = "BW_SNAPSHOT_INTERVAL_SECS")]
+    pub snapshot_interval_secs: u64,
+
+    /// size in bytes above which --record and --snapshot-dir files move on to {name}.1, {name}.2, ...
+    #[arg(long, value_parser=clap::value_parser!(u64).range(1..), env = "BW_MAX_FILE_SIZE")]
+    pub max_file_size: Option<u64>,
+
+    /// gzip compress --record and --snapshot-dir files, recordings get a .gz suffix,
+    /// snapshots go to {symbol}.ndjson.gz files, compressed recordings are replayed as they are
+    #[arg(long, env = "BW_COMPRESS")]
+    pub compress: bool,
+
+    /// interval in seconds to compare books with full books via http api, drift is logged
+    #[arg(long, value_parser=clap::value_parser!(u64).range(1..), env = "BW_RECONCILE_INTERVAL")]
+    pub reconcile_interval: Option<u64>,
+
+    /// seconds given to connections to close after ctrl-c or SIGTERM, the program is exited after that
+    #[arg(long, default_value_t = 10, env = "BW_SHUTDOWN_TIMEOUT")]
+    pub shutdown_timeout: u64,
+
+    /// max level of logs written to stderr
+    #[arg(long, value_enum, default_value_t = LogLevel::Info, env = "BW_LOG_LEVEL")]
+    pub log_level: LogLevel,
+
+    /// toml file with options by their long names, e.g. levels = 10 or instruments = ["btcusdt"],
+    /// flags and env vars win over its values
+    #[arg(long, env = "BW_CONFIG")]
+    pub config: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// api binance url [default: depends on market and testnet]
+    #[arg(long, env = "BW_API_URL")]
+    pub api_url: Option<String>,
+}
+
+/// Subcommands, books are watched live if none is given
+#[derive(Subcommand, Clone, Debug, PartialEq)]
+pub enum Command {
+    /// reconstruct books from a file recorded with --record, without network
+    Replay {
+        /// recorded file
+        path: String,
+
+        /// replay with recorded pace multiplied by speed, as fast as possible if not set
+        #[arg(long, value_parser = parse_replay_speed)]
+        speed: Option<f64>,
+    },
+}
+
+/// Depth updates cadences allowed by binance
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum UpdateSpeed {
+    #[value(name = "100ms")]
+    Ms100,
+    #[default]
+    #[value(name = "250ms")]
+    Ms250,
+    #[value(name = "500ms")]
+    Ms500,
+}
+
+impl UpdateSpeed {
+    /// depth stream name suffix, 250ms is binance default and has no suffix
+    pub fn depth_stream_suffix(&self) -> &'static str {
+        match self {
+            UpdateSpeed::Ms100 => "@100ms",
+            UpdateSpeed::Ms250 => "",
+            UpdateSpeed::Ms500 => "@500ms",
+        }
+    }
+}
+
+/// Streams which could be subscribed per instrument
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum StreamType {
+    /// diff book depth
+    #[value(name = "depth")]
+    Depth,
+    /// aggregated trades
+    #[value(name = "aggTrade")]
+    AggTrade,
+    /// best bid and ask
+    #[value(name = "bookTicker")]
+    BookTicker,
+}
+
+/// --market values, the market itself belongs to the books
+impl ValueEnum for Market {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Market::Futures, Market::Spot]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Market::Futures => {
+                PossibleValue::new("futures").help("usd-m futures, updates are chained by pu")
+            }
+            Market::Spot => {
+                PossibleValue::new("spot").help("spot, updates are chained by U == previous u + 1")
+            }
+        })
+    }
 }
 
+/// Books output formats
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    /// ascii table
+    #[default]
+    Table,
+    /// json snapshot per line
+    Json,
+    /// csv line with top of book
+    Csv,
+    /// single line with top of book
+    Compact,
+}
+
+/// Logs levels, each one includes the less verbose ones
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for tracing::Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => tracing::Level::ERROR,
+            LogLevel::Warn => tracing::Level::WARN,
+            LogLevel::Info => tracing::Level::INFO,
+            LogLevel::Debug => tracing::Level::DEBUG,
+            LogLevel::Trace => tracing::Level::TRACE,
+        }
+    }
+}
+
+fn parse_ema_alpha(value: &str) -> Result<f64, String> {
+    let alpha: f64 = value.parse().map_err(|e| format!("{e}"))?;
+    if alpha > 0.0 && alpha <= 1.0 {
+        Ok(alpha)
+    } else {
+        Err(format!("{alpha} isn't in (0, 1]"))
+    }
+}
+
+/// Options of the --config file, named like the long flags, unknown ones are rejected
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct FileConfig {
+    connections: Option<u32>,
+    levels: Option<u32>,
+    delay: Option<u32>,
+    read_timeout: Option<u32>,
+    frame_queue_capacity: Option<u32>,
+    stagger: Option<u64>,
+    snapshot_retries: Option<u32>,
+    max_concurrent_requests: Option<u32>,
+    connect_timeout: Option<u32>,
+    compress_ws: Option<bool>,
+    instruments: Option<Vec<String>>,
+    instruments_file: Option<String>,
+    ws_api_url: Option<String>,
+    combined: Option<bool>,
+    #[serde(default, deserialize_with = "value_enum")]
+    update_speed: Option<UpdateSpeed>,
+    partial_depth: Option<bool>,
+    #[serde(default, deserialize_with = "value_enums")]
+    streams: Option<Vec<StreamType>>,
+    mark_price: Option<bool>,
+    ticker24h: Option<bool>,
+    kline: Option<String>,
+    liquidations: Option<bool>,
+    dry_run: Option<bool>,
+    strict: Option<bool>,
+    #[serde(default, deserialize_with = "value_enum")]
+    market: Option<Market>,
+    testnet: Option<bool>,
+    #[serde(default, deserialize_with = "value_enum")]
+    format: Option<OutputFormat>,
+    price_decimals: Option<u32>,
+    qty_decimals: Option<u32>,
+    ndjson: Option<bool>,
+    compact: Option<bool>,
+    live: Option<bool>,
+    color: Option<bool>,
+    tape: Option<bool>,
+    trade_tape_size: Option<u64>,
+    ema_alpha: Option<f64>,
+    trade_window_secs: Option<u64>,
+    spread_alert_bps: Option<f64>,
+    stale_book_secs: Option<u64>,
+    webhook_url: Option<String>,
+    metrics_port: Option<u16>,
+    http_port: Option<u16>,
+    http_bind: Option<IpAddr>,
+    record: Option<String>,
+    snapshot_dir: Option<String>,
+    snapshot_interval_secs: Option<u64>,
+    max_file_size: Option<u64>,
+    compress: Option<bool>,
+    reconcile_interval: Option<u64>,
+    shutdown_timeout: Option<u64>,
+    #[serde(default, deserialize_with = "value_enum")]
+    log_level: Option<LogLevel>,
+    api_url: Option<String>,
+}
+
+impl FileConfig {
+    /// sets the file values of options which weren't given by args or env vars,
+    /// values are checked by the value parsers of the flags
+    fn apply(self, config: &mut Config, matches: &ArgMatches) -> Result<(), clap::Error> {
+        let command = Config::command();
+        let is_default = |id: &str| {
+            matches!(
+                matches.value_source(id),
+                None | Some(ValueSource::DefaultValue)
+            )
+        };
+        // values checked like flag values, Option fields of the config get Some of them
+        macro_rules! set_parsed {
+            ($($field:ident),*) => {$(
+                if let Some(value) = self.$field {
+                    if is_default(stringify!($field)) {
+                        check_value(&command, stringify!($field), &value.to_string())?;
+                        config.$field = value.into();
+                    }
+                }
+            )*};
+        }
+        // flags, enums and instruments are checked by their deserialization already
+        macro_rules! set {
+            ($($field:ident),*) => {$(
+                if let Some(value) = self.$field {
+                    if is_default(stringify!($field)) {
+                        config.$field = value;
+                    }
+                }
+            )*};
+        }
+        set_parsed!(
+            connections,
+            levels,
+            delay,
+            read_timeout,
+            frame_queue_capacity,
+            stagger,
+            snapshot_retries,
+            max_concurrent_requests,
+            connect_timeout,
+            instruments_file,
+            ws_api_url,
+            kline,
+            price_decimals,
+            qty_decimals,
+            trade_tape_size,
+            ema_alpha,
+            trade_window_secs,
+            spread_alert_bps,
+            stale_book_secs,
+            webhook_url,
+            metrics_port,
+            http_port,
+            http_bind,
+            record,
+            snapshot_dir,
+            snapshot_interval_secs,
+            max_file_size,
+            reconcile_interval,
+            shutdown_timeout,
+            api_url
+        );
+        set!(
+            compress_ws,
+            instruments,
+            combined,
+            update_speed,
+            partial_depth,
+            streams,
+            mark_price,
+            ticker24h,
+            liquidations,
+            dry_run,
+            strict,
+            market,
+            testnet,
+            format,
+            ndjson,
+            compact,
+            live,
+            color,
+            tape,
+            compress,
+            log_level
+        );
+        Ok(())
+    }
+}
+
+/// value checked by the value parser of its flag, so file values fail alike
+fn check_value(command: &clap::Command, id: &str, value: &str) -> Result<(), clap::Error> {
+    let arg = command
+        .get_arguments()
+        .find(|arg| arg.get_id() == id)
+        .expect("config file options are flags");
+    let long = arg.get_long().expect("config file options are long flags");
+    clap::Command::new("config")
+        .no_binary_name(true)
+        .arg(arg.clone())
+        .try_get_matches_from([format!("--{long}={value}")])
+        .map(|_| ())
+}
+
+/// value enum by its flag value name, e.g. "250ms" or "aggTrade"
+fn value_enum<'de, D: Deserializer<'de>, T: ValueEnum>(
+    deserializer: D,
+) -> Result<Option<T>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    T::from_str(&value, false)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn value_enums<'de, D: Deserializer<'de>, T: ValueEnum>(
+    deserializer: D,
+) -> Result<Option<Vec<T>>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|value| T::from_str(value, false).map_err(serde::de::Error::custom))
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+fn parse_replay_speed(value: &str) -> Result<f64, String> {
+    let speed: f64 = value.parse().map_err(|e| format!("{e}"))?;
+    if speed.is_finite() && speed > 0.0 {
+        Ok(speed)
+    } else {
+        Err(format!("{speed} isn't a positive number"))
+    }
+}
+
+/// depth limits accepted by binance http api
+const SNAPSHOT_LIMITS: [u32; 7] = [5, 10, 20, 50, 100, 500, 1000];
+
+/// frames waiting to be applied per connection, unless configured
+const DEFAULT_FRAME_QUEUE_CAPACITY: usize = 4096;
+
+/// levels of partial depth streams offered by binance
+const PARTIAL_DEPTH_LEVELS: [u32; 3] = [5, 10, 20];
+
+/// kline intervals accepted by binance, 1s is spot only
+const KLINE_INTERVALS: [&str; 16] = [
+    "1s", "1m", "3m", "5m", "15m", "30m", "1h", "2h", "4h", "6h", "8h", "12h", "1d", "3d", "1w",
+    "1M",
+];
+
+/// instrument watched if none is configured
+const DEFAULT_INSTRUMENT: &str = "btcusdt";
+
 impl Config {
-    /// calculates number of instruments per connection
+    /// parses process args over values of the --config file, exits on errors like [Parser::parse]
+    pub fn parse_with_config_file() -> Self {
+        Self::try_parse_with_config_file(std::env::args_os()).unwrap_or_else(|e| e.exit())
+    }
+
+    /// parses args over values of the --config file if any, file values are set
+    /// to options given neither by args nor by env vars
+    pub fn try_parse_with_config_file<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let matches = Self::command().try_get_matches_from(args)?;
+        let mut config = Self::from_arg_matches(&matches)?;
+        if let Some(path) = config.config.clone() {
+            let file = read_config_file(&path)
+                .map_err(|e| Self::command().error(ErrorKind::Io, format!("config {path}: {e}")))?;
+            file.apply(&mut config, &matches)?;
+        }
+        Ok(config)
+    }
+
+    /// websocket url, explicitly configured one wins over market and testnet defaults
+    pub fn ws_api_url(&self) -> String {
+        self.ws_api_url.clone().unwrap_or_else(|| {
+            match (self.market, self.testnet) {
+                (Market::Futures, false) => "wss://fstream.binance.com/ws",
+                (Market::Futures, true) => "wss://stream.binancefuture.com/ws",
+                (Market::Spot, false) => "wss://stream.binance.com:9443/ws",
+                (Market::Spot, true) => "wss://stream.testnet.binance.vision/ws",
+            }
+            .to_string()
+        })
+    }
+
+    /// books output format, --compact wins over --format
+    pub fn output_format(&self) -> OutputFormat {
+        match self.compact {
+            true => OutputFormat::Compact,
+            false => self.format,
+        }
+    }
+
+    /// stdout is kept for books or update lines only, e.g. piped to jq,
+    /// unless books are ascii tables, parameters and summary go to stderr then
+    pub fn books_only_stdout(&self) -> bool {
+        self.ndjson || self.output_format() != OutputFormat::Table
+    }
+
+    /// http api url, explicitly configured one wins over market and testnet defaults
+    pub fn api_url(&self) -> String {
+        self.api_url.clone().unwrap_or_else(|| {
+            match (self.market, self.testnet) {
+                (Market::Futures, false) => "https://fapi.binance.com/fapi/v1",
+                (Market::Futures, true) => "https://testnet.binancefuture.com/fapi/v1",
+                (Market::Spot, false) => "https://api.binance.com/api/v3",
+                (Market::Spot, true) => "https://testnet.binance.vision/api/v3",
+            }
+            .to_string()
+        })
+    }
+
+    /// merges instruments from the instruments file, if any, into instruments
+    pub fn load_instruments(&mut self) -> Result<(), String> {
+        if let Some(path) = &self.instruments_file {
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| format!("can't read instruments file {}: {}", path, e))?;
+            let instruments = parse_instruments_file(&content);
+            if instruments.is_empty() {
+                return Err(format!("instruments file {} has no instruments", path));
+            }
+            for instrument in instruments {
+                if !self.instruments.contains(&instrument) {
+                    self.instruments.push(instrument);
+                }
+            }
+        }
+        if self.instruments.is_empty() {
+            self.instruments.push(DEFAULT_INSTRUMENT.to_string());
+        }
+        self.split_instrument_levels()?;
+        self.instruments = self.validated_instruments()?;
+        Ok(())
+    }
+
+    /// moves levels of symbol:levels instruments to instrument_levels, the last one wins
+    pub fn split_instrument_levels(&mut self) -> Result<(), String> {
+        for instrument in self.instruments.iter_mut() {
+            let Some((symbol, levels)) = instrument.split_once(':') else {
+                continue;
+            };
+            let symbol = symbol.trim().to_lowercase();
+            let levels = levels
+                .trim()
+                .parse::<u32>()
+                .ok()
+                .filter(|levels| (1..200).contains(levels))
+                .ok_or_else(|| format!("levels of {instrument} aren't in 1..200"))?;
+            self.instrument_levels.insert(symbol.clone(), levels);
+            *instrument = symbol;
+        }
+        Ok(())
+    }
+
+    /// levels of the instrument, --levels unless set per instrument
+    pub fn levels_of(&self, symbol: &str) -> u32 {
+        self.instrument_levels
+            .get(symbol)
+            .copied()
+            .unwrap_or(self.levels)
+    }
+
+    /// trims explicitly configured urls and their trailing slash, fails on unexpected scheme
+    pub fn normalize_urls(&mut self) -> Result<(), String> {
+        if let Some(url) = &self.ws_api_url {
+            self.ws_api_url = Some(normalize_url(url, "wss")?);
+        }
+        if let Some(url) = &self.api_url {
+            self.api_url = Some(normalize_url(url, "https")?);
+        }
+        Ok(())
+    }
+
+    /// lowercased, trimmed and deduped instruments, fails if none is left
+    pub fn validated_instruments(&self) -> Result<Vec<String>, String> {
+        let mut instruments: Vec<String> = vec![];
+        for instrument in &self.instruments {
+            let instrument = instrument.trim().to_lowercase();
+            if !instrument.is_empty() && !instruments.contains(&instrument) {
+                instruments.push(instrument);
+            }
+        }
+        if instruments.is_empty() {
+            return Err("no instruments to watch".to_string());
+        }
+        Ok(instruments)
+    }
+
+    /// full book depth of the instrument to request via http api, levels snapped up to
+    /// the closest limit allowed by binance, the book is trimmed to levels afterwards anyway
+    pub fn snapshot_limit(&self, symbol: &str) -> u32 {
+        snapshot_limit(self.levels_of(symbol))
+    }
+
+    /// levels of the instrument partial depth stream
+    pub fn partial_depth_levels(&self, symbol: &str) -> u32 {
+        partial_depth_levels(self.levels_of(symbol))
+    }
+
+    /// spot partial depth payloads have no symbol, it's taken from the combined stream name
+    pub fn validate_partial_depth(&self) -> Result<(), String> {
+        if self.partial_depth && self.market == Market::Spot && !self.combined {
+            return Err("--partial-depth on spot market requires --combined".to_string());
+        }
+        Ok(())
+    }
+
+    /// metrics and books aren't exposed to the network, unless configured
+    pub fn http_bind(&self) -> IpAddr {
+        self.http_bind.unwrap_or(Ipv4Addr::LOCALHOST.into())
+    }
+
+    pub fn frame_queue_capacity(&self) -> usize {
+        self.frame_queue_capacity
+            .map_or(DEFAULT_FRAME_QUEUE_CAPACITY, |capacity| capacity as usize)
+    }
+
+    /// extra connections are never opened
+    pub fn connections_warning(&self) -> Option<String> {
+        let actual = self.actual_connection_count();
+        if (self.connections as usize) > actual {
+            Some(format!(
+                "{} connections requested for {} instruments, only {} will be opened",
+                self.connections,
+                self.instruments.len(),
+                actual
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// calculates number of instruments per connection, at least one
     pub fn instruments_per_connection(&self) -> usize {
-        (self.instruments.len() as f32 / self.connections as f32).ceil() as usize
+        self.instruments
+            .len()
+            .div_ceil(self.connections.max(1) as usize)
+            .max(1)
+    }
+
+    /// number of connections opened for instruments chunked by instruments_per_connection,
+    /// e.g. 5 instruments on 4 connections are chunked by 2 into 3 connections
+    pub fn actual_connection_count(&self) -> usize {
+        self.instruments
+            .len()
+            .div_ceil(self.instruments_per_connection())
+    }
+
+    /// builds combined streams url, e.g. wss://fstream.binance.com/stream?streams=btcusdt@depth/ethusdt@depth
+    pub fn combined_stream_url(&self, streams: &[String]) -> String {
+        let ws_api_url = self.ws_api_url();
+        let base_url = ws_api_url.trim_end_matches('/');
+        let base_url = base_url.strip_suffix("/ws").unwrap_or(base_url);
+        format!("{}/stream?streams={}", base_url, streams.join("/"))
+    }
+}
+
+fn read_config_file(path: &str) -> Result<FileConfig, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    toml::from_str(&content).map_err(|e| e.to_string())
+}
+
+/// trimmed url without trailing slash, it has to have the scheme and a host
+fn normalize_url(url: &str, scheme: &str) -> Result<String, String> {
+    let url = url.trim().trim_end_matches('/');
+    match url.split_once("://") {
+        Some((url_scheme, host)) if url_scheme == scheme && !host.is_empty() => Ok(url.to_string()),
+        _ => Err(format!(
+            "invalid url '{}', expected {}://<host>",
+            url, scheme
+        )),
+    }
+}
+
+/// smallest limit allowed by binance fitting the levels, the largest one if none fits
+fn snapshot_limit(levels: u32) -> u32 {
+    SNAPSHOT_LIMITS
+        .into_iter()
+        .find(|limit| *limit >= levels)
+        .unwrap_or(SNAPSHOT_LIMITS[SNAPSHOT_LIMITS.len() - 1])
+}
+
+/// smallest partial depth stream fitting the levels, 20 at most
+fn partial_depth_levels(levels: u32) -> u32 {
+    PARTIAL_DEPTH_LEVELS
+        .into_iter()
+        .find(|partial| *partial >= levels)
+        .unwrap_or(PARTIAL_DEPTH_LEVELS[PARTIAL_DEPTH_LEVELS.len() - 1])
+}
+
+/// one instrument per line, blank lines and # comments are ignored, result is lowercased and deduped
+fn parse_instruments_file(content: &str) -> Vec<String> {
+    let mut instruments: Vec<String> = vec![];
+    for line in content.lines() {
+        let instrument = line
+            .split('#')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        if !instrument.is_empty() && !instruments.contains(&instrument) {
+            instruments.push(instrument);
+        }
     }
+    instruments
 }
 
 impl fmt::Display for Config {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "====START PARAMETERS====")?;
-        writeln!(f, "binance url: {}", self.ws_api_url)?;
+        writeln!(f, "binance url: {}", self.ws_api_url())?;
+        writeln!(f, "binance api url: {}", self.api_url())?;
         writeln!(f, "instruments: [{}]", self.instruments.join(","))?;
         writeln!(f, "book's levels to display: {}", self.levels)?;
+        if !self.instrument_levels.is_empty() {
+            let levels: Vec<String> = self
+                .instrument_levels
+                .iter()
+                .map(|(symbol, levels)| format!("{symbol}:{levels}"))
+                .collect();
+            writeln!(f, "levels per instrument: [{}]", levels.join(","))?;
+        }
         writeln!(f, "screen update interval ms: {}", self.delay)?;
-        writeln!(f, "binance connections pool size: {}", self.connections)?;
+        let actual = self.actual_connection_count();
+        if actual == self.connections as usize {
+            writeln!(f, "binance connections pool size: {}", actual)?;
+        } else {
+            writeln!(
+                f,
+                "binance connections pool size: {} ({} requested)",
+                actual, self.connections
+            )?;
+        }
+        writeln!(f, "websocket read timeout ms: {}", self.read_timeout)?;
+        writeln!(f, "frame queue capacity: {}", self.frame_queue_capacity())?;
+        writeln!(f, "websocket connect timeout ms: {}", self.connect_timeout)?;
+        writeln!(f, "stagger ms: {}", self.stagger)?;
+        writeln!(f, "full book request retries: {}", self.snapshot_retries)?;
+        writeln!(
+            f,
+            "max concurrent requests: {}",
+            self.max_concurrent_requests
+        )?;
+        if self.compress_ws {
+            writeln!(f, "websocket compression: permessage-deflate")?;
+        }
+        writeln!(f, "combined streams: {}", self.combined)?;
+        writeln!(f, "market: {:?}", self.market)?;
+        writeln!(f, "streams: {:?}", self.streams)?;
+        writeln!(f, "mark price: {}", self.mark_price)?;
+        writeln!(f, "24hr ticker: {}", self.ticker24h)?;
+        if let Some(interval) = &self.kline {
+            writeln!(f, "kline: {}", interval)?;
+        }
+        writeln!(f, "liquidations: {}", self.liquidations)?;
+        writeln!(f, "strict symbols: {}", self.strict)?;
+        if self.dry_run {
+            writeln!(f, "dry run: connections aren't opened")?;
+        }
+        writeln!(f, "depth update speed: {:?}", self.update_speed)?;
+        if self.partial_depth {
+            writeln!(
+                f,
+                "partial depth levels: {}",
+                partial_depth_levels(self.levels)
+            )?;
+        }
+        writeln!(f, "output format: {:?}", self.output_format())?;
+        writeln!(f, "ndjson updates: {}", self.ndjson)?;
+        if let Some(decimals) = self.price_decimals {
+            writeln!(f, "price decimals: {}", decimals)?;
+        }
+        if let Some(decimals) = self.qty_decimals {
+            writeln!(f, "quantity decimals: {}", decimals)?;
+        }
+        writeln!(f, "live mode: {}", self.live)?;
+        writeln!(f, "colors: {}", self.color)?;
+        writeln!(f, "mid ema alpha: {}", self.ema_alpha)?;
+        writeln!(f, "trade window secs: {}", self.trade_window_secs)?;
+        if self.tape {
+            writeln!(f, "trade tape size: {}", self.trade_tape_size)?;
+        }
+        writeln!(f, "log level: {:?}", self.log_level)?;
+        writeln!(f, "shutdown timeout secs: {}", self.shutdown_timeout)?;
+        if let Some(path) = &self.record {
+            writeln!(f, "record frames to: {}", record_path(path, self.compress))?;
+        }
+        if let Some(port) = self.metrics_port {
+            writeln!(f, "metrics port: {}", port)?;
+        }
+        if let Some(port) = self.http_port {
+            writeln!(f, "http port: {}", port)?;
+        }
+        if self.metrics_port.is_some() || self.http_port.is_some() {
+            writeln!(f, "http bind: {}", self.http_bind())?;
+        }
+        if let Some(bps) = self.spread_alert_bps {
+            writeln!(f, "spread alert bps: {}", bps)?;
+        }
+        if let Some(secs) = self.stale_book_secs {
+            writeln!(f, "stale book secs: {}", secs)?;
+        }
+        if let Some(url) = &self.webhook_url {
+            writeln!(f, "webhook url: {}", url)?;
+        }
+        if let Some(dir) = &self.snapshot_dir {
+            writeln!(
+                f,
+                "snapshots to: {} every {} secs",
+                dir, self.snapshot_interval_secs
+            )?;
+        }
+        if let Some(size) = self.max_file_size {
+            writeln!(f, "max file size: {} bytes", size)?;
+        }
+        if self.compress {
+            writeln!(f, "files compression: gzip")?;
+        }
+        if let Some(interval) = self.reconcile_interval {
+            writeln!(f, "reconcile interval secs: {}", interval)?;
+        }
+        if let Some(path) = &self.config {
+            writeln!(f, "config file: {}", path)?;
+        }
         writeln!(f, "====END PARAMETERS====")?;
         Ok(())
     }
 }
 
-mod test {
-    use crate::console_arguments::Config;
+#[cfg(test)]
+pub mod test {
+    use crate::console_arguments::{
+        normalize_url, parse_instruments_file, snapshot_limit, Command, Config, FileConfig, Market,
+        StreamType,
+    };
+    use crate::order_book::DEFAULT_EMA_ALPHA;
+    use clap::{CommandFactory, FromArgMatches, Parser};
+    use std::collections::BTreeMap;
+    use std::ffi::OsString;
+    use std::net::IpAddr;
+    use std::sync::{Mutex, MutexGuard};
+
+    /// env vars are process wide, every test parsing args holds this lock
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    /// env vars set while the parsing lock is held, previous values are restored on drop
+    struct EnvGuard {
+        previous: Vec<(&'static str, Option<OsString>)>,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl EnvGuard {
+        fn set(vars: &[(&'static str, &str)]) -> Self {
+            // a failed test doesn't fail the others
+            let lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            let previous = vars
+                .iter()
+                .map(|(name, value)| {
+                    let previous = std::env::var_os(name);
+                    std::env::set_var(name, value);
+                    (*name, previous)
+                })
+                .collect();
+            Self {
+                previous,
+                _lock: lock,
+            }
+        }
+    }
+
+    impl Drop for EnvGuard {
+        fn drop(&mut self) {
+            for (name, previous) in &self.previous {
+                match previous {
+                    Some(value) => std::env::set_var(name, value),
+                    None => std::env::remove_var(name),
+                }
+            }
+        }
+    }
+
+    /// parses args while holding the env lock, for tests of all modules
+    pub fn parse(args: &[&str]) -> Result<Config, clap::Error> {
+        let _env = EnvGuard::set(&[]);
+        Config::try_parse_from(args)
+    }
 
     #[test]
     fn test_instruments_per_connection() {
-        let mut config: Config = Default::default();
+        let config = Config {
+            connections: 2,
+            instruments: vec![
+                "1".to_string(),
+                "2".to_string(),
+                "3".to_string(),
+                "4".to_string(),
+                "5".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(config.instruments_per_connection(), 3);
+        assert_eq!(config.actual_connection_count(), 2);
+    }
+
+    #[test]
+    fn test_actual_connection_count() {
+        let config = |instruments: usize, connections: u32| Config {
+            connections,
+            instruments: (0..instruments).map(|i| i.to_string()).collect(),
+            ..Default::default()
+        };
+        // oversubscribed, a connection per instrument
+        assert_eq!(config(2, 5).instruments_per_connection(), 1);
+        assert_eq!(config(2, 5).actual_connection_count(), 2);
+        // undersubscribed, chunks of 2,2,1
+        assert_eq!(config(5, 3).instruments_per_connection(), 2);
+        assert_eq!(config(5, 3).actual_connection_count(), 3);
+        // chunks of 2 leave a requested connection unused
+        assert_eq!(config(5, 4).actual_connection_count(), 3);
+        assert_eq!(config(6, 3).actual_connection_count(), 3);
+        assert_eq!(config(0, 3).instruments_per_connection(), 1);
+        assert_eq!(config(0, 3).actual_connection_count(), 0);
+        assert_eq!(config(3, 0).actual_connection_count(), 1);
+
+        // the count matches the chunks opened by the watcher
+        for instruments in 0..20 {
+            for connections in 1..8 {
+                let config = config(instruments, connections);
+                assert_eq!(
+                    config
+                        .instruments
+                        .chunks(config.instruments_per_connection())
+                        .count(),
+                    config.actual_connection_count()
+                );
+            }
+        }
+
+        assert!(config(2, 5)
+            .to_string()
+            .contains("binance connections pool size: 2 (5 requested)\n"));
+        assert!(config(6, 3)
+            .to_string()
+            .contains("binance connections pool size: 3\n"));
+    }
+
+    #[test]
+    fn test_combined_stream_url() {
+        let config = Config {
+            ws_api_url: Some("wss://fstream.binance.com/ws".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config.combined_stream_url(&["btcusdt@depth".to_string(), "ethusdt@depth".to_string()]),
+            "wss://fstream.binance.com/stream?streams=btcusdt@depth/ethusdt@depth"
+        )
+    }
+
+    #[test]
+    fn test_parse_instruments_file() {
+        let content = "# majors\nBTCUSDT\n\n  ethusdt  # second\nbtcusdt\n#solusdt\nBNBUSDT\n";
+
+        assert_eq!(
+            parse_instruments_file(content),
+            vec![
+                "btcusdt".to_string(),
+                "ethusdt".to_string(),
+                "bnbusdt".to_string()
+            ]
+        )
+    }
+
+    #[test]
+    fn test_load_instruments() {
+        let path = std::env::temp_dir().join(format!(
+            "binance_watcher_instruments_{}.txt",
+            std::process::id()
+        ));
+        std::fs::write(&path, "ethusdt\nbnbusdt\n").unwrap();
+
+        let mut config = Config {
+            instruments: vec!["btcusdt".to_string(), "ethusdt".to_string()],
+            instruments_file: Some(path.to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+        let result = config.load_instruments();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            config.instruments,
+            vec![
+                "btcusdt".to_string(),
+                "ethusdt".to_string(),
+                "bnbusdt".to_string()
+            ]
+        );
+
+        // missing file
+        let mut config = Config {
+            instruments_file: Some("/nonexistent/instruments.txt".to_string()),
+            ..Default::default()
+        };
+        assert!(config.load_instruments().is_err());
+
+        // default instrument
+        let mut config = Config::default();
+        assert_eq!(config.load_instruments(), Ok(()));
+        assert_eq!(config.instruments, vec!["btcusdt".to_string()]);
+    }
+
+    #[test]
+    fn test_validated_instruments() {
+        // duplicates and mixed case
+        let config = Config {
+            instruments: vec![
+                "btcusdt".to_string(),
+                " BTCUSDT ".to_string(),
+                "EthUsdt".to_string(),
+                "btcusdt".to_string(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validated_instruments(),
+            Ok(vec!["btcusdt".to_string(), "ethusdt".to_string()])
+        );
+
+        // empty
+        let config = Config {
+            instruments: vec!["".to_string(), "  ".to_string()],
+            ..Default::default()
+        };
+        assert!(config.validated_instruments().is_err());
+        assert!(Config::default().validated_instruments().is_err());
+    }
+
+    #[test]
+    fn test_connections_warning() {
+        let mut config = Config {
+            connections: 3,
+            instruments: vec!["btcusdt".to_string(), "ethusdt".to_string()],
+            ..Default::default()
+        };
+        assert!(config.connections_warning().is_some());
+
         config.connections = 2;
-        config.instruments = vec![
-            "1".to_string(),
-            "2".to_string(),
-            "3".to_string(),
-            "4".to_string(),
-            "5".to_string(),
-        ];
-
-        assert_eq!(config.instruments_per_connection(), 3)
+        assert!(config.connections_warning().is_none());
+
+        // 5 instruments are chunked by 2 on 3 connections of 4
+        config.connections = 4;
+        config
+            .instruments
+            .extend(["bnbusdt", "solusdt", "xrpusdt"].map(String::from));
+        assert_eq!(
+            config.connections_warning().unwrap(),
+            "4 connections requested for 5 instruments, only 3 will be opened"
+        );
+    }
+
+    #[test]
+    fn test_partial_depth() {
+        let mut config = Config {
+            levels: 1,
+            partial_depth: true,
+            ..Default::default()
+        };
+        assert_eq!(config.partial_depth_levels("btcusdt"), 5);
+        config.levels = 10;
+        assert_eq!(config.partial_depth_levels("btcusdt"), 10);
+        config.levels = 11;
+        assert_eq!(config.partial_depth_levels("btcusdt"), 20);
+        config.levels = 100;
+        assert_eq!(config.partial_depth_levels("btcusdt"), 20);
+        config.instrument_levels.insert("btcusdt".to_string(), 5);
+        assert_eq!(config.partial_depth_levels("btcusdt"), 5);
+        assert_eq!(config.partial_depth_levels("ethusdt"), 20);
+
+        assert!(config.validate_partial_depth().is_ok());
+        config.market = Market::Spot;
+        assert!(config.validate_partial_depth().is_err());
+        config.combined = true;
+        assert!(config.validate_partial_depth().is_ok());
+    }
+
+    #[test]
+    fn test_snapshot_limit() {
+        assert_eq!(snapshot_limit(1), 5);
+        assert_eq!(snapshot_limit(5), 5);
+        assert_eq!(snapshot_limit(6), 10);
+        assert_eq!(snapshot_limit(20), 20);
+        assert_eq!(snapshot_limit(37), 50);
+        assert_eq!(snapshot_limit(101), 500);
+        assert_eq!(snapshot_limit(199), 500);
+        assert_eq!(snapshot_limit(5000), 1000);
+
+        let config = Config {
+            levels: 37,
+            instrument_levels: BTreeMap::from([("btcusdt".to_string(), 101)]),
+            ..Default::default()
+        };
+        assert_eq!(config.snapshot_limit("ethusdt"), 50);
+        assert_eq!(config.snapshot_limit("btcusdt"), 500);
+    }
+
+    #[test]
+    fn test_instrument_levels() {
+        let mut config = parse(&[
+            "binance_watcher",
+            "-l",
+            "10",
+            "--instruments",
+            "BTCUSDT:100",
+            "--instruments",
+            "dogeusdt:5",
+            "-i",
+            "ethusdt",
+            "-i",
+            "btcusdt",
+        ])
+        .unwrap();
+        config.load_instruments().unwrap();
+        assert_eq!(config.instruments, vec!["btcusdt", "dogeusdt", "ethusdt"]);
+        assert_eq!(config.levels_of("btcusdt"), 100);
+        assert_eq!(config.levels_of("dogeusdt"), 5);
+        assert_eq!(config.levels_of("ethusdt"), 10);
+        assert!(config
+            .to_string()
+            .contains("levels per instrument: [btcusdt:100,dogeusdt:5]\n"));
+
+        for invalid in ["btcusdt:", "btcusdt:0", "btcusdt:x", "btcusdt:200"] {
+            let mut config = Config {
+                instruments: vec![invalid.to_string()],
+                ..Default::default()
+            };
+            assert!(config.load_instruments().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_urls() {
+        let mut config = Config::default();
+        assert_eq!(config.ws_api_url(), "wss://fstream.binance.com/ws");
+        assert_eq!(config.api_url(), "https://fapi.binance.com/fapi/v1");
+
+        config.testnet = true;
+        assert_eq!(config.ws_api_url(), "wss://stream.binancefuture.com/ws");
+        assert_eq!(
+            config.api_url(),
+            "https://testnet.binancefuture.com/fapi/v1"
+        );
+
+        // explicit urls win over testnet
+        config.ws_api_url = Some("wss://localhost/ws".to_string());
+        config.api_url = Some("https://localhost/fapi/v1".to_string());
+        assert_eq!(config.ws_api_url(), "wss://localhost/ws");
+        assert_eq!(config.api_url(), "https://localhost/fapi/v1");
+
+        let config = Config {
+            market: Market::Spot,
+            ..Default::default()
+        };
+        assert_eq!(config.ws_api_url(), "wss://stream.binance.com:9443/ws");
+        assert_eq!(config.api_url(), "https://api.binance.com/api/v3");
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        // leading space
+        assert_eq!(
+            normalize_url(" https://fapi.binance.com/fapi/v1", "https"),
+            Ok("https://fapi.binance.com/fapi/v1".to_string())
+        );
+        // trailing slash
+        assert_eq!(
+            normalize_url("wss://fstream.binance.com/ws/", "wss"),
+            Ok("wss://fstream.binance.com/ws".to_string())
+        );
+        // bad scheme
+        assert!(normalize_url("http://fapi.binance.com/fapi/v1", "https").is_err());
+        assert!(normalize_url("fstream.binance.com/ws", "wss").is_err());
+        assert!(normalize_url("wss://", "wss").is_err());
+    }
+
+    #[test]
+    fn test_normalize_urls() {
+        let mut config = Config {
+            ws_api_url: Some(" wss://fstream.binance.com/ws/ ".to_string()),
+            api_url: Some(" https://fapi.binance.com/fapi/v1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(config.normalize_urls(), Ok(()));
+        assert_eq!(config.ws_api_url(), "wss://fstream.binance.com/ws");
+        assert_eq!(config.api_url(), "https://fapi.binance.com/fapi/v1");
+
+        let mut config = Config {
+            api_url: Some("ftp://fapi.binance.com".to_string()),
+            ..Default::default()
+        };
+        assert!(config.normalize_urls().is_err());
+    }
+
+    #[test]
+    fn test_ema_alpha() {
+        let config = parse(&["binance_watcher"]).unwrap();
+        assert_eq!(config.ema_alpha, DEFAULT_EMA_ALPHA);
+        let config = parse(&["binance_watcher", "--ema-alpha", "1"]).unwrap();
+        assert_eq!(config.ema_alpha, 1.0);
+        for alpha in ["0", "1.5", "-0.1", "x"] {
+            assert!(parse(&["binance_watcher", "--ema-alpha", alpha]).is_err());
+        }
+    }
+
+    /// toml file in the temp dir, removed by the test
+    fn config_file(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "binance_watcher_{name}_{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_config_file() {
+        let path = config_file(
+            "config",
+            r#"
+                instruments = ["ethusdt"]
+                stagger = 100
+                read-timeout = 5000
+                mark-price = true
+                streams = ["depth", "aggTrade"]
+                market = "spot"
+                kline = "1h"
+                spread-alert-bps = 5.5
+                ws-api-url = "wss://example.com/ws"
+            "#,
+        );
+        let _env = EnvGuard::set(&[]);
+        let parse = |args: &[&str]| {
+            let args = [&["binance_watcher", "--config", &path], args].concat();
+            Config::try_parse_with_config_file(args)
+        };
+
+        let config = parse(&[]).unwrap();
+        assert_eq!(config.instruments, vec!["ethusdt"]);
+        assert_eq!(config.stagger, 100);
+        assert_eq!(config.read_timeout, 5000);
+        assert!(config.mark_price);
+        assert_eq!(
+            config.streams,
+            vec![StreamType::Depth, StreamType::AggTrade]
+        );
+        assert_eq!(config.market, Market::Spot);
+        assert_eq!(config.kline.as_deref(), Some("1h"));
+        assert_eq!(config.spread_alert_bps, Some(5.5));
+        assert_eq!(config.ws_api_url.as_deref(), Some("wss://example.com/ws"));
+        // defaults are below the file
+        assert_eq!(config.connect_timeout, 10000);
+
+        // flags win over the file, even when given their default values
+        let config = parse(&[
+            "--stagger",
+            "200",
+            "--streams",
+            "bookTicker",
+            "-i",
+            "btcusdt",
+            "replay",
+            "rec.txt",
+        ])
+        .unwrap();
+        assert_eq!(config.stagger, 200);
+        assert_eq!(config.streams, vec![StreamType::BookTicker]);
+        assert_eq!(config.instruments, vec!["btcusdt"]);
+        assert_eq!(config.read_timeout, 5000);
+        assert!(matches!(config.command, Some(Command::Replay { .. })));
+
+        // file values are checked like flag values
+        for content in [
+            "read-timeout = 0",
+            "kline = \"2m\"",
+            "market = \"moon\"",
+            "levels = \"ten\"",
+            "read_timeout = 5000",
+            "unknown = 1",
+            "config = \"other.toml\"",
+            "stagger = ",
+        ] {
+            std::fs::write(&path, content).unwrap();
+            assert!(parse(&[]).is_err(), "{content}");
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert!(parse(&[]).is_err());
+    }
+
+    #[test]
+    fn test_config_file_flags() {
+        let _env = EnvGuard::set(&[]);
+        let matches = Config::command().get_matches_from(["binance_watcher", "--live"]);
+        let mut config = Config::from_arg_matches(&matches).unwrap();
+        config.combined = true;
+        let file: FileConfig =
+            toml::from_str("combined = false\nmark-price = true\nlive = false").unwrap();
+        file.apply(&mut config, &matches).unwrap();
+
+        // false of the file is set like any other value
+        assert!(!config.combined);
+        assert!(config.mark_price);
+        // the flag wins
+        assert!(config.live);
+    }
+
+    #[test]
+    fn test_books_only_stdout() {
+        let config = |args: &[&str]| {
+            Config::parse_from(["binance_watcher", "-i", "btcusdt"].iter().chain(args))
+        };
+        assert!(!config(&[]).books_only_stdout());
+        assert!(config(&["--ndjson"]).books_only_stdout());
+        assert!(config(&["-f", "json"]).books_only_stdout());
+        assert!(config(&["-f", "csv"]).books_only_stdout());
+        assert!(config(&["--compact"]).books_only_stdout());
+    }
+
+    #[test]
+    fn test_reconcile_interval() {
+        let config = Config::parse_from(["binance_watcher", "--reconcile-interval", "30"]);
+        assert_eq!(config.reconcile_interval, Some(30));
+        assert_eq!(
+            Config::parse_from(["binance_watcher"]).reconcile_interval,
+            None
+        );
+        for interval in ["0", "-1", "x"] {
+            let args = ["binance_watcher", "--reconcile-interval", interval];
+            assert!(Config::try_parse_from(args).is_err(), "{interval}");
+        }
+    }
+
+    #[test]
+    fn test_http_bind() {
+        let config = parse(&["binance_watcher"]).unwrap();
+        assert_eq!(config.http_bind(), IpAddr::from([127, 0, 0, 1]));
+        let config = parse(&["binance_watcher", "--http-bind", "0.0.0.0"]).unwrap();
+        assert_eq!(config.http_bind(), IpAddr::from([0, 0, 0, 0]));
+        assert!(parse(&["binance_watcher", "--http-bind", "localhost"]).is_err());
+    }
+
+    #[test]
+    fn test_replay_speed() {
+        let config = Config::parse_from(["binance_watcher", "replay", "rec.txt", "--speed", "2.5"]);
+        assert_eq!(
+            config.command,
+            Some(Command::Replay {
+                path: "rec.txt".to_string(),
+                speed: Some(2.5)
+            })
+        );
+        for speed in ["0", "-1", "inf", "NaN", "x"] {
+            let args = ["binance_watcher", "replay", "rec.txt", "--speed", speed];
+            assert!(Config::try_parse_from(args).is_err(), "{speed}");
+        }
+    }
+
+    #[test]
+    fn test_env_vars() {
+        let _env = EnvGuard::set(&[
+            ("BW_INSTRUMENTS", "btcusdt,ethusdt"),
+            ("BW_LEVELS", "5"),
+            ("BW_TESTNET", "true"),
+        ]);
+        let config = Config::parse_from(["binance_watcher"]);
+        assert_eq!(config.instruments, vec!["btcusdt", "ethusdt"]);
+        assert_eq!(config.levels, 5);
+        assert!(config.testnet);
+
+        // flags win over env vars
+        let config = Config::parse_from(["binance_watcher", "-l", "10", "-i", "bnbusdt"]);
+        assert_eq!(config.instruments, vec!["bnbusdt"]);
+        assert_eq!(config.levels, 10);
+
+        // invalid env values fail like flags
+        std::env::set_var("BW_LEVELS", "0");
+        assert!(Config::try_parse_from(["binance_watcher"]).is_err());
+    }
+
+    #[test]
+    fn test_env_vars_restored() {
+        {
+            let _env = EnvGuard::set(&[("BW_STAGGER", "1")]);
+            assert_eq!(Config::parse_from(["binance_watcher"]).stagger, 1);
+        }
+        // defaults are below env vars
+        assert_eq!(parse(&["binance_watcher"]).unwrap().stagger, 200);
+    }
+
+    #[test]
+    fn test_config_file_env_vars() {
+        let path = config_file(
+            "env_config",
+            "stagger = 100\nread-timeout = 5000\nlevels = 7",
+        );
+        // the file is given by env var too
+        let _env = EnvGuard::set(&[
+            ("BW_CONFIG", &path),
+            ("BW_STAGGER", "150"),
+            ("BW_READ_TIMEOUT", "6000"),
+        ]);
+        let config =
+            Config::try_parse_with_config_file(["binance_watcher", "--stagger", "50"]).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // flag > env var > file > default
+        assert_eq!(config.stagger, 50);
+        assert_eq!(config.read_timeout, 6000);
+        assert_eq!(config.levels, 7);
+        assert_eq!(config.connect_timeout, 10000);
     }
 }
@@ -0,0 +1,249 @@
+use futures_util::{Sink, Stream};
+use soketto::connection::{CloseReason, Error, Mode, Sender};
+use soketto::data::ByteSlice125;
+use soketto::extension::deflate::Deflate;
+use soketto::handshake::{Client, ServerResponse};
+use soketto::{Data, Incoming};
+use std::pin::Pin;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::http::Uri;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use tracing::{info, warn};
+
+/// messages sent to a websocket, whichever library opened it
+pub type MessageSink = Pin<Box<dyn Sink<Message, Error = tungstenite::Error> + Send>>;
+
+/// messages read from a websocket, whichever library opened it
+pub type MessageStream = Pin<Box<dyn Stream<Item = Result<Message, tungstenite::Error>> + Send>>;
+
+/// Websocket opened with permessage-deflate offered
+struct DeflateConnection {
+    sink: MessageSink,
+    stream: MessageStream,
+    // false if the server declined the extension, frames aren't compressed then
+    compressed: bool,
+}
+
+/// opens ws or wss connection offering permessage-deflate, tungstenite doesn't implement it,
+/// frames aren't compressed if the server declines it
+pub async fn connect_deflate(url: &str) -> Result<(MessageSink, MessageStream), String> {
+    let connection = open(url).await?;
+    match connection.compressed {
+        true => info!("Websocket frames are compressed with permessage-deflate"),
+        false => warn!("Server declined permessage-deflate, websocket frames aren't compressed"),
+    }
+    Ok((connection.sink, connection.stream))
+}
+
+async fn open(url: &str) -> Result<DeflateConnection, String> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|e| format!("invalid url {url} ({e})"))?;
+    let host = uri.host().ok_or_else(|| format!("no host in {url}"))?;
+    // host header keeps the port
+    let authority = uri.authority().map_or(host, |authority| authority.as_str());
+    let resource = uri.path_and_query().map_or("/", |path| path.as_str());
+    let is_tls = match uri.scheme_str() {
+        Some("wss") => true,
+        Some("ws") => false,
+        _ => return Err(format!("{url} isn't a ws or wss url")),
+    };
+    let port = uri.port_u16().unwrap_or(if is_tls { 443 } else { 80 });
+    let socket = TcpStream::connect((host, port))
+        .await
+        .map_err(|e| e.to_string())?;
+    if is_tls {
+        let connector =
+            tokio_native_tls::native_tls::TlsConnector::new().map_err(|e| e.to_string())?;
+        let socket = tokio_native_tls::TlsConnector::from(connector)
+            .connect(host, socket)
+            .await
+            .map_err(|e| e.to_string())?;
+        handshake(socket, authority, resource).await
+    } else {
+        handshake(socket, authority, resource).await
+    }
+}
+
+/// websocket handshake over the socket, permessage-deflate is offered with default window bits
+async fn handshake<T>(socket: T, host: &str, resource: &str) -> Result<DeflateConnection, String>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut client = Client::new(socket.compat(), host, resource);
+    client.add_extension(Box::new(Deflate::new(Mode::Client)));
+    match client.handshake().await.map_err(|e| e.to_string())? {
+        ServerResponse::Accepted { .. } => {}
+        ServerResponse::Redirect {
+            status_code,
+            location,
+        } => return Err(format!("redirected ({status_code}) to {location}")),
+        ServerResponse::Rejected { status_code } => {
+            return Err(format!("handshake rejected ({status_code})"))
+        }
+    }
+    // the extension is enabled by the handshake if the server accepted it
+    let extensions: Vec<_> = client.drain_extensions().collect();
+    let compressed = extensions.iter().any(|extension| extension.is_enabled());
+    let mut builder = client.into_builder();
+    builder.add_extensions(extensions);
+    let (sender, receiver) = builder.finish();
+
+    let sink = futures_util::sink::unfold(sender, |mut sender, message: Message| async move {
+        send(&mut sender, message)
+            .await
+            .map_err(to_tungstenite_error)?;
+        Ok(sender)
+    });
+    // pings are answered by the receiver, the stream ends once the connection is closed
+    let stream = futures_util::stream::unfold(Some(receiver), |receiver| async move {
+        let mut receiver = receiver?;
+        let mut data = Vec::new();
+        let message = match receiver.receive(&mut data).await {
+            Ok(Incoming::Data(Data::Text(_))) => String::from_utf8(data)
+                .map(|text| Message::Text(text.into()))
+                .map_err(tungstenite::Error::from),
+            Ok(Incoming::Data(Data::Binary(_))) => Ok(Message::Binary(data.into())),
+            Ok(Incoming::Pong(bytes)) => Ok(Message::Pong(bytes.to_vec().into())),
+            Ok(Incoming::Closed(reason)) => {
+                return Some((Ok(Message::Close(Some(close_frame(reason)))), None))
+            }
+            Err(Error::Closed) => return None,
+            Err(e) => Err(to_tungstenite_error(e)),
+        };
+        Some((message, Some(receiver)))
+    });
+    Ok(DeflateConnection {
+        sink: Box::pin(sink),
+        stream: Box::pin(stream),
+        compressed,
+    })
+}
+
+/// sends the message right away, raw frames aren't sent
+async fn send<T>(sender: &mut Sender<T>, message: Message) -> Result<(), Error>
+where
+    T: futures_util::AsyncRead + futures_util::AsyncWrite + Unpin,
+{
+    match message {
+        Message::Text(text) => sender.send_text(text.as_str()).await?,
+        Message::Binary(bytes) => sender.send_binary(&bytes).await?,
+        Message::Ping(bytes) => sender.send_ping(control_payload(&bytes)?).await?,
+        Message::Pong(bytes) => sender.send_pong(control_payload(&bytes)?).await?,
+        Message::Close(_) => return sender.close().await,
+        Message::Frame(_) => {}
+    }
+    sender.flush().await
+}
+
+/// payload of a ping or pong, at most 125 bytes
+fn control_payload(bytes: &[u8]) -> Result<ByteSlice125<'_>, Error> {
+    ByteSlice125::try_from(bytes).map_err(|_| {
+        Error::Io(std::io::Error::other(
+            "control frame payload over 125 bytes",
+        ))
+    })
+}
+
+fn close_frame(reason: CloseReason) -> CloseFrame {
+    CloseFrame {
+        code: reason.code.into(),
+        reason: reason.descr.unwrap_or_default().into(),
+    }
+}
+
+fn to_tungstenite_error(e: Error) -> tungstenite::Error {
+    match e {
+        Error::Io(e) => tungstenite::Error::Io(e),
+        e => tungstenite::Error::Io(std::io::Error::other(e)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use soketto::handshake::server::Response;
+    use soketto::handshake::Server;
+    use tokio::net::TcpListener;
+
+    const FRAME: &str = r#"{"stream":"btcusdt@depth","data":{"e":"depthUpdate","s":"BTCUSDT","b":[["10.5","1"]],"a":[]}}"#;
+
+    /// accepts one connection, with permessage-deflate if supported, sends a text
+    /// and a binary frame once a message is received and closes, returns the message
+    /// and whether the extension was enabled
+    async fn serve_once(listener: TcpListener, deflate: bool) -> (String, bool) {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut server = Server::new(socket.compat());
+        if deflate {
+            server.add_extension(Box::new(Deflate::new(Mode::Server)));
+        }
+        let key = server.receive_request().await.unwrap().key();
+        server
+            .send_response(&Response::Accept {
+                key,
+                protocol: None,
+            })
+            .await
+            .unwrap();
+        let extensions: Vec<_> = server.drain_extensions().collect();
+        let compressed = extensions.iter().any(|extension| extension.is_enabled());
+        let mut builder = server.into_builder();
+        builder.add_extensions(extensions);
+        let (mut sender, mut receiver) = builder.finish();
+
+        let mut message = Vec::new();
+        receiver.receive_data(&mut message).await.unwrap();
+        sender.send_text(FRAME).await.unwrap();
+        sender.send_binary(FRAME).await.unwrap();
+        sender.close().await.unwrap();
+        (String::from_utf8(message).unwrap(), compressed)
+    }
+
+    async fn exchange_frames(deflate: bool) -> bool {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "ws://{}/ws?streams=btcusdt@depth",
+            listener.local_addr().unwrap()
+        );
+        let server = tokio::spawn(serve_once(listener, deflate));
+
+        let mut connection = open(&url).await.unwrap();
+        connection
+            .sink
+            .send(Message::Text(r#"{"method":"SUBSCRIBE"}"#.into()))
+            .await
+            .unwrap();
+        let text = connection.stream.next().await.unwrap().unwrap();
+        assert_eq!(text.to_text().unwrap(), FRAME);
+        let binary = connection.stream.next().await.unwrap().unwrap();
+        assert_eq!(binary, Message::Binary(FRAME.as_bytes().to_vec().into()));
+        let close = connection.stream.next().await.unwrap().unwrap();
+        assert!(matches!(close, Message::Close(Some(frame)) if u16::from(frame.code) == 1000));
+        assert!(connection.stream.next().await.is_none());
+
+        let (message, server_compressed) = server.await.unwrap();
+        assert_eq!(message, r#"{"method":"SUBSCRIBE"}"#);
+        assert_eq!(connection.compressed, server_compressed);
+        connection.compressed
+    }
+
+    #[tokio::test]
+    async fn deflate_negotiated_test() {
+        assert!(exchange_frames(true).await);
+    }
+
+    #[tokio::test]
+    async fn deflate_declined_test() {
+        assert!(!exchange_frames(false).await);
+    }
+
+    #[tokio::test]
+    async fn invalid_url_test() {
+        assert!(open("http://localhost/ws").await.is_err());
+        assert!(open("ws:///ws").await.is_err());
+    }
+}
@@ -0,0 +1,430 @@
+use crate::messages::{BookDepthUpdate, FullBook};
+use crate::number::{parse_number, Number};
+use crate::order_book::{ApplyError, LevelMismatch, OrderBook};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// max depth updates kept per symbol while its full book is requested
+const DEPTH_UPDATE_BUFFER_CAPACITY: usize = 1000;
+
+/// pause before the full book is requested again after a failed request, doubled per failure
+/// in a row, so a symbol failing for long doesn't use up the http api weight
+const FAILED_RESYNC_DELAY: Duration = Duration::from_secs(1);
+
+/// ceiling of the pause, a symbol failing for long is still requested now and then
+const MAX_FAILED_RESYNC_DELAY: Duration = Duration::from_secs(60);
+
+/// Depth updates received while the full book is requested via http api.
+/// Bounded, on overflow the oldest updates are dropped, so the first update kept
+/// won't contain lastUpdateId and the book will be requested again.
+pub struct DepthUpdateBuffer {
+    updates: VecDeque<BookDepthUpdate>,
+    capacity: usize,
+}
+
+impl DepthUpdateBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            updates: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, update: BookDepthUpdate) {
+        if self.updates.len() == self.capacity {
+            self.updates.pop_front();
+        }
+        self.updates.push_back(update);
+    }
+
+    /// applies buffered updates finished by the full book, so the book is at the same point,
+    /// returns prices of the update the full book is in the middle of, they can't be compared
+    pub fn catch_up(&self, book: &mut OrderBook, last_update_id: u64) -> Vec<Number> {
+        for update in &self.updates {
+            if update.u <= last_update_id {
+                // a book which can't catch up differs from the full book anyway
+                if book.apply_depth_book_update_from_websocket(update).is_err() {
+                    break;
+                }
+            } else {
+                if update.U <= last_update_id {
+                    return update
+                        .b
+                        .iter()
+                        .chain(&update.a)
+                        .filter_map(|level| parse_number(&level.price))
+                        .collect();
+                }
+                break;
+            }
+        }
+        vec![]
+    }
+
+    /// applies buffered updates in arrival order on top of the full book
+    pub fn apply_to(self, book: &mut OrderBook) -> Result<(), ApplyError> {
+        for update in &self.updates {
+            book.apply_depth_book_update_from_websocket(update)?;
+        }
+        Ok(())
+    }
+}
+
+/// Symbols needing a full book via http api (resync), their depth updates are buffered meanwhile
+#[derive(Default)]
+pub struct BookResync {
+    buffers: HashMap<String, DepthUpdateBuffer>,
+    queue: VecDeque<String>,
+    // symbols which books are compared with the full book before it's applied
+    reconciling: HashSet<String>,
+    // failed full book requests in a row, and when the symbols are queued again
+    failures: HashMap<String, u32>,
+    delayed: Vec<(Instant, String)>,
+}
+
+impl BookResync {
+    pub fn needs_resync(&self, symbol: &str) -> bool {
+        self.buffers.contains_key(symbol)
+    }
+
+    /// marks the symbol for resync and queues its full book request, if not yet
+    pub fn request(&mut self, symbol: &str) {
+        if !self.needs_resync(symbol) {
+            self.buffers.insert(
+                symbol.to_string(),
+                DepthUpdateBuffer::new(DEPTH_UPDATE_BUFFER_CAPACITY),
+            );
+            self.queue.push_back(symbol.to_string());
+        }
+    }
+
+    /// requests the full book to compare the book with, if the symbol isn't resynced already
+    pub fn reconcile(&mut self, symbol: &str) {
+        if !self.needs_resync(symbol) {
+            self.reconciling.insert(symbol.to_string());
+            self.request(symbol);
+        }
+    }
+
+    /// queues the full book request again, buffered updates are kept
+    pub fn retry(&mut self, symbol: &str) {
+        if self.needs_resync(symbol) {
+            self.queue.push_back(symbol.to_string());
+        } else {
+            self.request(symbol);
+        }
+    }
+
+    /// queues the full book request again once the delay has passed, the delay grows with
+    /// failed requests in a row up to the ceiling, returns the delay
+    pub fn retry_failed(&mut self, symbol: &str, now: Instant) -> Duration {
+        let failures = self.failures.entry(symbol.to_string()).or_default();
+        *failures += 1;
+        let delay = FAILED_RESYNC_DELAY
+            .saturating_mul(1 << (*failures - 1).min(16))
+            .min(MAX_FAILED_RESYNC_DELAY);
+        if !self.needs_resync(symbol) {
+            self.buffers.insert(
+                symbol.to_string(),
+                DepthUpdateBuffer::new(DEPTH_UPDATE_BUFFER_CAPACITY),
+            );
+        }
+        self.delayed.push((now + delay, symbol.to_string()));
+        delay
+    }
+
+    /// when the first delayed request is due
+    pub fn next_retry_at(&self) -> Option<Instant> {
+        self.delayed.iter().map(|(at, _)| *at).min()
+    }
+
+    /// queues the delayed requests which are due
+    pub fn queue_due(&mut self, now: Instant) {
+        let queue = &mut self.queue;
+        self.delayed.retain(|(at, symbol)| {
+            if *at > now {
+                return true;
+            }
+            queue.push_back(symbol.clone());
+            false
+        });
+    }
+
+    /// symbol isn't watched anymore, its buffer and requests are dropped
+    pub fn forget(&mut self, symbol: &str) {
+        self.buffers.remove(symbol);
+        self.queue.retain(|queued| queued != symbol);
+        self.reconciling.remove(symbol);
+        self.failures.remove(symbol);
+        self.delayed.retain(|(_, delayed)| delayed != symbol);
+    }
+
+    /// next symbol to request full book for
+    pub fn next_request(&mut self) -> Option<String> {
+        self.queue.pop_front()
+    }
+
+    /// buffers update of the symbol waiting for resync
+    pub fn buffer(&mut self, symbol: &str, update: BookDepthUpdate) {
+        self.buffers.get_mut(symbol).unwrap().push(update)
+    }
+
+    /// applies full book and buffered updates, requests resync again if the full book is too old,
+    /// returns levels the book differed in from the full book, if it's reconciled
+    pub fn complete(
+        &mut self,
+        book: &mut OrderBook,
+        full_book: &FullBook,
+    ) -> Result<Vec<LevelMismatch>, ApplyError> {
+        let symbol = book.get_symbol().to_string();
+        let mismatches = match (
+            self.reconciling.contains(&symbol),
+            self.buffers.get(&symbol),
+        ) {
+            (true, Some(buffer)) => {
+                let skipped_prices = buffer.catch_up(book, full_book.last_update_id);
+                book.mismatches(full_book, &skipped_prices)
+                    .inspect_err(|_| self.retry(&symbol))?
+            }
+            _ => vec![],
+        };
+        book.apply_full_book_from_http_api(full_book)
+            .inspect_err(|_| self.retry(&symbol))?;
+        self.reconciling.remove(&symbol);
+        self.failures.remove(&symbol);
+        if let Some(buffer) = self.buffers.remove(&symbol) {
+            buffer
+                .apply_to(book)
+                .inspect_err(|_| self.request(&symbol))?;
+        }
+        Ok(mismatches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::LevelApi;
+    use crate::number::number;
+
+    fn update(first: u64, last: u64, previous: u64, bid_price: &str) -> BookDepthUpdate {
+        BookDepthUpdate {
+            e: "depthUpdate".to_string(),
+            E: 0,
+            T: 0,
+            s: "BTCUSDT".to_string(),
+            U: first,
+            u: last,
+            pu: Some(previous),
+            b: vec![LevelApi {
+                price: bid_price.to_string(),
+                quantity: last.to_string(),
+            }],
+            a: vec![],
+        }
+    }
+
+    fn book_with_full_book(last_update_id: u64) -> OrderBook {
+        let mut book = OrderBook::new(5, "btcusdt".to_string());
+        book.apply_full_book_from_http_api(&FullBook {
+            last_update_id,
+            bids: vec![],
+            asks: vec![],
+        })
+        .unwrap();
+        book
+    }
+
+    #[test]
+    fn apply_to_straddling_full_book_test() {
+        let mut buffer = DepthUpdateBuffer::new(10);
+        // received while full book with lastUpdateId 100 was in flight
+        buffer.push(update(80, 90, 79, "1"));
+        buffer.push(update(91, 95, 90, "1"));
+        buffer.push(update(96, 105, 95, "1"));
+        buffer.push(update(106, 110, 105, "1"));
+
+        let mut book = book_with_full_book(100);
+
+        assert_eq!(buffer.apply_to(&mut book), Ok(()));
+        assert_eq!(book.get_last_update_id(), 110);
+        // same price, the latest update wins
+        assert_eq!(book.get_best_bid().unwrap().quantity, number("110.0"));
+    }
+
+    #[test]
+    fn apply_to_overflowed_test() {
+        let mut buffer = DepthUpdateBuffer::new(1);
+        buffer.push(update(96, 105, 95, "1"));
+        buffer.push(update(106, 110, 105, "2"));
+
+        let mut book = book_with_full_book(100);
+
+        // update containing lastUpdateId has been dropped
+        assert_eq!(
+            buffer.apply_to(&mut book),
+            Err(ApplyError::NeedsSnapshot {
+                last_update_id: 100,
+                first: 106,
+                last: 110
+            })
+        );
+    }
+
+    #[test]
+    fn book_resync_test() {
+        let mut resync = BookResync::default();
+        resync.request("btcusdt");
+        resync.request("btcusdt");
+
+        assert!(resync.needs_resync("btcusdt"));
+        assert!(!resync.needs_resync("ethusdt"));
+        assert_eq!(resync.next_request(), Some("btcusdt".to_string()));
+        assert_eq!(resync.next_request(), None);
+
+        resync.buffer("btcusdt", update(96, 105, 95, "1"));
+        let mut book = OrderBook::new(5, "btcusdt".to_string());
+        assert_eq!(
+            resync.complete(
+                &mut book,
+                &FullBook {
+                    last_update_id: 100,
+                    bids: vec![],
+                    asks: vec![],
+                }
+            ),
+            Ok(vec![])
+        );
+        assert!(!resync.needs_resync("btcusdt"));
+        assert_eq!(book.get_last_update_id(), 105);
+
+        // full book is older than buffered updates
+        resync.request("btcusdt");
+        resync.next_request();
+        resync.buffer("btcusdt", update(120, 125, 119, "1"));
+        assert_eq!(
+            resync.complete(
+                &mut book,
+                &FullBook {
+                    last_update_id: 110,
+                    bids: vec![],
+                    asks: vec![],
+                }
+            ),
+            Err(ApplyError::NeedsSnapshot {
+                last_update_id: 110,
+                first: 120,
+                last: 125
+            })
+        );
+        assert!(resync.needs_resync("btcusdt"));
+        assert_eq!(resync.next_request(), Some("btcusdt".to_string()));
+
+        // removed symbol is requested again once it's watched again
+        resync.retry("btcusdt");
+        resync.forget("btcusdt");
+        assert!(!resync.needs_resync("btcusdt"));
+        assert_eq!(resync.next_request(), None);
+        resync.request("btcusdt");
+        assert_eq!(resync.next_request(), Some("btcusdt".to_string()));
+    }
+
+    #[test]
+    fn retry_failed_test() {
+        let mut resync = BookResync::default();
+        let now = Instant::now();
+        resync.request("btcusdt");
+        resync.next_request();
+
+        // the delay doubles per failure up to the ceiling
+        let delays: Vec<u64> = (0..8)
+            .map(|_| {
+                let delay = resync.retry_failed("btcusdt", now);
+                resync.queue_due(now + delay);
+                resync.next_request();
+                delay.as_secs()
+            })
+            .collect();
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+
+        // not queued before the delay has passed
+        let delay = resync.retry_failed("btcusdt", now);
+        assert_eq!(resync.next_retry_at(), Some(now + delay));
+        resync.queue_due(now + delay - Duration::from_millis(1));
+        assert_eq!(resync.next_request(), None);
+        resync.queue_due(now + delay);
+        assert_eq!(resync.next_retry_at(), None);
+        assert_eq!(resync.next_request(), Some("btcusdt".to_string()));
+        assert!(resync.needs_resync("btcusdt"));
+
+        // a successful full book resets the failures
+        let mut book = OrderBook::new(5, "btcusdt".to_string());
+        let full_book = FullBook {
+            last_update_id: 100,
+            bids: vec![],
+            asks: vec![],
+        };
+        resync.complete(&mut book, &full_book).unwrap();
+        assert_eq!(resync.retry_failed("btcusdt", now), FAILED_RESYNC_DELAY);
+
+        // removed symbol isn't requested anymore
+        resync.forget("btcusdt");
+        assert_eq!(resync.next_retry_at(), None);
+        resync.queue_due(now + MAX_FAILED_RESYNC_DELAY);
+        assert_eq!(resync.next_request(), None);
+    }
+
+    #[test]
+    fn book_reconcile_test() {
+        let level = |price: &str, quantity: &str| LevelApi {
+            price: price.to_string(),
+            quantity: quantity.to_string(),
+        };
+        let mut book = OrderBook::new(5, "btcusdt".to_string());
+        book.apply_full_book_from_http_api(&FullBook {
+            last_update_id: 100,
+            bids: vec![level("10", "1"), level("9", "1")],
+            asks: vec![level("11", "1")],
+        })
+        .unwrap();
+        book.apply_depth_book_update_from_websocket(&update(96, 102, 95, "10"))
+            .unwrap();
+
+        let mut resync = BookResync::default();
+        resync.reconcile("btcusdt");
+        assert!(resync.needs_resync("btcusdt"));
+        assert_eq!(resync.next_request(), Some("btcusdt".to_string()));
+        resync.buffer("btcusdt", update(103, 105, 102, "10"));
+        resync.buffer("btcusdt", update(106, 110, 105, "9"));
+
+        // the book has missed level 8, the full book is in the middle of the last update
+        let full_book = FullBook {
+            last_update_id: 107,
+            bids: vec![level("10", "105"), level("9", "200"), level("8", "1")],
+            asks: vec![level("11", "1")],
+        };
+        assert_eq!(
+            resync.complete(&mut book, &full_book),
+            Ok(vec![LevelMismatch {
+                is_bid: true,
+                price: number("8.0"),
+                local_quantity: None,
+                remote_quantity: Some(number("1.0")),
+            }])
+        );
+        assert!(!resync.needs_resync("btcusdt"));
+        assert_eq!(book.get_last_update_id(), 110);
+        assert_eq!(book.snapshot().bids.len(), 3);
+
+        // the book is in sync now
+        resync.reconcile("btcusdt");
+        resync.next_request();
+        let full_book = FullBook {
+            last_update_id: 110,
+            bids: vec![level("10", "105"), level("9", "110"), level("8", "1")],
+            asks: vec![level("11", "1")],
+        };
+        assert_eq!(resync.complete(&mut book, &full_book), Ok(vec![]));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::messages::{ExchangeInfo, SymbolFilter, SymbolInfo};
+use crate::order_book::Precision;
+use std::collections::HashMap;
+
+/// fetches trading rules of all symbols of the market
+pub async fn fetch_exchange_info(
+    http_client: &reqwest::Client,
+    api_url: &str,
+) -> Result<ExchangeInfo, String> {
+    let body = http_client
+        .get(format!("{}/exchangeInfo", api_url))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| e.to_string())?
+        .text()
+        .await
+        .map_err(|e| e.to_string())?;
+    serde_json::from_str(&body).map_err(|e| e.to_string())
+}
+
+/// splits symbols into the ones trading on the market and errors of the others,
+/// e.g. typos or halted symbols, which would never get any update
+pub fn validate_symbols(info: &ExchangeInfo, symbols: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut valid = vec![];
+    let mut errors = vec![];
+    for symbol in symbols {
+        match info
+            .symbols
+            .iter()
+            .find(|info| info.symbol.eq_ignore_ascii_case(symbol))
+        {
+            Some(info) if info.status == "TRADING" => valid.push(symbol.clone()),
+            Some(info) => errors.push(format!("{symbol} isn't trading ({})", info.status)),
+            None => errors.push(format!("{symbol} is unknown")),
+        }
+    }
+    (valid, errors)
+}
+
+/// display precision of every symbol, keyed by lowercase symbol
+pub fn precisions(info: &ExchangeInfo) -> HashMap<String, Precision> {
+    info.symbols
+        .iter()
+        .map(|symbol| (symbol.symbol.to_lowercase(), precision(symbol)))
+        .collect()
+}
+
+/// decimals of tick size and step size, missing filters keep the shortest representation
+pub fn precision(symbol: &SymbolInfo) -> Precision {
+    let mut precision = Precision::default();
+    for filter in &symbol.filters {
+        match filter {
+            SymbolFilter::PriceFilter { tick_size } => {
+                precision.price_decimals = Some(decimals(tick_size))
+            }
+            SymbolFilter::LotSize { step_size } => {
+                precision.quantity_decimals = Some(decimals(step_size))
+            }
+            SymbolFilter::Other => {}
+        }
+    }
+    precision
+}
+
+/// significant decimal places of a step, e.g. 4 for "0.00010000" and 0 for "1.00000000"
+pub fn decimals(step: &str) -> u32 {
+    match step.split_once('.') {
+        Some((_, fraction)) => fraction.trim_end_matches('0').len() as u32,
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decimals_test() {
+        assert_eq!(decimals("0.00010000"), 4);
+        assert_eq!(decimals("0.01"), 2);
+        assert_eq!(decimals("0.10"), 1);
+        assert_eq!(decimals("1.00000000"), 0);
+        assert_eq!(decimals("10"), 0);
+    }
+
+    #[test]
+    fn validate_symbols_test() {
+        let text = r#"{"symbols":[
+            {"symbol":"BTCUSDT","status":"TRADING","filters":[]},
+            {"symbol":"ETHUSDT","status":"TRADING","filters":[]},
+            {"symbol":"LUNAUSDT","status":"BREAK","filters":[]}
+        ]}"#;
+        let info: ExchangeInfo = serde_json::from_str(text).unwrap();
+        let symbols = ["btcusdt", "btcusd", "ethusdt", "lunausdt"].map(String::from);
+
+        let (valid, errors) = validate_symbols(&info, &symbols);
+
+        assert_eq!(valid, vec!["btcusdt", "ethusdt"]);
+        assert_eq!(
+            errors,
+            vec!["btcusd is unknown", "lunausdt isn't trading (BREAK)"]
+        );
+    }
+
+    #[test]
+    fn precisions_test() {
+        let text = r#"{"timezone":"UTC","serverTime":1565246363776,"symbols":[
+            {"symbol":"ETHBTC","status":"TRADING","baseAsset":"ETH","filters":[
+                {"filterType":"PRICE_FILTER","minPrice":"0.00000100","maxPrice":"100000.00000000","tickSize":"0.00010000"},
+                {"filterType":"LOT_SIZE","minQty":"0.00100000","maxQty":"100000.00000000","stepSize":"0.00100000"},
+                {"filterType":"MIN_NOTIONAL","minNotional":"0.00100000"}
+            ]},
+            {"symbol":"BTCUSDT","status":"TRADING","filters":[
+                {"filterType":"PRICE_FILTER","minPrice":"556.80","maxPrice":"4529764","tickSize":"0.10"}
+            ]}
+        ]}"#;
+        let info: ExchangeInfo = serde_json::from_str(text).unwrap();
+        assert_eq!(info.symbols[0].filters[2], SymbolFilter::Other);
+
+        let precisions = precisions(&info);
+
+        assert_eq!(
+            precisions["ethbtc"],
+            Precision {
+                price_decimals: Some(4),
+                quantity_decimals: Some(3)
+            }
+        );
+        assert_eq!(
+            precisions["btcusdt"],
+            Precision {
+                price_decimals: Some(1),
+                quantity_decimals: None
+            }
+        );
+    }
+}
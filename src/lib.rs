@@ -0,0 +1,52 @@
+//! Binance order book watcher: keeps local order books in sync with binance streams.
+//!
+//! The books can be used on their own, fed with the messages of binance apis:
+//!
+//! ```
+//! use binance_watcher::messages::{BookDepthUpdate, FullBook};
+//! use binance_watcher::number::Number;
+//! use binance_watcher::order_book::{ApplyResult, OrderBook};
+//!
+//! let mut book = OrderBook::new(5, "btcusdt".to_string());
+//! let full_book: FullBook = serde_json::from_str(
+//!     r#"{"lastUpdateId":100,"bids":[["10","1"]],"asks":[["11","1"]]}"#,
+//! )
+//! .unwrap();
+//! book.apply_full_book_from_http_api(&full_book).unwrap();
+//!
+//! let update: BookDepthUpdate = serde_json::from_str(
+//!     r#"{"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":95,"u":105,"pu":94,"b":[["10","2"]],"a":[]}"#,
+//! )
+//! .unwrap();
+//! let result = book.apply_depth_book_update_from_websocket(&update).unwrap();
+//!
+//! assert_eq!(result, ApplyResult::Applied);
+//! assert_eq!(book.get_last_update_id(), 105);
+//! assert_eq!(book.get_best_bid().unwrap().quantity, Number::from(2));
+//! assert_eq!(book.get_mid(), "10.5".parse().ok());
+//! ```
+//!
+//! Prices and quantities are f64, or exact decimals with the `decimal` feature, see [Number](number::Number).
+//!
+//! Or the whole watcher is run with a [Config](console_arguments::Config), see [Watcher].
+
+pub mod alerts;
+pub mod commands;
+pub mod console_arguments;
+pub mod deflate_socket;
+pub mod depth_buffer;
+pub mod exchange_info;
+pub mod messages;
+pub mod metrics;
+pub mod number;
+pub mod order_book;
+pub mod recorder;
+pub mod render_throttle;
+pub mod renderer;
+pub mod rotating_file;
+pub mod snapshots;
+pub mod stats;
+pub mod subscriptions;
+pub mod watcher;
+
+pub use watcher::{run, Watcher};
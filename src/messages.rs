@@ -1,4 +1,5 @@
-use serde::{Deserialize, Serialize};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize};
 
 /// web socket Subscription entity [documentation]
 ///
@@ -16,32 +17,714 @@ pub struct Subscription {
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize)]
 pub struct BookDepthUpdate {
-    pub e: String,        // Event type
-    pub E: u64,           // Event time
-    pub T: u64,           // Transaction time
-    pub s: String,        // Symbol
-    pub U: u64,           // First update ID in event
-    pub u: u64,           // Final update ID in event
-    pub pu: u64,          // Final update ID in last stream(ie `u` in last stream)
+    pub e: String, // Event type
+    pub E: u64,    // Event time
+    #[serde(default)]
+    pub T: u64, // Transaction time, absent on spot
+    pub s: String, // Symbol
+    pub U: u64,    // First update ID in event
+    pub u: u64,    // Final update ID in event
+    pub pu: Option<u64>, // Final update ID in last stream(ie `u` in last stream), absent on spot
     pub b: Vec<LevelApi>, // bids
     pub a: Vec<LevelApi>, // asks
 }
 
+/// web socket AggTrade entity [documentation]
+///
+/// [documentation]: [https://developers.binance.com/docs/derivatives/usds-margined-futures/websocket-market-streams/Aggregate-Trade-Streams]
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AggTrade {
+    pub e: String, // Event type
+    pub E: u64,    // Event time
+    pub s: String, // Symbol
+    pub a: u64,    // Aggregate trade ID
+    pub p: String, // Price
+    pub q: String, // Quantity
+    pub f: u64,    // First trade ID
+    pub l: u64,    // Last trade ID
+    pub T: u64,    // Trade time
+    pub m: bool,   // Is the buyer the market maker?
+}
+
+/// web socket BookTicker entity [documentation]
+///
+/// [documentation]: [https://developers.binance.com/docs/derivatives/usds-margined-futures/websocket-market-streams/Individual-Symbol-Book-Ticker-Streams]
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize)]
+pub struct BookTicker {
+    pub e: String, // Event type
+    pub u: u64,    // Order book updateId
+    pub E: u64,    // Event time
+    pub T: u64,    // Transaction time
+    pub s: String, // Symbol
+    pub b: String, // Best bid price
+    pub B: String, // Best bid qty
+    pub a: String, // Best ask price
+    pub A: String, // Best ask qty
+}
+
+/// web socket MarkPriceUpdate entity, futures only [documentation]
+///
+/// [documentation]: [https://developers.binance.com/docs/derivatives/usds-margined-futures/websocket-market-streams/Mark-Price-Stream]
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize)]
+pub struct MarkPriceUpdate {
+    pub e: String, // Event type
+    pub E: u64,    // Event time
+    pub s: String, // Symbol
+    pub p: String, // Mark price
+    #[serde(default)]
+    pub i: String, // Index price
+    #[serde(default)]
+    pub P: String, // Estimated settle price
+    pub r: String, // Funding rate
+    pub T: u64,    // Next funding time
+}
+
+/// web socket 24hr rolling window ticker entity, spot sends more fields [documentation]
+///
+/// [documentation]: [https://developers.binance.com/docs/derivatives/usds-margined-futures/websocket-market-streams/Individual-Symbol-Ticker-Streams]
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize)]
+pub struct Ticker24hr {
+    pub e: String, // Event type
+    pub E: u64,    // Event time
+    pub s: String, // Symbol
+    pub p: String, // Price change
+    pub P: String, // Price change percent
+    pub c: String, // Last price
+    pub o: String, // Open price
+    pub h: String, // High price
+    pub l: String, // Low price
+    pub v: String, // Total traded base asset volume
+    pub q: String, // Total traded quote asset volume
+}
+
+/// web socket liquidation order entity, futures only [documentation]
+///
+/// [documentation]: [https://developers.binance.com/docs/derivatives/usds-margined-futures/websocket-market-streams/Liquidation-Order-Streams]
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ForceOrderUpdate {
+    pub e: String,     // Event type
+    pub E: u64,        // Event time
+    pub o: ForceOrder, // Liquidation order
+}
+
+/// liquidation order of [ForceOrderUpdate]
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ForceOrder {
+    pub s: String,  // Symbol
+    pub S: String,  // Side, BUY or SELL
+    pub o: String,  // Order type
+    pub f: String,  // Time in force
+    pub q: String,  // Original quantity
+    pub p: String,  // Price
+    pub ap: String, // Average price
+    pub X: String,  // Order status
+    pub l: String,  // Order last filled quantity
+    pub z: String,  // Order filled accumulated quantity
+    pub T: u64,     // Order trade time
+}
+
+/// web socket Kline entity [documentation]
+///
+/// [documentation]: [https://developers.binance.com/docs/derivatives/usds-margined-futures/websocket-market-streams/Kline-Candlestick-Streams]
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize)]
+pub struct KlineUpdate {
+    pub e: String, // Event type
+    pub E: u64,    // Event time
+    pub s: String, // Symbol
+    pub k: Kline,  // Kline
+}
+
+/// candle of [KlineUpdate]
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize)]
+pub struct Kline {
+    pub t: u64,    // Kline start time
+    pub T: u64,    // Kline close time
+    pub i: String, // Interval
+    pub o: String, // Open price
+    pub c: String, // Close price
+    pub h: String, // High price
+    pub l: String, // Low price
+    pub v: String, // Base asset volume
+    pub n: u64,    // Number of trades
+    pub x: bool,   // Is this kline closed?
+}
+
+/// http api exchangeInfo response body entity, only used fields [documentation]
+///
+/// [documentation]: [https://developers.binance.com/docs/derivatives/usds-margined-futures/market-data/rest-api/Exchange-Information]
+#[derive(Serialize, Deserialize)]
+pub struct ExchangeInfo {
+    pub symbols: Vec<SymbolInfo>,
+}
+
+/// symbol of [ExchangeInfo]
+#[derive(Serialize, Deserialize)]
+pub struct SymbolInfo {
+    pub symbol: String,
+    pub status: String,
+    pub filters: Vec<SymbolFilter>,
+}
+
+/// trading rule of [SymbolInfo], only used filters are parsed
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "filterType")]
+pub enum SymbolFilter {
+    #[serde(rename = "PRICE_FILTER")]
+    PriceFilter {
+        #[serde(rename = "tickSize")]
+        tick_size: String,
+    },
+    #[serde(rename = "LOT_SIZE")]
+    LotSize {
+        #[serde(rename = "stepSize")]
+        step_size: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
 /// http api full book response body entity
 ///
 /// [documentation]: [https://developers.binance.com/docs/derivatives/usds-margined-futures/websocket-market-streams/How-to-manage-a-local-order-book-correctly]
 #[derive(Serialize, Deserialize)]
 pub struct FullBook {
     // tbd: warning could've been ignored as above but it has a long name?
-    #[serde(rename(deserialize = "lastUpdateId"))]
+    #[serde(rename = "lastUpdateId")]
     pub last_update_id: u64,
     pub bids: Vec<LevelApi>,
     pub asks: Vec<LevelApi>,
 }
 
-/// Book level sent by binance via ws and http, the order matters
+/// Partial book depth stream entity, top levels replacing the book on every update,
+/// spot sends lastUpdateId with bids and asks, futures a depthUpdate with u, b and a [documentation]
+///
+/// [documentation]: [https://developers.binance.com/docs/derivatives/usds-margined-futures/websocket-market-streams/Partial-Book-Depth-Streams]
 #[derive(Serialize, Deserialize)]
+pub struct PartialDepthUpdate {
+    #[serde(rename = "lastUpdateId", alias = "u")]
+    pub last_update_id: u64,
+    // event time, absent on spot
+    #[serde(rename = "E", default)]
+    pub event_time: Option<u64>,
+    #[serde(alias = "b")]
+    pub bids: Vec<LevelApi>,
+    #[serde(alias = "a")]
+    pub asks: Vec<LevelApi>,
+}
+
+impl From<BookDepthUpdate> for PartialDepthUpdate {
+    fn from(update: BookDepthUpdate) -> Self {
+        Self {
+            last_update_id: update.u,
+            event_time: Some(update.E),
+            bids: update.b,
+            asks: update.a,
+        }
+    }
+}
+
+/// http api error body, e.g. `{"code":-1121,"msg":"Invalid symbol."}` for a mistyped symbol
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiError {
+    pub code: i64,
+    pub msg: String,
+}
+
+/// http api error code of an unknown symbol
+pub const INVALID_SYMBOL_CODE: i64 = -1121;
+
+/// Full book received via http api as a local event, it's recorded along with websocket frames
+/// so the books could be replayed
+#[derive(Serialize, Deserialize)]
+pub struct FullBookEvent {
+    pub e: String, // Event type, always fullBook
+    pub s: String, // Symbol
+    #[serde(flatten)]
+    pub book: FullBook,
+}
+
+impl FullBookEvent {
+    pub fn new(symbol: &str, book: FullBook) -> Self {
+        Self {
+            e: "fullBook".to_string(),
+            s: symbol.to_string(),
+            book,
+        }
+    }
+}
+
+/// Book level sent by binance via ws and http as ["price", "quantity"], the order matters
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(from = "[String; 2]", into = "[String; 2]")]
 pub struct LevelApi {
     pub price: String,
     pub quantity: String,
 }
+
+impl From<[String; 2]> for LevelApi {
+    fn from([price, quantity]: [String; 2]) -> Self {
+        LevelApi { price, quantity }
+    }
+}
+
+impl From<LevelApi> for [String; 2] {
+    fn from(level: LevelApi) -> Self {
+        [level.price, level.quantity]
+    }
+}
+
+/// web socket Subscription response entity, result is null on success [documentation]
+///
+/// [documentation]: [https://developers.binance.com/docs/derivatives/usds-margined-futures/websocket-market-streams/Live-Subscribing-Unsubscribing-to-streams]
+#[derive(Serialize, Deserialize)]
+pub struct SubscribeAck {
+    #[serde(default)]
+    pub result: Option<serde_json::Value>,
+    pub id: String,
+    /// spot error is nested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<AckError>,
+    /// futures error code and message are aside the id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub msg: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AckError {
+    pub code: i64,
+    pub msg: String,
+}
+
+impl SubscribeAck {
+    /// error of the request in either form, None on success
+    pub fn error_message(&self) -> Option<String> {
+        match (&self.error, self.code, &self.msg) {
+            (Some(error), _, _) => Some(format!("{} {}", error.code, error.msg)),
+            (None, Some(code), msg) => Some(format!("{} {}", code, msg.as_deref().unwrap_or(""))),
+            (None, None, Some(msg)) => Some(msg.clone()),
+            (None, None, None) => None,
+        }
+    }
+}
+
+/// web socket combined stream envelope [documentation]
+///
+/// [documentation]: [https://developers.binance.com/docs/derivatives/usds-margined-futures/websocket-market-streams/Connect]
+#[derive(Serialize, Deserialize)]
+pub struct CombinedStreamMessage {
+    pub stream: String, // Stream name, e.g. btcusdt@depth
+    pub data: serde_json::Value,
+}
+
+impl CombinedStreamMessage {
+    /// symbol the stream belongs to, e.g. btcusdt for btcusdt@depth
+    pub fn symbol(&self) -> &str {
+        self.stream.split('@').next().unwrap_or_default()
+    }
+}
+
+/// Any text frame received from binance web socket, routed by the `e` (event type) field
+pub enum StreamMessage {
+    DepthUpdate(BookDepthUpdate),
+    AggTrade(AggTrade),
+    BookTicker(BookTicker),
+    MarkPrice(MarkPriceUpdate),
+    Ticker24hr(Ticker24hr),
+    ForceOrder(ForceOrderUpdate),
+    Kline(KlineUpdate),
+    FullBook(FullBookEvent),
+    // never deserialized by itself, see [StreamMessage::into_partial_depth]
+    PartialDepth(PartialDepthUpdate),
+    SubscribeAck(SubscribeAck),
+    Other(serde_json::Value),
+}
+
+impl StreamMessage {
+    /// lowercase symbol the message belongs to, if any
+    pub fn symbol(&self) -> Option<String> {
+        match self {
+            StreamMessage::DepthUpdate(update) => Some(update.s.to_lowercase()),
+            StreamMessage::AggTrade(trade) => Some(trade.s.to_lowercase()),
+            StreamMessage::BookTicker(ticker) => Some(ticker.s.to_lowercase()),
+            StreamMessage::MarkPrice(update) => Some(update.s.to_lowercase()),
+            StreamMessage::Ticker24hr(ticker) => Some(ticker.s.to_lowercase()),
+            StreamMessage::ForceOrder(update) => Some(update.o.s.to_lowercase()),
+            StreamMessage::Kline(update) => Some(update.s.to_lowercase()),
+            StreamMessage::FullBook(event) => Some(event.s.to_lowercase()),
+            StreamMessage::Other(value) => value
+                .get("s")
+                .and_then(|s| s.as_str())
+                .map(|s| s.to_lowercase()),
+            StreamMessage::PartialDepth(_) | StreamMessage::SubscribeAck(_) => None,
+        }
+    }
+
+    /// depth payloads of partial depth streams, they're routed as depth updates (futures)
+    /// or have no event type at all (spot), other messages are kept
+    pub fn into_partial_depth(self) -> StreamMessage {
+        match self {
+            StreamMessage::DepthUpdate(update) => StreamMessage::PartialDepth(update.into()),
+            StreamMessage::Other(value) if value.get("lastUpdateId").is_some() => {
+                match serde_json::from_value(value.clone()) {
+                    Ok(update) => StreamMessage::PartialDepth(update),
+                    Err(_) => StreamMessage::Other(value),
+                }
+            }
+            message => message,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for StreamMessage {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let event_type = value.get("e").and_then(|e| e.as_str()).map(str::to_owned);
+        let message = match event_type.as_deref() {
+            Some("depthUpdate") => {
+                StreamMessage::DepthUpdate(serde_json::from_value(value).map_err(Error::custom)?)
+            }
+            Some("aggTrade") => {
+                StreamMessage::AggTrade(serde_json::from_value(value).map_err(Error::custom)?)
+            }
+            Some("bookTicker") => {
+                StreamMessage::BookTicker(serde_json::from_value(value).map_err(Error::custom)?)
+            }
+            Some("markPriceUpdate") => {
+                StreamMessage::MarkPrice(serde_json::from_value(value).map_err(Error::custom)?)
+            }
+            Some("24hrTicker") => {
+                StreamMessage::Ticker24hr(serde_json::from_value(value).map_err(Error::custom)?)
+            }
+            Some("forceOrder") => {
+                StreamMessage::ForceOrder(serde_json::from_value(value).map_err(Error::custom)?)
+            }
+            Some("kline") => {
+                StreamMessage::Kline(serde_json::from_value(value).map_err(Error::custom)?)
+            }
+            Some("fullBook") => {
+                StreamMessage::FullBook(serde_json::from_value(value).map_err(Error::custom)?)
+            }
+            Some(_) => StreamMessage::Other(value),
+            // acks have no event type, only the id of the request
+            None if value.get("id").is_some() => {
+                StreamMessage::SubscribeAck(serde_json::from_value(value).map_err(Error::custom)?)
+            }
+            None => StreamMessage::Other(value),
+        };
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stream_message_depth_update_test() {
+        let text = r#"{"e":"depthUpdate","E":123456789,"T":123456788,"s":"BTCUSDT","U":157,"u":160,"pu":149,"b":[["0.0024","10"]],"a":[["0.0026","100"]]}"#;
+
+        match serde_json::from_str::<StreamMessage>(text).unwrap() {
+            StreamMessage::DepthUpdate(update) => {
+                assert_eq!(update.s, "BTCUSDT");
+                assert_eq!(update.U, 157);
+                assert_eq!(update.u, 160);
+                assert_eq!(update.pu, Some(149));
+                assert_eq!(update.b[0].price, "0.0024");
+                assert_eq!(update.a[0].quantity, "100");
+            }
+            _ => panic!("expected depth update"),
+        }
+    }
+
+    #[test]
+    fn stream_message_spot_depth_update_test() {
+        let text = r#"{"e":"depthUpdate","E":1672515782136,"s":"BNBBTC","U":157,"u":160,"b":[["0.0024","10"]],"a":[["0.0026","100"]]}"#;
+
+        match serde_json::from_str::<StreamMessage>(text).unwrap() {
+            StreamMessage::DepthUpdate(update) => {
+                assert_eq!(update.U, 157);
+                assert_eq!(update.pu, None);
+            }
+            _ => panic!("expected depth update"),
+        }
+    }
+
+    #[test]
+    fn stream_message_agg_trade_test() {
+        let text = r#"{"e":"aggTrade","E":123456789,"s":"BTCUSDT","a":5933014,"p":"0.001","q":"100","f":100,"l":105,"T":123456785,"m":true}"#;
+
+        match serde_json::from_str::<StreamMessage>(text).unwrap() {
+            StreamMessage::AggTrade(trade) => {
+                assert_eq!(trade.s, "BTCUSDT");
+                assert_eq!(trade.p, "0.001");
+                assert_eq!(trade.q, "100");
+                assert_eq!(trade.T, 123456785);
+                assert!(trade.m);
+            }
+            _ => panic!("expected agg trade"),
+        }
+    }
+
+    #[test]
+    fn stream_message_book_ticker_test() {
+        let text = r#"{"e":"bookTicker","u":400900217,"E":1568014460893,"T":1568014460891,"s":"BNBUSDT","b":"25.35190000","B":"31.21000000","a":"25.36520000","A":"40.66000000"}"#;
+
+        match serde_json::from_str::<StreamMessage>(text).unwrap() {
+            StreamMessage::BookTicker(ticker) => {
+                assert_eq!(ticker.s, "BNBUSDT");
+                assert_eq!(ticker.b, "25.35190000");
+                assert_eq!(ticker.A, "40.66000000");
+            }
+            _ => panic!("expected book ticker"),
+        }
+    }
+
+    #[test]
+    fn stream_message_mark_price_test() {
+        let text = r#"{"e":"markPriceUpdate","E":1562305380000,"s":"BTCUSDT","p":"11794.15000000","i":"11784.62659091","P":"11784.25641265","r":"0.00038167","T":1562306400000}"#;
+
+        match serde_json::from_str::<StreamMessage>(text).unwrap() {
+            StreamMessage::MarkPrice(update) => {
+                assert_eq!(update.s, "BTCUSDT");
+                assert_eq!(update.p, "11794.15000000");
+                assert_eq!(update.r, "0.00038167");
+                assert_eq!(update.T, 1562306400000);
+            }
+            _ => panic!("expected mark price"),
+        }
+    }
+
+    #[test]
+    fn stream_message_ticker_24hr_test() {
+        let text = r#"{"e":"24hrTicker","E":123456789,"s":"BTCUSDT","p":"0.0015","P":"250.00","w":"0.0018","c":"0.0025","Q":"10","o":"0.0010","h":"0.0025","l":"0.0010","v":"10000","q":"18","O":0,"C":86400000,"F":0,"L":18150,"n":18151}"#;
+
+        match serde_json::from_str::<StreamMessage>(text).unwrap() {
+            StreamMessage::Ticker24hr(ticker) => {
+                assert_eq!(ticker.s, "BTCUSDT");
+                assert_eq!(ticker.P, "250.00");
+                assert_eq!(ticker.c, "0.0025");
+                assert_eq!(ticker.h, "0.0025");
+                assert_eq!(ticker.l, "0.0010");
+                assert_eq!(ticker.v, "10000");
+            }
+            _ => panic!("expected 24hr ticker"),
+        }
+    }
+
+    #[test]
+    fn stream_message_force_order_test() {
+        let text = r#"{"e":"forceOrder","E":1568014460893,"o":{"s":"BTCUSDT","S":"SELL","o":"LIMIT","f":"IOC","q":"0.014","p":"9910","ap":"9910","X":"FILLED","l":"0.014","z":"0.014","T":1568014460893}}"#;
+
+        let message = serde_json::from_str::<StreamMessage>(text).unwrap();
+        assert_eq!(message.symbol().as_deref(), Some("btcusdt"));
+        match message {
+            StreamMessage::ForceOrder(update) => {
+                assert_eq!(update.o.S, "SELL");
+                assert_eq!(update.o.p, "9910");
+                assert_eq!(update.o.q, "0.014");
+                assert_eq!(update.o.ap, "9910");
+                assert_eq!(update.o.T, 1568014460893);
+            }
+            _ => panic!("expected force order"),
+        }
+    }
+
+    #[test]
+    fn stream_message_kline_test() {
+        let text = r#"{"e":"kline","E":1638747660000,"s":"BTCUSDT","k":{"t":1638747660000,"T":1638747719999,"s":"BTCUSDT","i":"1m","f":100,"L":200,"o":"0.0010","c":"0.0020","h":"0.0025","l":"0.0015","v":"1000","n":100,"x":false,"q":"1.0000","V":"500","Q":"0.500","B":"123456"}}"#;
+
+        match serde_json::from_str::<StreamMessage>(text).unwrap() {
+            StreamMessage::Kline(update) => {
+                assert_eq!(update.s, "BTCUSDT");
+                assert_eq!(update.k.i, "1m");
+                assert_eq!(update.k.o, "0.0010");
+                assert_eq!(update.k.h, "0.0025");
+                assert_eq!(update.k.v, "1000");
+                assert!(!update.k.x);
+            }
+            _ => panic!("expected kline"),
+        }
+    }
+
+    #[test]
+    fn stream_message_subscribe_ack_test() {
+        let text = r#"{"result":null,"id":"btcusdt_1700000000000"}"#;
+
+        match serde_json::from_str::<StreamMessage>(text).unwrap() {
+            StreamMessage::SubscribeAck(ack) => {
+                assert!(ack.result.is_none());
+                assert_eq!(ack.id, "btcusdt_1700000000000");
+                assert_eq!(ack.error_message(), None);
+            }
+            _ => panic!("expected subscribe ack"),
+        }
+    }
+
+    #[test]
+    fn subscribe_ack_error_test() {
+        let spot = r#"{"error":{"code":2,"msg":"Invalid request"},"id":"btcusdt_1"}"#;
+        let futures = r#"{"code":0,"msg":"Unknown property","id":"btcusdt_1"}"#;
+
+        for (text, expected) in [(spot, "2 Invalid request"), (futures, "0 Unknown property")] {
+            match serde_json::from_str::<StreamMessage>(text).unwrap() {
+                StreamMessage::SubscribeAck(ack) => {
+                    assert_eq!(ack.id, "btcusdt_1");
+                    assert_eq!(ack.error_message().as_deref(), Some(expected));
+                }
+                _ => panic!("expected subscribe ack"),
+            }
+        }
+    }
+
+    #[test]
+    fn combined_stream_message_test() {
+        let text = r#"{"stream":"ethusdt@depth","data":{"e":"depthUpdate","E":1,"T":1,"s":"ETHUSDT","U":1,"u":2,"pu":0,"b":[],"a":[]}}"#;
+
+        let envelope = serde_json::from_str::<CombinedStreamMessage>(text).unwrap();
+
+        assert_eq!(envelope.symbol(), "ethusdt");
+        assert!(matches!(
+            serde_json::from_value::<StreamMessage>(envelope.data).unwrap(),
+            StreamMessage::DepthUpdate(_)
+        ));
+    }
+
+    #[test]
+    fn level_api_array_round_trip_test() {
+        let level: LevelApi = serde_json::from_str(r#"["59000.1","0.5"]"#).unwrap();
+
+        assert_eq!(level.price, "59000.1");
+        assert_eq!(level.quantity, "0.5");
+        assert_eq!(
+            serde_json::to_string(&level).unwrap(),
+            r#"["59000.1","0.5"]"#
+        );
+    }
+
+    #[test]
+    fn full_book_test() {
+        let text = r#"{"lastUpdateId":1027024,"E":1589436922972,"T":1589436922959,"bids":[["4.00000000","431.00000000"]],"asks":[["4.00000200","12.00000000"]]}"#;
+
+        let book: FullBook = serde_json::from_str(text).unwrap();
+
+        assert_eq!(book.last_update_id, 1027024);
+        assert_eq!(book.bids[0].price, "4.00000000");
+        assert_eq!(book.asks[0].quantity, "12.00000000");
+    }
+
+    #[test]
+    fn stream_message_full_book_test() {
+        let event = FullBookEvent::new(
+            "btcusdt",
+            FullBook {
+                last_update_id: 100,
+                bids: vec![LevelApi {
+                    price: "1".to_string(),
+                    quantity: "2".to_string(),
+                }],
+                asks: vec![],
+            },
+        );
+        let text = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            text,
+            r#"{"e":"fullBook","s":"btcusdt","lastUpdateId":100,"bids":[["1","2"]],"asks":[]}"#
+        );
+
+        match serde_json::from_str::<StreamMessage>(&text).unwrap() {
+            StreamMessage::FullBook(event) => {
+                assert_eq!(event.s, "btcusdt");
+                assert_eq!(event.book.last_update_id, 100);
+            }
+            _ => panic!("expected full book"),
+        }
+    }
+
+    #[test]
+    fn truncated_stream_message_test() {
+        // every prefix of a valid frame is an error, never a panic, see fuzz/ for arbitrary input
+        for text in [
+            r#"{"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":157,"u":160,"pu":149,"b":[["0.0024","10"]],"a":[]}"#,
+            r#"{"e":"aggTrade","E":1,"s":"BTCUSDT","a":1,"p":"0.001","q":"100","f":100,"l":105,"T":1,"m":true}"#,
+            r#"{"error":{"code":2,"msg":"Invalid request"},"id":"btcusdt_1"}"#,
+        ] {
+            assert!(serde_json::from_str::<StreamMessage>(text).is_ok());
+            for end in 0..text.len() {
+                assert!(serde_json::from_str::<StreamMessage>(&text[..end]).is_err());
+            }
+        }
+        // known event of a wrong shape is an error, anything else is kept
+        assert!(serde_json::from_str::<StreamMessage>(r#"{"e":"depthUpdate","U":"1"}"#).is_err());
+        for text in [r#"{"e":"unknown"}"#, "[]", "1"] {
+            let message = serde_json::from_str::<StreamMessage>(text).unwrap();
+            assert!(matches!(message, StreamMessage::Other(_)));
+            assert_eq!(message.symbol(), None);
+        }
+    }
+
+    #[test]
+    fn partial_depth_test() {
+        // spot
+        let text = r#"{"lastUpdateId":160,"bids":[["0.0024","10"]],"asks":[["0.0026","100"]]}"#;
+        match serde_json::from_str::<StreamMessage>(text)
+            .unwrap()
+            .into_partial_depth()
+        {
+            StreamMessage::PartialDepth(update) => {
+                assert_eq!(update.last_update_id, 160);
+                assert_eq!(update.event_time, None);
+                assert_eq!(update.bids[0].price, "0.0024");
+                assert_eq!(update.asks[0].quantity, "100");
+            }
+            _ => panic!("Expected partial depth"),
+        }
+
+        // futures
+        let text = r#"{"e":"depthUpdate","E":1571889248277,"T":1571889248276,"s":"BTCUSDT","U":390497796,"u":390497878,"pu":390497794,"b":[["7403.89","0.002"]],"a":[["7405.96","3.340"],["7406.63","4.525"]]}"#;
+        let message = serde_json::from_str::<StreamMessage>(text).unwrap();
+        assert_eq!(message.symbol(), Some("btcusdt".to_string()));
+        match message.into_partial_depth() {
+            StreamMessage::PartialDepth(update) => {
+                assert_eq!(update.last_update_id, 390497878);
+                assert_eq!(update.event_time, Some(1571889248277));
+                assert_eq!(update.bids.len(), 1);
+                assert_eq!(update.asks.len(), 2);
+            }
+            _ => panic!("Expected partial depth"),
+        }
+
+        // other messages are kept
+        let text = r#"{"result":null,"id":"btcusdt_1"}"#;
+        assert!(matches!(
+            serde_json::from_str::<StreamMessage>(text)
+                .unwrap()
+                .into_partial_depth(),
+            StreamMessage::SubscribeAck(_)
+        ));
+    }
+
+    #[test]
+    fn stream_message_other_test() {
+        // symbol containing "id" must not be taken for an ack
+        let text =
+            r#"{"e":"indexPriceUpdate","E":1562305380000,"s":"IDUSDT","p":"11794.15000000"}"#;
+
+        assert!(matches!(
+            serde_json::from_str::<StreamMessage>(text).unwrap(),
+            StreamMessage::Other(_)
+        ));
+    }
+}
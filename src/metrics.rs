@@ -0,0 +1,188 @@
+use crate::number::to_f64;
+use crate::order_book::OrderBook;
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tracing::error;
+
+/// Prometheus metrics shared by handlers and /metrics endpoint [documentation]
+///
+/// [documentation]: [https://prometheus.io/docs/instrumenting/exposition_formats/]
+#[derive(Default)]
+pub struct Metrics {
+    books: Mutex<BTreeMap<String, BookMetrics>>,
+    reconnects: AtomicU64,
+    dropped_frames: AtomicU64,
+}
+
+/// Latest values of a book, gauges except updates_applied
+#[derive(Default)]
+struct BookMetrics {
+    mid: Option<f64>,
+    spread: Option<f64>,
+    best_bid_quantity: Option<f64>,
+    best_ask_quantity: Option<f64>,
+    updates_applied: u64,
+}
+
+/// reads gauge value of a book
+type GaugeValue = fn(&BookMetrics) -> Option<f64>;
+
+impl Metrics {
+    /// refreshes gauges of the book after an applied update
+    pub fn record_book_update(&self, book: &OrderBook) {
+        let mut books = self.books.lock().unwrap();
+        let metrics = books.entry(book.get_symbol().to_string()).or_default();
+        metrics.mid = book.get_mid().map(to_f64);
+        metrics.spread = book.get_spread().map(to_f64);
+        metrics.best_bid_quantity = book.get_best_bid().ok().map(|level| to_f64(level.quantity));
+        metrics.best_ask_quantity = book.get_best_ask().ok().map(|level| to_f64(level.quantity));
+        metrics.updates_applied += 1;
+    }
+
+    /// drops the book series of a symbol which isn't watched anymore
+    pub fn forget(&self, symbol: &str) {
+        self.books.lock().unwrap().remove(symbol);
+    }
+
+    pub fn record_reconnect(&self) {
+        self.reconnects.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn record_dropped_frame(&self) {
+        self.dropped_frames.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// metrics in prometheus text format
+    pub fn render(&self) -> String {
+        let books = self.books.lock().unwrap();
+        let mut text = String::new();
+        let gauges: [(&str, &str, GaugeValue); 4] = [
+            ("binance_watcher_mid_price", "Mid price of the book", |m| {
+                m.mid
+            }),
+            (
+                "binance_watcher_spread",
+                "Best ask minus best bid price",
+                |m| m.spread,
+            ),
+            (
+                "binance_watcher_best_bid_quantity",
+                "Quantity of the best bid",
+                |m| m.best_bid_quantity,
+            ),
+            (
+                "binance_watcher_best_ask_quantity",
+                "Quantity of the best ask",
+                |m| m.best_ask_quantity,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            writeln!(text, "# HELP {name} {help}").unwrap();
+            writeln!(text, "# TYPE {name} gauge").unwrap();
+            for (symbol, metrics) in books.iter() {
+                if let Some(value) = value(metrics) {
+                    writeln!(text, "{name}{{symbol=\"{symbol}\"}} {value}").unwrap();
+                }
+            }
+        }
+
+        let name = "binance_watcher_updates_applied_total";
+        writeln!(text, "# HELP {name} Depth updates applied to the book").unwrap();
+        writeln!(text, "# TYPE {name} counter").unwrap();
+        for (symbol, metrics) in books.iter() {
+            writeln!(
+                text,
+                "{name}{{symbol=\"{symbol}\"}} {}",
+                metrics.updates_applied
+            )
+            .unwrap();
+        }
+
+        let name = "binance_watcher_reconnects_total";
+        writeln!(text, "# HELP {name} Reconnections to binance websocket").unwrap();
+        writeln!(text, "# TYPE {name} counter").unwrap();
+        writeln!(text, "{name} {}", self.reconnects.load(Ordering::SeqCst)).unwrap();
+
+        let name = "binance_watcher_dropped_frames_total";
+        writeln!(
+            text,
+            "# HELP {name} Frames dropped while the frame queue was full"
+        )
+        .unwrap();
+        writeln!(text, "# TYPE {name} counter").unwrap();
+        writeln!(
+            text,
+            "{name} {}",
+            self.dropped_frames.load(Ordering::SeqCst)
+        )
+        .unwrap();
+        text
+    }
+}
+
+/// serves GET /metrics, any other path is 404
+pub async fn serve_metrics(listener: TcpListener, metrics: Arc<Metrics>) {
+    let router = Router::new()
+        .route("/metrics", get(render_metrics))
+        .with_state(metrics);
+    if let Err(e) = axum::serve(listener, router).await {
+        error!("Metrics server stopped ({e})");
+    }
+}
+
+async fn render_metrics(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::order_book::test_book;
+
+    #[tokio::test]
+    async fn serve_metrics_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let metrics = Arc::new(Metrics::default());
+        tokio::spawn(serve_metrics(listener, metrics.clone()));
+
+        let book = test_book("btcusdt", &[["1", "3"]], &[["2", "4"]]);
+        metrics.record_book_update(&book);
+        metrics.record_reconnect();
+
+        let body = reqwest::get(format!("{url}/metrics"))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        assert!(body.contains("binance_watcher_mid_price{symbol=\"btcusdt\"} 1.5"));
+        assert!(body.contains("binance_watcher_spread{symbol=\"btcusdt\"} 1"));
+        assert!(body.contains("binance_watcher_best_bid_quantity{symbol=\"btcusdt\"} 3"));
+        assert!(body.contains("binance_watcher_best_ask_quantity{symbol=\"btcusdt\"} 4"));
+        assert!(body.contains("binance_watcher_updates_applied_total{symbol=\"btcusdt\"} 1"));
+        assert!(body.contains("binance_watcher_reconnects_total 1"));
+
+        let status = reqwest::get(format!("{url}/other")).await.unwrap().status();
+        assert_eq!(status, reqwest::StatusCode::NOT_FOUND);
+        let status = reqwest::Client::new()
+            .post(format!("{url}/metrics"))
+            .send()
+            .await
+            .unwrap()
+            .status();
+        assert_eq!(status, reqwest::StatusCode::METHOD_NOT_ALLOWED);
+    }
+}
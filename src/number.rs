@@ -0,0 +1,194 @@
+use std::cmp::Ordering;
+
+/// Price or quantity of levels and trades, f64 by default,
+/// exact `rust_decimal::Decimal` parsed from binance strings with the `decimal` feature
+#[cfg(not(feature = "decimal"))]
+pub type Number = f64;
+
+/// Price or quantity of levels and trades, f64 by default,
+/// exact `rust_decimal::Decimal` parsed from binance strings with the `decimal` feature
+#[cfg(feature = "decimal")]
+pub type Number = rust_decimal::Decimal;
+
+#[cfg(not(feature = "decimal"))]
+pub const ZERO: Number = 0.0;
+
+#[cfg(feature = "decimal")]
+pub const ZERO: Number = rust_decimal::Decimal::ZERO;
+
+/// decimal or scientific notation, surrounding whitespace is ignored, nan and inf are invalid
+#[cfg(not(feature = "decimal"))]
+pub fn parse_number(value: &str) -> Option<Number> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite())
+}
+
+/// decimal or scientific notation, surrounding whitespace is ignored
+#[cfg(feature = "decimal")]
+pub fn parse_number(value: &str) -> Option<Number> {
+    let value = value.trim();
+    match value.contains(['e', 'E']) {
+        true => Number::from_scientific(value).ok(),
+        false => value.parse::<Number>().ok(),
+    }
+}
+
+/// equal within a few ulps relative to the magnitude, so zero equals only zero
+/// and 59000.1 equals 59000.0 + 0.1
+#[cfg(not(feature = "decimal"))]
+pub fn numbers_equal(a: Number, b: Number) -> bool {
+    const ULPS: f64 = 4.0;
+    (a - b).abs() <= a.abs().max(b.abs()) * f64::EPSILON * ULPS
+}
+
+/// decimals are exact, 59000.1 equals 59000.0 + 0.1 as is
+#[cfg(feature = "decimal")]
+pub fn numbers_equal(a: Number, b: Number) -> bool {
+    a == b
+}
+
+/// total order, for f64 -0 is below 0
+#[cfg(not(feature = "decimal"))]
+pub fn compare_numbers(a: &Number, b: &Number) -> Ordering {
+    a.total_cmp(b)
+}
+
+/// total order
+#[cfg(feature = "decimal")]
+pub fn compare_numbers(a: &Number, b: &Number) -> Ordering {
+    a.cmp(b)
+}
+
+/// for values which don't need to be exact, e.g. ratios or metrics
+#[cfg(not(feature = "decimal"))]
+pub fn to_f64(number: Number) -> f64 {
+    number
+}
+
+/// for values which don't need to be exact, e.g. ratios or metrics
+#[cfg(feature = "decimal")]
+pub fn to_f64(number: Number) -> f64 {
+    use rust_decimal::prelude::ToPrimitive;
+    number.to_f64().unwrap_or(f64::NAN)
+}
+
+/// a + b, None on overflow, f64 would get infinite
+#[cfg(not(feature = "decimal"))]
+pub fn checked_add(a: Number, b: Number) -> Option<Number> {
+    Some(a + b).filter(|sum| sum.is_finite())
+}
+
+/// a + b, None on overflow, decimals would panic
+#[cfg(feature = "decimal")]
+pub fn checked_add(a: Number, b: Number) -> Option<Number> {
+    a.checked_add(b)
+}
+
+/// a - b, None on overflow, f64 would get infinite
+#[cfg(not(feature = "decimal"))]
+pub fn checked_sub(a: Number, b: Number) -> Option<Number> {
+    Some(a - b).filter(|difference| difference.is_finite())
+}
+
+/// a - b, None on overflow, decimals would panic
+#[cfg(feature = "decimal")]
+pub fn checked_sub(a: Number, b: Number) -> Option<Number> {
+    a.checked_sub(b)
+}
+
+/// a * b, None on overflow, f64 would get infinite
+#[cfg(not(feature = "decimal"))]
+pub fn checked_mul(a: Number, b: Number) -> Option<Number> {
+    Some(a * b).filter(|product| product.is_finite())
+}
+
+/// a * b, None on overflow, decimals would panic
+#[cfg(feature = "decimal")]
+pub fn checked_mul(a: Number, b: Number) -> Option<Number> {
+    a.checked_mul(b)
+}
+
+/// a / b, None on overflow or division by zero
+#[cfg(not(feature = "decimal"))]
+pub fn checked_div(a: Number, b: Number) -> Option<Number> {
+    Some(a / b).filter(|quotient| quotient.is_finite())
+}
+
+/// a / b, None on overflow or division by zero,
+/// trailing zeros of the quotient are dropped, 1 / 2 is 0.5 and not 0.50
+#[cfg(feature = "decimal")]
+pub fn checked_div(a: Number, b: Number) -> Option<Number> {
+    a.checked_div(b).map(|quotient| quotient.normalize())
+}
+
+/// sum of the numbers, None on overflow
+pub fn checked_sum(numbers: impl IntoIterator<Item = Number>) -> Option<Number> {
+    numbers.into_iter().try_fold(ZERO, checked_add)
+}
+
+/// number of a test literal, the same for f64 and decimals, e.g. `number("10.5")`
+#[cfg(test)]
+pub fn number(value: &str) -> Number {
+    parse_number(value).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn numbers_equal_test() {
+        // large prices, computed and parsed values are equal
+        assert!(numbers_equal(
+            number("59000.0") + number("0.1"),
+            number("59000.1")
+        ));
+        assert!(numbers_equal(number("59000.10"), number("59000.1")));
+        // the closest prices on the exchange aren't
+        assert!(!numbers_equal(number("59000.0"), number("59000.01")));
+        assert!(!numbers_equal(number("59000.1"), number("59000.2")));
+
+        // zero is only equal to zero
+        assert!(numbers_equal(ZERO, number("0.00000000")));
+        assert!(!numbers_equal(number("0.00000001"), ZERO));
+    }
+
+    #[test]
+    fn checked_arithmetic_test() {
+        #[cfg(not(feature = "decimal"))]
+        let max = f64::MAX;
+        #[cfg(feature = "decimal")]
+        let max = Number::MAX;
+        assert_eq!(checked_add(number("1"), number("2")), Some(number("3")));
+        assert_eq!(checked_add(max, max), None);
+        assert_eq!(checked_sub(-max, max), None);
+        assert_eq!(checked_mul(max, max), None);
+        assert_eq!(checked_div(number("3"), number("2")), Some(number("1.5")));
+        assert_eq!(checked_div(number("1"), ZERO), None);
+        assert_eq!(checked_sum([number("1"), number("2")]), Some(number("3")));
+        assert_eq!(checked_sum([max, max]), None);
+        assert_eq!(checked_sum([]), Some(ZERO));
+    }
+
+    #[cfg(feature = "decimal")]
+    #[test]
+    fn decimal_arithmetic_test() {
+        // f64 gets 0.30000000000000004
+        assert_eq!(number("0.1") + number("0.2"), number("0.3"));
+        assert_eq!(number("59000.0") + number("0.1"), number("59000.1"));
+        assert_eq!(
+            (number("0.00000003") * number("3")).to_string(),
+            "0.00000009"
+        );
+        // trailing zeros are kept as scale, still equal
+        assert_eq!(number("100.00000000"), number("100"));
+        assert_eq!(number("100.00000000").to_string(), "100.00000000");
+        assert_eq!(
+            checked_div(number("1"), number("2")).unwrap().to_string(),
+            "0.5"
+        );
+    }
+}
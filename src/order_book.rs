@@ -1,410 +1,1740 @@
-use crate::messages::{BookDepthUpdate, FullBook, LevelApi};
+use crate::exchange_info::decimals;
+use crate::messages::{
+    AggTrade, BookDepthUpdate, BookTicker, FullBook, KlineUpdate, LevelApi, MarkPriceUpdate,
+    PartialDepthUpdate, Ticker24hr,
+};
+use crate::number::{
+    checked_add, checked_div, checked_mul, checked_sub, checked_sum, compare_numbers,
+    numbers_equal, to_f64, Number, ZERO,
+};
+use serde::Serialize;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt::{Display, Formatter};
+use tracing::warn;
 
 /// OrderBook maintained during application runtime.
 /// My thoughts:
 /// This struct is Send, so it safe to use it cross-await call as we do (not simultaneously)
-#[derive(Default)]
-pub struct OrderBook {
+/// Sides are stored in a sorted Vec by default, see [BTreeOrderBook] for large books
+#[derive(Default, Clone)]
+pub struct OrderBook<S = Vec<Level>> {
     last_update_id: u64,
     levels: u32,
     symbol: String,
-    bid: Vec<Level>,
-    ask: Vec<Level>,
+    bid: S,
+    ask: S,
+    // set by a full book, cleared by the first applied depth update, whose pu isn't checked
     is_just_initialised: bool,
+    // defines depth updates sequence rules
+    market: Market,
+    last_trade: Option<Number>,
+    // top of book from bookTicker stream, updated independently of depth updates
+    ticker: Option<(Level, Level)>,
+    // latest markPrice stream update, futures only
+    mark: Option<Mark>,
+    // latest candle of the kline stream, open or just closed
+    candle: Option<Candle>,
+    // latest 24hr rolling window statistics of the ticker stream
+    day: Option<DayStats>,
+    // event time of the last applied depth update, epoch ms
+    event_time: Option<u64>,
+    // cached top of book, refreshed once per applied full book or depth update
+    top: Option<TopOfBook>,
+    // decimals of displayed levels
+    precision: Precision,
+    // reconciliations which found the book different from the full book
+    drift_count: u64,
+    // exponential moving average of mid, updated on applied depth updates
+    mid_ema: Option<f64>,
+    ema_alpha: f64,
+    // aggTrades within the window before the latest trade or depth update, oldest first
+    window_trades: VecDeque<WindowTrade>,
+    trade_window_ms: u64,
+    // taker bought minus sold quantity since the start or the last reset, None on overflow
+    cumulative_delta: Option<Number>,
+    // latest aggTrades for the trade tape, oldest first, none are kept by default
+    tape: VecDeque<AggTrade>,
+    tape_size: usize,
 }
 
-/// My thoughts:
-/// in real life scenario better to use tick size (u8), and qty (as long), so 5.0009 = (4, 50009) = 50009 * 10 ^ -4
-/// but for this app to ease development f64 used
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub struct Level {
-    pub quantity: f64,
-    pub price: f64,
+/// Configuration of a new book, unset options keep defaults of [OrderBook::new]
+///
+/// ```
+/// use binance_watcher::order_book::{BTreeOrderBook, OrderBook};
+///
+/// let book = OrderBook::builder()
+///     .symbol("btcusdt")
+///     .levels(5)
+///     .tick_size("0.10")
+///     .trade_tape_size(10)
+///     .build();
+/// assert_eq!(book.get_symbol(), "btcusdt");
+/// assert_eq!(book.get_levels(), 5);
+/// assert_eq!(book.get_precision().price_decimals, Some(1));
+///
+/// let large: BTreeOrderBook = OrderBook::builder().levels(1000).build_with_side();
+/// assert_eq!(large.get_levels(), 1000);
+/// ```
+#[derive(Debug, Clone)]
+pub struct OrderBookBuilder {
+    symbol: String,
+    levels: u32,
+    market: Market,
+    precision: Precision,
+    ema_alpha: f64,
+    trade_window_secs: u64,
+    trade_tape_size: usize,
 }
 
-impl OrderBook {
-    pub fn new(levels: u32, symbol: String) -> Self {
+impl Default for OrderBookBuilder {
+    fn default() -> Self {
         Self {
-            levels,
-            symbol: symbol.clone(),
-            ..Default::default()
+            symbol: String::new(),
+            levels: DEFAULT_LEVELS,
+            market: Market::default(),
+            precision: Precision::default(),
+            ema_alpha: DEFAULT_EMA_ALPHA,
+            trade_window_secs: DEFAULT_TRADE_WINDOW_SECS,
+            trade_tape_size: 0,
         }
     }
+}
 
-    pub fn get_mid(&self) -> Option<f64> {
-        let bid = self.get_best_bid();
-        match bid {
-            Ok(bid_val) => {
-                let ask = self.get_best_ask();
-                match ask {
-                    Ok(ask_val) => Some((ask_val.price - bid_val.price) / 2.0 + bid_val.price),
-                    Err(_) => None,
-                }
-            }
-            Err(_) => None,
-        }
+impl OrderBookBuilder {
+    pub fn symbol(mut self, symbol: impl Into<String>) -> Self {
+        self.symbol = symbol.into();
+        self
     }
 
-    pub fn get_spread(&self) -> Option<f64> {
-        let bid = self.get_best_bid();
-        match bid {
-            Ok(bid_val) => {
-                let ask = self.get_best_ask();
-                match ask {
-                    Ok(ask_val) => Some(ask_val.price - bid_val.price),
-                    Err(_) => None,
-                }
-            }
-            Err(_) => None,
-        }
+    /// levels the book is trimmed to
+    pub fn levels(mut self, levels: u32) -> Self {
+        self.levels = levels;
+        self
     }
 
-    pub fn get_best_bid(&self) -> Result<Level, String> {
-        let level_option: Option<&Level> = self.bid.get(0);
-        match level_option {
-            None => Err("empty bid".to_string()),
-            Some(level) => Ok(level.clone()),
-        }
+    pub fn market(mut self, market: Market) -> Self {
+        self.market = market;
+        self
     }
 
-    pub fn get_best_ask(&self) -> Result<Level, String> {
-        let level_option: Option<&Level> = self.ask.get(0);
-        match level_option {
-            None => Err("empty ask".to_string()),
-            Some(level) => Ok(level.clone()),
-        }
+    /// decimals of displayed prices and quantities
+    pub fn precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
     }
 
-    pub fn apply_full_book_from_http_api(&mut self, book: &FullBook) {
-        self.last_update_id = book.last_update_id;
-        self.is_just_initialised = true;
-
-        // bid
-        self.bid.clear();
-        for level in &book.bids {
-            self.bid.push(level_api_to_level(&level));
-        }
+    /// displayed prices get decimals of the tick size, e.g. 2 for "0.01000000"
+    pub fn tick_size(mut self, tick_size: &str) -> Self {
+        self.precision.price_decimals = Some(decimals(tick_size));
+        self
+    }
 
-        // ask
-        self.ask.clear();
-        for level in &book.asks {
-            self.ask.push(level_api_to_level(&level));
-        }
+    /// displayed quantities get decimals of the step size, e.g. 3 for "0.00100000"
+    pub fn step_size(mut self, step_size: &str) -> Self {
+        self.precision.quantity_decimals = Some(decimals(step_size));
+        self
+    }
 
-        self.trim()
+    /// weight of the latest mid in its moving average, in (0, 1]
+    pub fn ema_alpha(mut self, ema_alpha: f64) -> Self {
+        self.ema_alpha = ema_alpha;
+        self
     }
 
-    // tbd: Result<(), ()>? Better looking bool?
-    pub fn apply_depth_book_update_from_websocket(
-        &mut self,
-        book: &BookDepthUpdate,
-    ) -> Result<(), ()> {
-        // for already applied updates from ws
-        if self.is_update_applied(book) {
-            return Ok(());
-        }
-        // if book already too old, we need ask http api again
-        if !self.is_eligible_for_update(book) {
-            return Err(());
-        }
-        // check that previous final id was last_id
-        if !self.is_just_initialised && self.last_update_id != book.pu {
-            return Err(());
-        }
+    /// trades of the last seconds are averaged in VWAP
+    pub fn trade_window_secs(mut self, secs: u64) -> Self {
+        self.trade_window_secs = secs;
+        self
+    }
 
-        // update
-        for level in &book.b {
-            self.apply_bid(&level);
-        }
-        for level in &book.a {
-            self.apply_ask(&level);
-        }
-        self.last_update_id = book.u;
-        self.trim();
+    /// latest trades kept for the trade tape, none by default
+    pub fn trade_tape_size(mut self, size: usize) -> Self {
+        self.trade_tape_size = size;
+        self
+    }
 
-        Ok(())
+    pub fn build(self) -> OrderBook {
+        self.build_with_side()
     }
 
-    // utils
-    fn is_update_applied(&self, book_update: &BookDepthUpdate) -> bool {
-        self.last_update_id > book_update.u
+    /// book with the chosen side storage, e.g. [BTreeOrderBook] for large books
+    pub fn build_with_side<S: BookSide>(self) -> OrderBook<S> {
+        OrderBook::with_side(self.levels, self.symbol)
+            .with_market(self.market)
+            .with_precision(self.precision)
+            .with_ema_alpha(self.ema_alpha)
+            .with_trade_window_secs(self.trade_window_secs)
+            .with_trade_tape_size(self.trade_tape_size)
     }
+}
 
-    fn is_eligible_for_update(&self, book_update: &BookDepthUpdate) -> bool {
-        let last_update_id = self.last_update_id;
-        book_update.U <= last_update_id && last_update_id <= book_update.u
+/// Binance markets, they differ in depth updates sequence rules [documentation]
+///
+/// [documentation]: [https://developers.binance.com/docs/binance-spot-api-docs/web-socket-streams#how-to-manage-a-local-order-book-correctly]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Market {
+    /// usd-m futures, updates are chained by pu
+    #[default]
+    Futures,
+    /// spot, updates are chained by U == previous u + 1
+    Spot,
+}
+
+/// Mark price and funding of futures
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mark {
+    pub price: f64,
+    pub funding_rate: f64,
+    /// epoch ms
+    pub next_funding_time: u64,
+}
+
+/// Decimals of displayed prices and quantities, e.g. from exchangeInfo,
+/// the shortest representation is used if not set
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Precision {
+    pub price_decimals: Option<u32>,
+    pub quantity_decimals: Option<u32>,
+}
+
+impl Precision {
+    fn format_price(&self, price: Number) -> String {
+        format_decimals(price, self.price_decimals)
     }
 
-    fn apply_bid(&mut self, api_level: &LevelApi) {
-        Self::do_apply_to_level(&mut self.bid, api_level, false)
+    fn format_quantity(&self, quantity: Number) -> String {
+        format_decimals(quantity, self.quantity_decimals)
     }
-    fn apply_ask(&mut self, api_level: &LevelApi) {
-        Self::do_apply_to_level(&mut self.ask, api_level, true)
+}
+
+fn format_decimals(value: Number, decimals: Option<u32>) -> String {
+    match decimals {
+        Some(decimals) => format!("{:.*}", decimals as usize, value),
+        None => value.to_string(),
     }
+}
 
-    fn do_apply_to_level(levels: &mut Vec<Level>, api_level: &LevelApi, ascending: bool) {
-        let level_update = level_api_to_level(api_level);
-        let result = Self::look_for_level(level_update.price, levels, ascending);
-        match result {
-            Ok(index) => {
-                if Self::floats_equal(level_update.price, 0.0) {
-                    // TBD: could be done much more efficiently
-                    levels.remove(index);
-                } else {
-                    levels[index] = Level {
-                        price: level_update.price,
-                        quantity: level_update.quantity,
-                    }
-                }
-            }
-            Err(index) => {
-                if Self::floats_equal(level_update.price, 0.0) {
-                    // ignore
-                } else {
-                    levels.insert(
-                        index,
-                        Level {
-                            price: level_update.price,
-                            quantity: level_update.quantity,
-                        },
-                    );
-                }
-            }
+/// 24hr rolling window statistics
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DayStats {
+    pub last_price: f64,
+    pub price_change_percent: f64,
+    pub high: f64,
+    pub low: f64,
+    pub volume: f64,
+}
+
+/// Kline stream candle
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Candle {
+    /// epoch ms
+    pub open_time: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+    pub closed: bool,
+}
+
+/// Values of the best levels cached not to walk the sides on every read
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct TopOfBook {
+    mid: Number,
+    spread: Number,
+    // None if the weighted prices overflow
+    microprice: Option<Number>,
+}
+
+/// Trade kept for the rolling VWAP
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct WindowTrade {
+    price: Number,
+    quantity: Number,
+    time: u64,
+    // the taker sold to the buyer, a negative delta
+    is_buyer_maker: bool,
+}
+
+impl WindowTrade {
+    /// quantity bought by the taker, negative if sold
+    fn delta(&self) -> Number {
+        match self.is_buyer_maker {
+            true => -self.quantity,
+            false => self.quantity,
         }
     }
+}
 
-    fn look_for_level(price: f64, levels: &Vec<Level>, ascending: bool) -> Result<usize, usize> {
-        // TBD: in reality unnecessary for small levels limits <=100
-        levels.binary_search_by(|level| {
-            if ascending {
-                level.price.total_cmp(&price)
-            } else {
-                price.total_cmp(&level.price)
-            }
-        })
+/// My thoughts:
+/// in real life scenario better to use tick size (u8), and qty (as long), so 5.0009 = (4, 50009) = 50009 * 10 ^ -4
+/// but for this app to ease development f64 used, or exact decimals with the `decimal` feature
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct Level {
+    pub quantity: Number,
+    pub price: Number,
+}
+
+/// Levels are ordered by price, then by quantity to agree with equality,
+/// so sorted levels go from the best ask, reversed from the best bid
+impl Ord for Level {
+    fn cmp(&self, other: &Self) -> Ordering {
+        PriceKey(self.price)
+            .cmp(&PriceKey(other.price))
+            .then_with(|| compare_numbers(&self.quantity, &other.quantity))
+    }
+}
+
+impl PartialOrd for Level {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
+}
 
-    fn floats_equal(a: f64, b: f64) -> bool {
-        (a - b).abs() < f64::EPSILON
+impl PartialEq for Level {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
     }
+}
 
-    fn trim(&mut self) {
-        self.bid.truncate(self.levels as usize);
-        self.ask.truncate(self.levels as usize)
+impl Eq for Level {}
+
+/// Side of the book
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+/// Storage of one side of the book, bids are ordered by descending prices, asks by ascending
+pub trait BookSide: Default {
+    /// levels from the best one
+    fn levels(&self, ascending: bool) -> Box<dyn Iterator<Item = Level> + '_>;
+    /// replaces the level of the same price or inserts a new one
+    fn set_level(&mut self, level: Level, ascending: bool);
+    fn remove_level(&mut self, price: Number, ascending: bool);
+    /// keeps only the best levels
+    fn truncate_levels(&mut self, levels: usize, ascending: bool);
+    fn clear_levels(&mut self);
+}
+
+/// Sorted Vec side, O(n) per level change, but compact and fast for small books
+impl BookSide for Vec<Level> {
+    fn levels(&self, _ascending: bool) -> Box<dyn Iterator<Item = Level> + '_> {
+        Box::new(self.iter().copied())
     }
 
-    fn write_level(
-        &self,
-        f: &mut Formatter<'_>,
-        level_bid: Option<&Level>,
-        level_ask: Option<&Level>,
-    ) {
-        let empty_level = "|         ---          |";
-        match level_bid {
-            Some(level) => {
-                write!(f, "|{:10}|{:10}|", level.quantity, level.price).unwrap();
-            }
-            None => {
-                write!(f, "{}", empty_level).unwrap();
-            }
+    fn set_level(&mut self, level: Level, ascending: bool) {
+        match look_for_level(level.price, self, ascending) {
+            Ok(index) => self[index] = level,
+            Err(index) => self.insert(index, level),
         }
-        write!(f, "     ").unwrap();
-        match level_ask {
-            Some(level) => {
-                write!(f, "|{:10}|{:10}|\n", level.quantity, level.price).unwrap();
-            }
-            None => {
-                write!(f, "{}\n", empty_level).unwrap();
-            }
+    }
+
+    fn remove_level(&mut self, price: Number, ascending: bool) {
+        if let Ok(index) = look_for_level(price, self, ascending) {
+            self.remove(index);
         }
     }
+
+    fn truncate_levels(&mut self, levels: usize, _ascending: bool) {
+        self.truncate(levels)
+    }
+
+    fn clear_levels(&mut self) {
+        self.clear()
+    }
 }
 
-impl Display for OrderBook {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        writeln!(
-            f,
-            "====         ORDER BOOK : {}              ====",
-            self.symbol
-        )?;
-        writeln!(
-            f,
-            "|                mid: {:10}                  |",
-            self.get_mid().unwrap_or(f64::NAN)
-        )?;
-        writeln!(f, "|         bid         |     |         ask         |")?;
-        writeln!(f, "|   qty    |   price  |     |   qty    |  price   |")?;
-        writeln!(f, "---------------------------------------------------")?;
-        for index in 0..self.levels as usize {
-            let reference = &self.bid;
-            let bid_level: Option<&Level> = reference.get(index);
-            let reference = &self.ask;
-            let ask_level: Option<&Level> = reference.get(index);
-            self.write_level(f, bid_level, ask_level);
-        }
-        writeln!(f, "====            END ORDER BOOK                 ====")?;
-        Ok(())
+/// Price as a key of the ordered map
+#[derive(Debug, Copy, Clone)]
+pub struct PriceKey(pub Number);
+
+impl PartialEq for PriceKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
     }
 }
 
-fn level_api_to_level(api_level: &LevelApi) -> Level {
-    Level {
-        quantity: api_level.quantity.parse::<f64>().unwrap(),
-        price: api_level.price.parse::<f64>().unwrap(),
+impl Eq for PriceKey {}
+
+impl PartialOrd for PriceKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-mod test {
-    use super::*;
+impl Ord for PriceKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_numbers(&self.0, &other.0)
+    }
+}
 
-    #[test]
-    fn get_best_bid_test() {
-        let mut book = OrderBook::default();
+/// Price to quantity map side, O(log n) per level change
+impl BookSide for BTreeMap<PriceKey, Number> {
+    fn levels(&self, ascending: bool) -> Box<dyn Iterator<Item = Level> + '_> {
+        let to_level = |(price, quantity): (&PriceKey, &Number)| Level {
+            quantity: *quantity,
+            price: price.0,
+        };
+        if ascending {
+            Box::new(self.iter().map(to_level))
+        } else {
+            Box::new(self.iter().rev().map(to_level))
+        }
+    }
 
-        book.bid.push(Level {
-            quantity: 1.0,
-            price: 20.0,
-        });
-        book.bid.push(Level {
-            quantity: 1.0,
-            price: 19.0,
-        });
-        book.bid.push(Level {
-            quantity: 1.0,
-            price: 18.0,
-        });
-        book.bid.push(Level {
-            quantity: 1.0,
-            price: 17.0,
-        });
+    fn set_level(&mut self, level: Level, _ascending: bool) {
+        self.insert(PriceKey(level.price), level.quantity);
+    }
 
-        book.ask.push(Level {
-            quantity: 1.0,
-            price: 21.0,
-        });
-        book.ask.push(Level {
-            quantity: 1.0,
-            price: 22.0,
-        });
-        book.ask.push(Level {
-            quantity: 1.0,
-            price: 23.0,
-        });
-        book.ask.push(Level {
-            quantity: 1.0,
-            price: 24.0,
-        });
+    fn remove_level(&mut self, price: Number, _ascending: bool) {
+        self.remove(&PriceKey(price));
+    }
 
-        assert_eq!(
-            book.get_best_bid().unwrap(),
-            Level {
-                quantity: 1.0,
-                price: 20.0
-            }
-        );
-        assert_eq!(
-            book.get_best_ask().unwrap(),
-            Level {
-                quantity: 1.0,
-                price: 21.0
+    fn truncate_levels(&mut self, levels: usize, ascending: bool) {
+        while self.len() > levels {
+            // the worst level is the last one for asks and the first one for bids
+            if ascending {
+                self.pop_last();
+            } else {
+                self.pop_first();
             }
-        );
+        }
     }
 
-    #[test]
-    fn apply_http_full_book_apply_test() {
-        let mut book = OrderBook::default();
-        book.levels = 3;
+    fn clear_levels(&mut self) {
+        self.clear()
+    }
+}
 
-        let http_book = FullBook {
-            last_update_id: 100500,
-            bids: vec![
-                LevelApi {
-                    quantity: "1".to_string(),
-                    price: "5".to_string(),
-                },
-                LevelApi {
-                    quantity: "1".to_string(),
-                    price: "4".to_string(),
-                },
-                LevelApi {
-                    quantity: "1".to_string(),
-                    price: "3".to_string(),
-                },
-            ],
-            asks: vec![
-                LevelApi {
-                    quantity: "1".to_string(),
-                    price: "6".to_string(),
-                },
-                LevelApi {
-                    quantity: "1".to_string(),
-                    price: "7".to_string(),
-                },
-                LevelApi {
-                    quantity: "1".to_string(),
-                    price: "8".to_string(),
-                },
-            ],
-        };
+/// Order book with O(log n) level changes, for books with many levels
+pub type BTreeOrderBook = OrderBook<BTreeMap<PriceKey, Number>>;
 
-        book.apply_full_book_from_http_api(&http_book);
+/// Serializable view of the book, used for json output,
+/// derived values are taken together with levels, so they are consistent across tasks
+#[derive(Clone, Serialize)]
+pub struct BookSnapshot {
+    pub symbol: String,
+    pub last_update_id: u64,
+    pub mid: Option<Number>,
+    pub spread: Option<Number>,
+    pub best_bid: Option<Level>,
+    pub best_ask: Option<Level>,
+    pub last_trade: Option<Number>,
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
 
-        assert_eq!(
-            book.get_best_bid().unwrap(),
-            Level {
-                quantity: 1.0,
-                price: 5.0
-            }
-        );
-        assert_eq!(
-            book.get_best_ask().unwrap(),
-            Level {
-                quantity: 1.0,
-                price: 6.0
-            }
-        );
-        assert_eq!(book.bid.len(), 3);
-        assert_eq!(book.ask.len(), 3);
+/// Applied depth update, printed as a json line with --ndjson
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UpdateRecord {
+    pub symbol: String,
+    pub last_update_id: u64,
+    pub mid: Option<Number>,
+    pub spread: Option<Number>,
+    pub best_bid: Option<Level>,
+    pub best_ask: Option<Level>,
+    // event time of the update, epoch ms
+    pub event_time: Option<u64>,
+}
 
-        // change levels param
+/// Top of the book after an applied update, broadcast to the watcher subscribers
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BookEvent {
+    pub symbol: String,
+    pub mid: Option<Number>,
+    pub spread: Option<Number>,
+    pub best_bid: Option<Level>,
+    pub best_ask: Option<Level>,
+    // epoch ms of the update
+    pub ts: u128,
+}
 
-        book.levels = 2;
-        book.apply_full_book_from_http_api(&http_book);
+/// Level which differs between the book and the full book, quantity is None if there is no level
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelMismatch {
+    pub is_bid: bool,
+    pub price: Number,
+    pub local_quantity: Option<Number>,
+    pub remote_quantity: Option<Number>,
+}
 
-        assert_eq!(
-            book.get_best_bid().unwrap(),
-            Level {
-                quantity: 1.0,
-                price: 5.0
-            }
-        );
-        assert_eq!(
-            book.get_best_ask().unwrap(),
-            Level {
-                quantity: 1.0,
-                price: 6.0
-            }
-        );
-        assert_eq!(book.bid.len(), 2);
-        assert_eq!(book.ask.len(), 2);
+impl Display for LevelMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let optional = |value: Option<Number>| value.map(|v| v.to_string()).unwrap_or("-".into());
+        write!(
+            f,
+            "{} {}: local {} remote {}",
+            if self.is_bid { "bid" } else { "ask" },
+            self.price,
+            optional(self.local_quantity),
+            optional(self.remote_quantity)
+        )
     }
+}
 
-    #[test]
-    fn apply_websocket_update_book_apply_test() {
-        let mut book = OrderBook::default();
-        book.levels = 3;
-        book.is_just_initialised = true;
+/// Level change from a book to another one, see [OrderBook::diff]
+#[derive(Debug, Clone, PartialEq)]
+pub enum LevelChange {
+    Added(Side, Level),
+    Removed(Side, Level),
+    Modified {
+        side: Side,
+        price: Number,
+        old_quantity: Number,
+        new_quantity: Number,
+    },
+}
 
-        let ws_book = BookDepthUpdate {
-            e: "".to_string(),
-            E: 0,
+/// Successful outcome of a depth update
+#[derive(Debug, PartialEq)]
+pub enum ApplyResult {
+    Applied,
+    // update finished before the book's last update id, nothing to do
+    AlreadyApplied,
+}
+
+/// Update which can't be applied, for depth updates the book has to be requested via http api again
+#[derive(Debug, PartialEq)]
+pub enum ApplyError {
+    // first update after the full book doesn't contain its last update id
+    NeedsSnapshot {
+        last_update_id: u64,
+        first: u64,
+        last: u64,
+    },
+    // pu of the update isn't the last applied update id
+    SequenceGap {
+        expected: u64,
+        got: u64,
+    },
+    // price or quantity isn't a number, nothing is applied
+    InvalidNumber(String),
+}
+
+impl Display for ApplyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApplyError::NeedsSnapshot {
+                last_update_id,
+                first,
+                last,
+            } => write!(
+                f,
+                "full book {} is out of first update range [{}, {}]",
+                last_update_id, first, last
+            ),
+            ApplyError::SequenceGap { expected, got } => {
+                write!(f, "sequence gap, expected {} got {}", expected, got)
+            }
+            ApplyError::InvalidNumber(value) => write!(f, "invalid number '{}'", value),
+        }
+    }
+}
+
+impl OrderBook {
+    pub fn new(levels: u32, symbol: String) -> Self {
+        Self::with_side(levels, symbol)
+    }
+
+    /// configures a book step by step, see [OrderBookBuilder]
+    pub fn builder() -> OrderBookBuilder {
+        OrderBookBuilder::default()
+    }
+
+    pub fn csv_header() -> &'static str {
+        "symbol,last_update_id,mid,last_trade,bid_price,bid_qty,ask_price,ask_qty"
+    }
+}
+
+impl<S: BookSide> OrderBook<S> {
+    /// book with the chosen side storage, e.g. `BTreeOrderBook::with_side(1000, symbol)`
+    pub fn with_side(levels: u32, symbol: String) -> Self {
+        Self {
+            levels,
+            symbol,
+            ema_alpha: DEFAULT_EMA_ALPHA,
+            trade_window_ms: DEFAULT_TRADE_WINDOW_SECS * 1000,
+            cumulative_delta: Some(ZERO),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_market(mut self, market: Market) -> Self {
+        self.market = market;
+        self
+    }
+
+    /// decimals of displayed prices and quantities
+    pub fn with_precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    pub fn get_precision(&self) -> Precision {
+        self.precision
+    }
+
+    /// trades of the last seconds are averaged in VWAP
+    pub fn with_trade_window_secs(mut self, secs: u64) -> Self {
+        self.trade_window_ms = secs * 1000;
+        self
+    }
+
+    /// latest trades kept for the trade tape, displayed below the book if any are kept
+    pub fn with_trade_tape_size(mut self, size: usize) -> Self {
+        self.tape_size = size;
+        self
+    }
+
+    /// latest trades of the tape, oldest first
+    pub fn recent_trades(&self) -> Vec<AggTrade> {
+        self.tape.iter().cloned().collect()
+    }
+
+    /// weight of the latest mid in its moving average, in (0, 1]
+    pub fn with_ema_alpha(mut self, ema_alpha: f64) -> Self {
+        self.ema_alpha = ema_alpha;
+        self
+    }
+
+    /// levels the book is trimmed to
+    pub fn get_levels(&self) -> u32 {
+        self.levels
+    }
+
+    pub fn get_symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    pub fn get_last_update_id(&self) -> u64 {
+        self.last_update_id
+    }
+
+    /// None if a side is empty, or the prices are too far apart to be computed with
+    pub fn get_mid(&self) -> Option<Number> {
+        self.top.map(|top| top.mid)
+    }
+
+    /// None if a side is empty, or the prices are too far apart to be computed with
+    pub fn get_spread(&self) -> Option<Number> {
+        self.top.map(|top| top.spread)
+    }
+
+    /// mid weighted by the opposite best quantities, leans towards the thinner side,
+    /// None if a side is empty or on overflow
+    pub fn get_microprice(&self) -> Option<Number> {
+        self.top.and_then(|top| top.microprice)
+    }
+
+    /// top of book from the best levels, none if a side is empty
+    fn compute_top(&self) -> Option<TopOfBook> {
+        let bid = self.get_best_bid().ok()?;
+        let ask = self.get_best_ask().ok()?;
+        let spread = checked_sub(ask.price, bid.price)?;
+        let mid = checked_add(checked_div(spread, Number::from(2))?, bid.price)?;
+        let microprice =
+            checked_add(bid.quantity, ask.quantity).and_then(|quantity| match quantity > ZERO {
+                true => checked_div(
+                    checked_add(
+                        checked_mul(bid.price, ask.quantity)?,
+                        checked_mul(ask.price, bid.quantity)?,
+                    )?,
+                    quantity,
+                ),
+                false => Some(mid),
+            });
+        Some(TopOfBook {
+            mid,
+            spread,
+            microprice,
+        })
+    }
+
+    /// spread relative to mid in basis points, comparable across symbols
+    pub fn get_spread_bps(&self) -> Option<f64> {
+        let mid = to_f64(self.get_mid()?);
+        if mid == 0.0 {
+            return None;
+        }
+        Some(to_f64(self.get_spread()?) / mid * 10_000.0)
+    }
+
+    pub fn get_mid_ema(&self) -> Option<f64> {
+        self.mid_ema
+    }
+
+    fn update_mid_ema(&mut self) {
+        if let Some(mid) = self.get_mid().map(to_f64) {
+            self.mid_ema = Some(match self.mid_ema {
+                Some(ema) => self.ema_alpha * mid + (1.0 - self.ema_alpha) * ema,
+                None => mid,
+            });
+        }
+    }
+
+    /// levels changes from this book to the other one, bids then asks, from the best level
+    pub fn diff(&self, other: &OrderBook<S>) -> Vec<LevelChange> {
+        let mut changes = side_changes(
+            Side::Bid,
+            self.bid.levels(false),
+            other.bid.levels(false),
+            false,
+        );
+        changes.extend(side_changes(
+            Side::Ask,
+            self.ask.levels(true),
+            other.ask.levels(true),
+            true,
+        ));
+        changes
+    }
+
+    pub fn get_drift_count(&self) -> u64 {
+        self.drift_count
+    }
+
+    pub fn record_drift(&mut self) {
+        self.drift_count += 1;
+    }
+
+    /// levels of the book different from the full book, levels deeper than any side
+    /// keeps can't be compared and skipped prices are ignored
+    pub fn mismatches(
+        &self,
+        full_book: &FullBook,
+        skipped_prices: &[Number],
+    ) -> Result<Vec<LevelMismatch>, ApplyError> {
+        let mut remote: OrderBook = OrderBook::new(self.levels, self.symbol.clone());
+        remote.apply_full_book_from_http_api(full_book)?;
+        let local = self.snapshot();
+        let remote = remote.snapshot();
+        let levels = self.levels as usize;
+
+        let mut mismatches = side_mismatches(&local.bids, &remote.bids, levels, false);
+        mismatches.extend(side_mismatches(&local.asks, &remote.asks, levels, true));
+        mismatches.retain(|mismatch| {
+            !skipped_prices
+                .iter()
+                .any(|price| numbers_equal(*price, mismatch.price))
+        });
+        Ok(mismatches)
+    }
+
+    pub fn get_last_trade(&self) -> Option<Number> {
+        self.last_trade
+    }
+
+    pub fn apply_agg_trade(&mut self, trade: &AggTrade) -> Result<(), ApplyError> {
+        let price = parse_number(&trade.p)?;
+        let quantity = parse_number(&trade.q)?;
+        self.last_trade = Some(price);
+
+        let window_trade = WindowTrade {
+            price,
+            quantity,
+            time: trade.T,
+            is_buyer_maker: trade.m,
+        };
+        self.cumulative_delta = self
+            .cumulative_delta
+            .and_then(|delta| checked_add(delta, window_trade.delta()));
+        self.window_trades.push_back(window_trade);
+        self.evict_window_trades(trade.T);
+
+        if self.tape_size > 0 {
+            if self.tape.len() == self.tape_size {
+                self.tape.pop_front();
+            }
+            self.tape.push_back(trade.clone());
+        }
+        Ok(())
+    }
+
+    /// volume weighted average price of the trades within the window,
+    /// None without volume or if the sums overflow
+    pub fn get_trade_vwap(&self) -> Option<Number> {
+        let (notional, volume) =
+            self.window_trades
+                .iter()
+                .try_fold((ZERO, ZERO), |(notional, volume), trade| {
+                    Some((
+                        checked_add(notional, checked_mul(trade.price, trade.quantity)?)?,
+                        checked_add(volume, trade.quantity)?,
+                    ))
+                })?;
+        match volume > ZERO {
+            true => checked_div(notional, volume),
+            false => None,
+        }
+    }
+
+    /// quantity bought minus sold by takers since the start or the last reset,
+    /// trades of buyer makers are sells, None if it overflowed since
+    pub fn cumulative_delta(&self) -> Option<Number> {
+        self.cumulative_delta
+    }
+
+    /// starts the cumulative delta over, e.g. on a new session
+    pub fn reset_cumulative_delta(&mut self) {
+        self.cumulative_delta = Some(ZERO);
+    }
+
+    /// quantity bought minus sold by takers within the trades window, see [Self::get_trade_vwap],
+    /// None on overflow
+    pub fn window_delta(&self) -> Option<Number> {
+        checked_sum(self.window_trades.iter().map(WindowTrade::delta))
+    }
+
+    /// best bid from bookTicker stream, could be fresher than the book itself
+    pub fn get_ticker_best_bid(&self) -> Option<Level> {
+        self.ticker.map(|(bid, _)| bid)
+    }
+
+    /// best ask from bookTicker stream, could be fresher than the book itself
+    pub fn get_ticker_best_ask(&self) -> Option<Level> {
+        self.ticker.map(|(_, ask)| ask)
+    }
+
+    pub fn apply_book_ticker(&mut self, ticker: &BookTicker) -> Result<(), ApplyError> {
+        let bid = Level {
+            quantity: parse_number(&ticker.B)?,
+            price: parse_number(&ticker.b)?,
+        };
+        let ask = Level {
+            quantity: parse_number(&ticker.A)?,
+            price: parse_number(&ticker.a)?,
+        };
+        self.ticker = Some((bid, ask));
+        Ok(())
+    }
+
+    pub fn get_mark(&self) -> Option<Mark> {
+        self.mark
+    }
+
+    pub fn apply_mark_price(&mut self, update: &MarkPriceUpdate) -> Result<(), ApplyError> {
+        self.mark = Some(Mark {
+            price: parse_float(&update.p)?,
+            funding_rate: parse_float(&update.r)?,
+            next_funding_time: update.T,
+        });
+        Ok(())
+    }
+
+    pub fn get_day_stats(&self) -> Option<DayStats> {
+        self.day
+    }
+
+    pub fn apply_ticker_24hr(&mut self, ticker: &Ticker24hr) -> Result<(), ApplyError> {
+        self.day = Some(DayStats {
+            last_price: parse_float(&ticker.c)?,
+            price_change_percent: parse_float(&ticker.P)?,
+            high: parse_float(&ticker.h)?,
+            low: parse_float(&ticker.l)?,
+            volume: parse_float(&ticker.v)?,
+        });
+        Ok(())
+    }
+
+    pub fn get_candle(&self) -> Option<Candle> {
+        self.candle
+    }
+
+    /// replaces the candle, updates of an older candle than the current one are ignored
+    pub fn apply_kline(&mut self, update: &KlineUpdate) -> Result<(), ApplyError> {
+        let kline = &update.k;
+        if self.candle.is_some_and(|candle| candle.open_time > kline.t) {
+            return Ok(());
+        }
+        self.candle = Some(Candle {
+            open_time: kline.t,
+            open: parse_float(&kline.o)?,
+            high: parse_float(&kline.h)?,
+            low: parse_float(&kline.l)?,
+            close: parse_float(&kline.c)?,
+            volume: parse_float(&kline.v)?,
+            closed: kline.x,
+        });
+        Ok(())
+    }
+
+    pub fn get_best_bid(&self) -> Result<Level, String> {
+        let level_option: Option<Level> = self.bid.levels(false).next();
+        match level_option {
+            None => Err("empty bid".to_string()),
+            Some(level) => Ok(level),
+        }
+    }
+
+    pub fn get_best_ask(&self) -> Result<Level, String> {
+        let level_option: Option<Level> = self.ask.levels(true).next();
+        match level_option {
+            None => Err("empty ask".to_string()),
+            Some(level) => Ok(level),
+        }
+    }
+
+    /// levels of the side from the best one
+    fn side_levels(&self, side: Side) -> Box<dyn Iterator<Item = Level> + '_> {
+        match side {
+            Side::Bid => self.bid.levels(false),
+            Side::Ask => self.ask.levels(true),
+        }
+    }
+
+    /// sum of quantities over all levels of the side, None on overflow
+    pub fn total_quantity(&self, side: Side) -> Option<Number> {
+        checked_sum(self.side_levels(side).map(|level| level.quantity))
+    }
+
+    /// sum of price * quantity over all levels of the side, None on overflow
+    pub fn total_notional(&self, side: Side) -> Option<Number> {
+        self.side_levels(side).try_fold(ZERO, |notional, level| {
+            checked_add(notional, checked_mul(level.price, level.quantity)?)
+        })
+    }
+
+    /// sum of quantities of the side levels within bps of mid, 0 without mid, None on overflow
+    pub fn liquidity_within_bps(&self, side: Side, bps: f64) -> Option<Number> {
+        let Some(mid) = self.get_mid() else {
+            return Some(ZERO);
+        };
+        let band = to_f64(mid).abs() * bps / 10_000.0;
+        // levels go away from mid, the first one outside the band ends it
+        checked_sum(
+            self.side_levels(side)
+                .take_while(|level| {
+                    checked_sub(level.price, mid)
+                        .is_some_and(|distance| to_f64(distance).abs() <= band)
+                })
+                .map(|level| level.quantity),
+        )
+    }
+
+    /// levels of the side with quantity above multiple times the side average, best first
+    pub fn large_levels(&self, side: Side, multiple: f64) -> Vec<Level> {
+        let count = self.side_levels(side).count();
+        if count == 0 {
+            return Vec::new();
+        }
+        // the threshold is a ratio of the average, so it doesn't need to be exact
+        let total: f64 = self
+            .side_levels(side)
+            .map(|level| to_f64(level.quantity))
+            .sum();
+        let threshold = multiple * total / count as f64;
+        self.side_levels(side)
+            .filter(|level| to_f64(level.quantity) > threshold)
+            .collect()
+    }
+
+    /// quantities of the side levels summed by price buckets of bucket size,
+    /// (bucket lower price, quantity) from the best bucket, empty for a non positive size
+    pub fn volume_profile(&self, side: Side, bucket_size: Number) -> Vec<(Number, Number)> {
+        let mut profile: Vec<(Number, Number)> = Vec::new();
+        if bucket_size <= ZERO {
+            return profile;
+        }
+        // levels are sorted, so levels of a bucket follow each other,
+        // levels overflowing the bucket price or quantity are skipped
+        for level in self.side_levels(side) {
+            let Some(bucket) = checked_div(level.price, bucket_size)
+                .and_then(|buckets| checked_mul(buckets.floor(), bucket_size))
+            else {
+                continue;
+            };
+            match profile.last_mut() {
+                Some((price, quantity)) if numbers_equal(*price, bucket) => {
+                    if let Some(sum) = checked_add(*quantity, level.quantity) {
+                        *quantity = sum
+                    }
+                }
+                _ => profile.push((bucket, level.quantity)),
+            }
+        }
+        profile
+    }
+
+    /// top n bids and asks from the best level, less if the book isn't that deep
+    pub fn get_depth(&self, n: usize) -> (Vec<Level>, Vec<Level>) {
+        (
+            self.bid.levels(false).take(n).collect(),
+            self.ask.levels(true).take(n).collect(),
+        )
+    }
+
+    /// replaces the book, it's kept as is if any level is invalid,
+    /// levels are sorted, binance is expected to send them sorted already
+    pub fn apply_full_book_from_http_api(&mut self, book: &FullBook) -> Result<(), ApplyError> {
+        let mut bids = levels_api_to_levels(&book.bids)?;
+        let mut asks = levels_api_to_levels(&book.asks)?;
+        if !sort_levels(&mut bids, false) || !sort_levels(&mut asks, true) {
+            warn!(
+                symbol = self.symbol.as_str(),
+                "Full book levels weren't sorted, sorted them"
+            );
+        }
+        self.last_update_id = book.last_update_id;
+        self.is_just_initialised = true;
+        self.replace_levels(bids, asks);
+        Ok(())
+    }
+
+    /// replaces the book with top levels of the partial depth stream, no sync is needed,
+    /// the book is kept as is if any level is invalid or the update is older
+    pub fn apply_partial_depth(
+        &mut self,
+        update: &PartialDepthUpdate,
+    ) -> Result<ApplyResult, ApplyError> {
+        if update.last_update_id < self.last_update_id {
+            return Ok(ApplyResult::AlreadyApplied);
+        }
+        let mut bids = levels_api_to_levels(&update.bids)?;
+        let mut asks = levels_api_to_levels(&update.asks)?;
+        sort_levels(&mut bids, false);
+        sort_levels(&mut asks, true);
+        self.last_update_id = update.last_update_id;
+        self.event_time = update.event_time.or(self.event_time);
+        self.is_just_initialised = false;
+        self.replace_levels(bids, asks);
+        self.update_mid_ema();
+        Ok(ApplyResult::Applied)
+    }
+
+    /// levels are expected to be sorted from the best one
+    fn replace_levels(&mut self, bids: Vec<Level>, asks: Vec<Level>) {
+        // bid
+        self.bid.clear_levels();
+        for level in bids {
+            self.bid.set_level(level, false);
+        }
+
+        // ask
+        self.ask.clear_levels();
+        for level in asks {
+            self.ask.set_level(level, true);
+        }
+
+        self.trim();
+    }
+
+    pub fn apply_depth_book_update_from_websocket(
+        &mut self,
+        book: &BookDepthUpdate,
+    ) -> Result<ApplyResult, ApplyError> {
+        // for already applied updates from ws, i.e. u < lastUpdateId of the full book
+        if self.is_update_applied(book) {
+            return Ok(ApplyResult::AlreadyApplied);
+        }
+        if self.is_just_initialised {
+            // first update after the full book should bracket its lastUpdateId,
+            // otherwise the book is already too old, we need ask http api again
+            if !self.first_event_valid(book) {
+                return Err(ApplyError::NeedsSnapshot {
+                    last_update_id: self.last_update_id,
+                    first: book.U,
+                    last: book.u,
+                });
+            }
+        } else {
+            // check that previous final id was last_id, otherwise updates were missed
+            let (expected, got) = match (self.market, book.pu) {
+                (Market::Futures, Some(pu)) => (self.last_update_id, pu),
+                // spot updates have no pu, they go one after another
+                _ => (self.last_update_id + 1, book.U),
+            };
+            if expected != got {
+                return Err(ApplyError::SequenceGap { expected, got });
+            }
+        }
+
+        // update, nothing is applied if any level is invalid
+        let bids = levels_api_to_levels(&book.b)?;
+        let asks = levels_api_to_levels(&book.a)?;
+        for level in bids {
+            self.apply_bid(level);
+        }
+        for level in asks {
+            self.apply_ask(level);
+        }
+        self.last_update_id = book.u;
+        self.event_time = Some(book.E);
+        self.is_just_initialised = false;
+        self.trim();
+        self.update_mid_ema();
+        // trades leave the window while trading pauses too
+        self.evict_window_trades(book.E);
+
+        Ok(ApplyResult::Applied)
+    }
+
+    // utils
+    /// trades come in time order, the ones older than the window before the time are evicted
+    fn evict_window_trades(&mut self, time: u64) {
+        let window_start = time.saturating_sub(self.trade_window_ms);
+        while let Some(oldest) = self.window_trades.front() {
+            if oldest.time > window_start {
+                break;
+            }
+            self.window_trades.pop_front();
+        }
+    }
+
+    fn is_update_applied(&self, book_update: &BookDepthUpdate) -> bool {
+        match self.market {
+            Market::Futures => self.last_update_id > book_update.u,
+            Market::Spot => self.last_update_id >= book_update.u,
+        }
+    }
+
+    /// whether the update could be the first one processed after the full book,
+    /// spot one has to bracket lastUpdateId + 1, U <= lastUpdateId + 1 <= u,
+    /// futures one has to bracket lastUpdateId itself, U <= lastUpdateId <= u
+    pub fn first_event_valid(&self, book_update: &BookDepthUpdate) -> bool {
+        // spot full book doesn't include the first update to apply
+        let last_update_id = match self.market {
+            Market::Futures => self.last_update_id,
+            Market::Spot => self.last_update_id + 1,
+        };
+        book_update.U <= last_update_id && last_update_id <= book_update.u
+    }
+
+    fn apply_bid(&mut self, level: Level) {
+        Self::do_apply_to_level(&mut self.bid, level, false)
+    }
+    fn apply_ask(&mut self, level: Level) {
+        Self::do_apply_to_level(&mut self.ask, level, true)
+    }
+
+    fn do_apply_to_level(levels: &mut S, level_update: Level, ascending: bool) {
+        if numbers_equal(level_update.quantity, ZERO) {
+            levels.remove_level(level_update.price, ascending);
+        } else {
+            levels.set_level(level_update, ascending);
+        }
+    }
+
+    /// keeps the displayed levels and refreshes the cached top of book
+    fn trim(&mut self) {
+        self.bid.truncate_levels(self.levels as usize, false);
+        self.ask.truncate_levels(self.levels as usize, true);
+        self.top = self.compute_top();
+    }
+
+    /// copy of the book and its derived values taken under one borrow,
+    /// it's published to other tasks instead of reading the book field by field
+    pub fn snapshot(&self) -> BookSnapshot {
+        let bids: Vec<Level> = self.bid.levels(false).collect();
+        let asks: Vec<Level> = self.ask.levels(true).collect();
+        BookSnapshot {
+            symbol: self.symbol.clone(),
+            last_update_id: self.last_update_id,
+            mid: self.get_mid(),
+            spread: self.get_spread(),
+            best_bid: bids.first().copied(),
+            best_ask: asks.first().copied(),
+            last_trade: self.get_last_trade(),
+            bids,
+            asks,
+        }
+    }
+
+    pub fn update_record(&self) -> UpdateRecord {
+        UpdateRecord {
+            symbol: self.symbol.clone(),
+            last_update_id: self.last_update_id,
+            mid: self.get_mid(),
+            spread: self.get_spread(),
+            best_bid: self.get_best_bid().ok(),
+            best_ask: self.get_best_ask().ok(),
+            event_time: self.event_time,
+        }
+    }
+
+    pub fn event(&self, ts: u128) -> BookEvent {
+        BookEvent {
+            symbol: self.symbol.clone(),
+            mid: self.get_mid(),
+            spread: self.get_spread(),
+            best_bid: self.get_best_bid().ok(),
+            best_ask: self.get_best_ask().ok(),
+            ts,
+        }
+    }
+
+    /// top of book as csv line, see csv_header, empty values are left blank
+    pub fn to_csv_line(&self) -> String {
+        let optional = |value: Option<Number>| value.map(|v| v.to_string()).unwrap_or_default();
+        let bid = self.get_best_bid().ok();
+        let ask = self.get_best_ask().ok();
+        format!(
+            "{},{},{},{},{},{},{},{}",
+            self.symbol,
+            self.last_update_id,
+            optional(self.get_mid()),
+            optional(self.get_last_trade()),
+            optional(bid.map(|level| level.price)),
+            optional(bid.map(|level| level.quantity)),
+            optional(ask.map(|level| level.price)),
+            optional(ask.map(|level| level.quantity)),
+        )
+    }
+
+    /// top of book as a single line, e.g. for many symbols logging, empty values are -
+    pub fn to_compact_line(&self) -> String {
+        let level = |level: Result<Level, String>| {
+            level
+                .map(|level| {
+                    format!(
+                        "{}x{}",
+                        self.precision.format_price(level.price),
+                        self.precision.format_quantity(level.quantity)
+                    )
+                })
+                .unwrap_or_else(|_| "-".to_string())
+        };
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        format!(
+            "{} bid={} ask={} mid={} spread={}bps ts={}",
+            self.symbol.to_uppercase(),
+            level(self.get_best_bid()),
+            level(self.get_best_ask()),
+            optional(self.get_mid().map(|mid| mid.to_string())),
+            optional(self.get_spread_bps().map(|bps| format!("{bps:.2}"))),
+            optional(self.event_time.map(|ts| ts.to_string())),
+        )
+    }
+
+    /// event time of the last applied depth update, epoch ms
+    pub fn get_event_time(&self) -> Option<u64> {
+        self.event_time
+    }
+
+    /// table like Display with green bids and red asks, changed levels are bold
+    pub fn to_colored_table(&self, is_changed: &dyn Fn(Side, &Level) -> bool) -> String {
+        ColoredTable {
+            book: self,
+            is_changed,
+        }
+        .to_string()
+    }
+
+    fn write_level(
+        &self,
+        f: &mut Formatter<'_>,
+        level_bid: Option<&Level>,
+        level_ask: Option<&Level>,
+        is_changed: Option<&IsLevelChanged<'_>>,
+    ) {
+        self.write_cell(f, Side::Bid, level_bid, is_changed);
+        write!(f, "     ").unwrap();
+        self.write_cell(f, Side::Ask, level_ask, is_changed);
+        writeln!(f).unwrap();
+    }
+
+    fn write_cell(
+        &self,
+        f: &mut Formatter<'_>,
+        side: Side,
+        level: Option<&Level>,
+        is_changed: Option<&IsLevelChanged<'_>>,
+    ) {
+        let empty_level = "|         ---          |";
+        match (level, is_changed) {
+            (None, _) => {
+                write!(f, "{}", empty_level).unwrap();
+            }
+            (Some(level), None) => {
+                write!(
+                    f,
+                    "|{:>10}|{:>10}|",
+                    self.precision.format_quantity(level.quantity),
+                    self.precision.format_price(level.price)
+                )
+                .unwrap();
+            }
+            (Some(level), Some(is_changed)) => {
+                let color = match side {
+                    Side::Bid => ANSI_GREEN,
+                    Side::Ask => ANSI_RED,
+                };
+                let bold = if is_changed(side, level) {
+                    ANSI_BOLD
+                } else {
+                    ""
+                };
+                write!(
+                    f,
+                    "|{bold}{color}{:>10}|{:>10}{ANSI_RESET}|",
+                    self.precision.format_quantity(level.quantity),
+                    self.precision.format_price(level.price)
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    fn write_table(
+        &self,
+        f: &mut Formatter<'_>,
+        is_changed: Option<&IsLevelChanged<'_>>,
+    ) -> std::fmt::Result {
+        writeln!(
+            f,
+            "====         ORDER BOOK : {}              ====",
+            self.symbol
+        )?;
+        writeln!(
+            f,
+            "|  mid: {:12}  |     |  last: {:11}  |",
+            OrMissing(self.get_mid()),
+            OrMissing(self.get_last_trade())
+        )?;
+        writeln!(
+            f,
+            "| spread bps: {:8.2} |     |  ema: {:12.6}  |",
+            OrMissing(self.get_spread_bps()),
+            OrMissing(self.get_mid_ema())
+        )?;
+        if let Some(mark) = self.mark {
+            writeln!(
+                f,
+                "|  mark: {:11}  |     |  funding: {:9.4}%  |",
+                mark.price,
+                mark.funding_rate * 100.0
+            )?;
+        }
+        if let Some(day) = self.day {
+            writeln!(
+                f,
+                "| 24h: last {} {:+.2}% h {} l {} v {}",
+                day.last_price, day.price_change_percent, day.high, day.low, day.volume
+            )?;
+        }
+        if let Some(candle) = self.candle {
+            writeln!(
+                f,
+                "| candle{}: o {} h {} l {} c {} v {}",
+                if candle.closed { " (closed)" } else { "" },
+                candle.open,
+                candle.high,
+                candle.low,
+                candle.close,
+                candle.volume
+            )?;
+        }
+        writeln!(f, "|         bid         |     |         ask         |")?;
+        writeln!(f, "|   qty    |   price  |     |   qty    |  price   |")?;
+        writeln!(f, "---------------------------------------------------")?;
+        let mut bids = self.bid.levels(false);
+        let mut asks = self.ask.levels(true);
+        for _ in 0..self.levels {
+            self.write_level(f, bids.next().as_ref(), asks.next().as_ref(), is_changed);
+        }
+        if self.tape_size > 0 {
+            writeln!(f, "---------------------------------------------------")?;
+            // the latest trade first, buyer maker is a sell of the taker
+            for trade in self.tape.iter().rev() {
+                let side = if trade.m { "sell" } else { "buy" };
+                writeln!(f, "| {:4} | {:>14} @ {:<14} |", side, trade.q, trade.p)?;
+            }
+        }
+        writeln!(f, "====            END ORDER BOOK                 ====")?;
+        Ok(())
+    }
+}
+
+/// Books are equal if they're of the same symbol at the same update with the same levels,
+/// stats like trades or moving averages aren't compared
+impl<S: BookSide> PartialEq for OrderBook<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.symbol == other.symbol
+            && self.last_update_id == other.last_update_id
+            && self.bid.levels(false).eq(other.bid.levels(false))
+            && self.ask.levels(true).eq(other.ask.levels(true))
+    }
+}
+
+impl<S: BookSide> Display for OrderBook<S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.write_table(f, None)
+    }
+}
+
+/// levels of a book, unless configured
+pub const DEFAULT_LEVELS: u32 = 20;
+
+/// trades of the last seconds averaged in VWAP, unless configured
+pub const DEFAULT_TRADE_WINDOW_SECS: u64 = 60;
+
+/// weight of the latest mid in its moving average, unless configured
+pub const DEFAULT_EMA_ALPHA: f64 = 0.1;
+
+/// tells if the level has changed since the last render
+type IsLevelChanged<'a> = dyn Fn(Side, &Level) -> bool + 'a;
+
+const ANSI_GREEN: &str = "\x1b[32m";
+const ANSI_RED: &str = "\x1b[31m";
+const ANSI_BOLD: &str = "\x1b[1m";
+const ANSI_RESET: &str = "\x1b[0m";
+
+/// Colored table of the book, see [OrderBook::to_colored_table]
+struct ColoredTable<'a, S> {
+    book: &'a OrderBook<S>,
+    is_changed: &'a IsLevelChanged<'a>,
+}
+
+impl<S: BookSide> Display for ColoredTable<'_, S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.book.write_table(f, Some(self.is_changed))
+    }
+}
+
+/// Value of a table header, --- right aligned in its width when missing, e.g. one sided book
+struct OrMissing<T>(Option<T>);
+
+impl<T: Display> Display for OrMissing<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Some(value) => Display::fmt(value, f),
+            None => write!(f, "{:>width$}", "---", width = f.width().unwrap_or(0)),
+        }
+    }
+}
+
+/// changes of one side, sides are ordered from the best level
+fn side_changes(
+    side: Side,
+    old: impl Iterator<Item = Level>,
+    new: impl Iterator<Item = Level>,
+    ascending: bool,
+) -> Vec<LevelChange> {
+    let mut old = old.peekable();
+    let mut new = new.peekable();
+    let mut changes = vec![];
+    loop {
+        match (old.peek(), new.peek()) {
+            (Some(a), Some(b)) if numbers_equal(a.price, b.price) => {
+                if !numbers_equal(a.quantity, b.quantity) {
+                    changes.push(LevelChange::Modified {
+                        side,
+                        price: b.price,
+                        old_quantity: a.quantity,
+                        new_quantity: b.quantity,
+                    });
+                }
+                old.next();
+                new.next();
+            }
+            // the better price is the one missing in the other book
+            (Some(a), Some(b)) if (a.price < b.price) == ascending => {
+                changes.push(LevelChange::Removed(side, *a));
+                old.next();
+            }
+            (_, Some(b)) => {
+                changes.push(LevelChange::Added(side, *b));
+                new.next();
+            }
+            (Some(a), None) => {
+                changes.push(LevelChange::Removed(side, *a));
+                old.next();
+            }
+            (None, None) => return changes,
+        }
+    }
+}
+
+/// mismatched levels of one side, sides are ordered from the best level
+fn side_mismatches(
+    local: &[Level],
+    remote: &[Level],
+    levels: usize,
+    ascending: bool,
+) -> Vec<LevelMismatch> {
+    // a side with less levels than the limit is complete, otherwise it ends at its worst level
+    let worst = |side: &[Level]| {
+        if side.len() < levels {
+            None
+        } else {
+            side.last().map(|level| level.price)
+        }
+    };
+    let boundary = match (worst(local), worst(remote)) {
+        (Some(a), Some(b)) if ascending => Some(a.min(b)),
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    };
+    let is_comparable = |price: Number| match boundary {
+        None => true,
+        Some(boundary) if ascending => price <= boundary,
+        Some(boundary) => price >= boundary,
+    };
+    let quantity = |side: &[Level], price: Number| {
+        side.iter()
+            .find(|level| numbers_equal(level.price, price))
+            .map(|level| level.quantity)
+    };
+
+    let mut prices: Vec<Number> = local
+        .iter()
+        .chain(remote)
+        .map(|level| level.price)
+        .collect();
+    prices.sort_by(|a, b| {
+        if ascending {
+            compare_numbers(a, b)
+        } else {
+            compare_numbers(b, a)
+        }
+    });
+    prices.dedup_by(|a, b| numbers_equal(*a, *b));
+    prices
+        .into_iter()
+        .filter(|price| is_comparable(*price))
+        .filter_map(|price| {
+            let local_quantity = quantity(local, price);
+            let remote_quantity = quantity(remote, price);
+            let is_equal = match (local_quantity, remote_quantity) {
+                (Some(a), Some(b)) => numbers_equal(a, b),
+                _ => false,
+            };
+            (!is_equal).then_some(LevelMismatch {
+                is_bid: !ascending,
+                price,
+                local_quantity,
+                remote_quantity,
+            })
+        })
+        .collect()
+}
+
+fn look_for_level(price: Number, levels: &[Level], ascending: bool) -> Result<usize, usize> {
+    // TBD: in reality unnecessary for small levels limits <=100
+    let key = PriceKey(price);
+    if ascending {
+        levels.binary_search_by_key(&key, |level| PriceKey(level.price))
+    } else {
+        levels.binary_search_by_key(&Reverse(key), |level| Reverse(PriceKey(level.price)))
+    }
+}
+
+/// sorts levels by price, false if they weren't sorted
+fn sort_levels(levels: &mut [Level], ascending: bool) -> bool {
+    let compare = |a: &Level, b: &Level| match ascending {
+        true => a.cmp(b),
+        false => b.cmp(a),
+    };
+    let is_sorted = levels.is_sorted_by(|a, b| compare(a, b) != Ordering::Greater);
+    if !is_sorted {
+        levels.sort_by(compare);
+    }
+    is_sorted
+}
+
+fn level_api_to_level(api_level: &LevelApi) -> Result<Level, ApplyError> {
+    Ok(Level {
+        quantity: parse_number(&api_level.quantity)?,
+        price: parse_number(&api_level.price)?,
+    })
+}
+
+/// all levels or the first invalid one
+fn levels_api_to_levels(api_levels: &[LevelApi]) -> Result<Vec<Level>, ApplyError> {
+    api_levels.iter().map(level_api_to_level).collect()
+}
+
+fn parse_number(value: &str) -> Result<Number, ApplyError> {
+    crate::number::parse_number(value).ok_or_else(|| ApplyError::InvalidNumber(value.to_string()))
+}
+
+/// number of stats which don't need to be exact, like mark price or candles
+fn parse_float(value: &str) -> Result<f64, ApplyError> {
+    parse_number(value).map(to_f64)
+}
+
+/// 5 levels book of the symbol with the full book of the [price, quantity] levels applied,
+/// lastUpdateId is 1, shared by tests of the modules using books
+#[cfg(test)]
+pub fn test_book(symbol: &str, bids: &[[&str; 2]], asks: &[[&str; 2]]) -> OrderBook {
+    let levels = |levels: &[[&str; 2]]| {
+        levels
+            .iter()
+            .map(|[price, quantity]| LevelApi {
+                price: price.to_string(),
+                quantity: quantity.to_string(),
+            })
+            .collect()
+    };
+    let mut book = OrderBook::new(5, symbol.to_string());
+    book.apply_full_book_from_http_api(&FullBook {
+        last_update_id: 1,
+        bids: levels(bids),
+        asks: levels(asks),
+    })
+    .unwrap();
+    book
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::number::number;
+    use proptest::prelude::*;
+
+    #[test]
+    fn get_best_bid_test() {
+        let mut book: OrderBook = OrderBook::default();
+
+        book.bid.push(Level {
+            quantity: number("1.0"),
+            price: number("20.0"),
+        });
+        book.bid.push(Level {
+            quantity: number("1.0"),
+            price: number("19.0"),
+        });
+        book.bid.push(Level {
+            quantity: number("1.0"),
+            price: number("18.0"),
+        });
+        book.bid.push(Level {
+            quantity: number("1.0"),
+            price: number("17.0"),
+        });
+
+        book.ask.push(Level {
+            quantity: number("1.0"),
+            price: number("21.0"),
+        });
+        book.ask.push(Level {
+            quantity: number("1.0"),
+            price: number("22.0"),
+        });
+        book.ask.push(Level {
+            quantity: number("1.0"),
+            price: number("23.0"),
+        });
+        book.ask.push(Level {
+            quantity: number("1.0"),
+            price: number("24.0"),
+        });
+
+        assert_eq!(
+            book.get_best_bid().unwrap(),
+            Level {
+                quantity: number("1.0"),
+                price: number("20.0")
+            }
+        );
+        assert_eq!(
+            book.get_best_ask().unwrap(),
+            Level {
+                quantity: number("1.0"),
+                price: number("21.0")
+            }
+        );
+    }
+
+    #[test]
+    fn apply_http_full_book_apply_test() {
+        let mut book: OrderBook = OrderBook {
+            levels: 3,
+            ..Default::default()
+        };
+
+        let http_book = FullBook {
+            last_update_id: 100500,
+            bids: vec![
+                LevelApi {
+                    quantity: "1".to_string(),
+                    price: "5".to_string(),
+                },
+                LevelApi {
+                    quantity: "1".to_string(),
+                    price: "4".to_string(),
+                },
+                LevelApi {
+                    quantity: "1".to_string(),
+                    price: "3".to_string(),
+                },
+            ],
+            asks: vec![
+                LevelApi {
+                    quantity: "1".to_string(),
+                    price: "6".to_string(),
+                },
+                LevelApi {
+                    quantity: "1".to_string(),
+                    price: "7".to_string(),
+                },
+                LevelApi {
+                    quantity: "1".to_string(),
+                    price: "8".to_string(),
+                },
+            ],
+        };
+
+        book.apply_full_book_from_http_api(&http_book).unwrap();
+
+        assert_eq!(
+            book.get_best_bid().unwrap(),
+            Level {
+                quantity: number("1.0"),
+                price: number("5.0")
+            }
+        );
+        assert_eq!(
+            book.get_best_ask().unwrap(),
+            Level {
+                quantity: number("1.0"),
+                price: number("6.0")
+            }
+        );
+        assert_eq!(book.bid.len(), 3);
+        assert_eq!(book.ask.len(), 3);
+
+        // change levels param
+
+        book.levels = 2;
+        book.apply_full_book_from_http_api(&http_book).unwrap();
+
+        assert_eq!(
+            book.get_best_bid().unwrap(),
+            Level {
+                quantity: number("1.0"),
+                price: number("5.0")
+            }
+        );
+        assert_eq!(
+            book.get_best_ask().unwrap(),
+            Level {
+                quantity: number("1.0"),
+                price: number("6.0")
+            }
+        );
+        assert_eq!(book.bid.len(), 2);
+        assert_eq!(book.ask.len(), 2);
+    }
+
+    #[test]
+    fn apply_websocket_update_book_apply_test() {
+        let mut book: OrderBook = OrderBook {
+            levels: 3,
+            is_just_initialised: true,
+            ..Default::default()
+        };
+
+        let ws_book = BookDepthUpdate {
+            e: "".to_string(),
+            E: 0,
             T: 0,
             s: "".to_string(),
             U: 100000,
             u: 100500,
-            pu: 0,
+            pu: Some(0),
             b: vec![
                 LevelApi {
                     quantity: "1".to_string(),
@@ -435,45 +1765,1439 @@ mod test {
             ],
         };
 
-        let succ = book.apply_depth_book_update_from_websocket(&ws_book);
+        let succ = book.apply_depth_book_update_from_websocket(&ws_book);
+
+        // 1) our original book is too old with last_update_id == 0, update should return false
+        assert_eq!(
+            succ,
+            Err(ApplyError::NeedsSnapshot {
+                last_update_id: 0,
+                first: 100000,
+                last: 100500
+            })
+        );
+
+        // 2) if book already applied update, then nothing should be done
+        book.last_update_id = 100501;
+
+        let succ = book.apply_depth_book_update_from_websocket(&ws_book);
+
+        assert_eq!(succ, Ok(ApplyResult::AlreadyApplied));
+        assert_eq!(book.bid.len(), 0);
+        assert_eq!(book.ask.len(), 0);
+        assert_eq!(book.last_update_id, 100501);
+
+        // 3) we are in range for update between U <= last_update_id <= u
+
+        book.last_update_id = 100499;
+
+        let succ = book.apply_depth_book_update_from_websocket(&ws_book);
+
+        assert_eq!(succ, Ok(ApplyResult::Applied));
+        assert_eq!(book.bid.len(), 3);
+        assert_eq!(book.ask.len(), 3);
+        assert_eq!(book.last_update_id, 100500);
+
+        assert_eq!(
+            book.get_best_bid().unwrap(),
+            Level {
+                quantity: number("1.0"),
+                price: number("5.0")
+            }
+        );
+        assert_eq!(
+            book.get_best_ask().unwrap(),
+            Level {
+                quantity: number("1.0"),
+                price: number("6.0")
+            }
+        );
+    }
+
+    #[test]
+    fn apply_websocket_update_zero_quantity_test() {
+        let level = |price: &str, quantity: &str| LevelApi {
+            price: price.to_string(),
+            quantity: quantity.to_string(),
+        };
+        let mut book = OrderBook::new(5, "btcusdt".to_string());
+        book.apply_full_book_from_http_api(&FullBook {
+            last_update_id: 100,
+            bids: vec![level("5", "1"), level("4", "2")],
+            asks: vec![level("6", "1"), level("7", "2")],
+        })
+        .unwrap();
+
+        // zero quantity removes the level, the zero price one is kept as binance sent it
+        let update = BookDepthUpdate {
+            e: "depthUpdate".to_string(),
+            E: 0,
+            T: 0,
+            s: "BTCUSDT".to_string(),
+            U: 100,
+            u: 101,
+            pu: Some(99),
+            b: vec![level("5", "0"), level("0", "3")],
+            a: vec![level("6", "0.00000000")],
+        };
+        assert_eq!(
+            book.apply_depth_book_update_from_websocket(&update),
+            Ok(ApplyResult::Applied)
+        );
+        assert_eq!(book.get_best_bid().unwrap().price, number("4.0"));
+        assert_eq!(book.get_best_ask().unwrap().price, number("7.0"));
+        assert!(book
+            .bid
+            .levels(false)
+            .all(|level| level.price != number("5.0")));
+        assert_eq!(book.bid.len(), 2);
+        assert_eq!(book.ask.len(), 1);
+    }
+
+    #[test]
+    fn apply_agg_trade_test() {
+        let mut book: OrderBook = OrderBook::default();
+        assert_eq!(book.get_last_trade(), None);
+
+        let text = r#"{"e":"aggTrade","E":123456789,"s":"BTCUSDT","a":5933014,"p":"59000.1","q":"100","f":100,"l":105,"T":123456785,"m":true}"#;
+        let trade: AggTrade = serde_json::from_str(text).unwrap();
+        book.apply_agg_trade(&trade).unwrap();
+
+        assert_eq!(book.get_last_trade(), Some(number("59000.1")));
+    }
+
+    #[test]
+    fn trade_tape_test() {
+        let trade = |id: u64, buyer_maker: bool| -> AggTrade {
+            serde_json::from_str(&format!(
+                r#"{{"e":"aggTrade","E":1,"s":"BTCUSDT","a":{id},"p":"{id}.5","q":"0.{id}","f":1,"l":1,"T":{id},"m":{buyer_maker}}}"#
+            ))
+            .unwrap()
+        };
+        let mut book = OrderBook::new(1, "btcusdt".to_string());
+        book.apply_agg_trade(&trade(1, false)).unwrap();
+        // nothing is kept by default
+        assert!(book.recent_trades().is_empty());
+        assert!(!book.to_string().contains("buy"));
+
+        let mut book = OrderBook::new(1, "btcusdt".to_string()).with_trade_tape_size(3);
+        for id in 1..=5 {
+            book.apply_agg_trade(&trade(id, id % 2 == 0)).unwrap();
+        }
+        let ids: Vec<u64> = book.recent_trades().iter().map(|trade| trade.a).collect();
+        assert_eq!(ids, vec![3, 4, 5]);
+
+        let table = book.to_string();
+        let tape: Vec<&str> = table
+            .lines()
+            .filter(|line| line.starts_with("| buy") || line.starts_with("| sell"))
+            .collect();
+        assert_eq!(
+            tape,
+            vec![
+                "| buy  |            0.5 @ 5.5            |",
+                "| sell |            0.4 @ 4.5            |",
+                "| buy  |            0.3 @ 3.5            |",
+            ]
+        );
+    }
+
+    #[test]
+    fn trade_vwap_test() {
+        let mut book = OrderBook::new(5, "btcusdt".to_string()).with_trade_window_secs(10);
+        assert_eq!(book.get_trade_vwap(), None);
+        let mut apply = |price: &str, quantity: &str, time: u64| {
+            let trade: AggTrade = serde_json::from_str(&format!(
+                r#"{{"e":"aggTrade","E":{time},"s":"BTCUSDT","a":1,"p":"{price}","q":"{quantity}","f":1,"l":1,"T":{time},"m":true}}"#
+            ))
+            .unwrap();
+            book.apply_agg_trade(&trade).unwrap();
+            book.get_trade_vwap()
+        };
+
+        assert_eq!(apply("100", "1", 1_000), Some(number("100.0")));
+        assert_eq!(apply("110", "3", 5_000), Some(number("107.5")));
+        // the first trade is 10s old exactly, it's out of the window
+        assert_eq!(apply("120", "1", 11_000), Some(number("112.5")));
+        // all trades are out of the window, except the latest
+        assert_eq!(apply("50", "2", 30_000), Some(number("50.0")));
+
+        // no trades after the window, depth updates move it on
+        book.apply_full_book_from_http_api(&FullBook {
+            last_update_id: 1,
+            bids: vec![],
+            asks: vec![],
+        })
+        .unwrap();
+        let mut update = |time: u64, first: u64, last: u64, previous: u64| {
+            let update: BookDepthUpdate = serde_json::from_str(&format!(
+                r#"{{"e":"depthUpdate","E":{time},"T":{time},"s":"BTCUSDT","U":{first},"u":{last},"pu":{previous},"b":[["10","1"]],"a":[]}}"#
+            ))
+            .unwrap();
+            book.apply_depth_book_update_from_websocket(&update)
+                .unwrap();
+            book.get_trade_vwap()
+        };
+        assert_eq!(update(35_000, 1, 2, 0), Some(number("50.0")));
+        assert_eq!(update(40_000, 3, 3, 2), None);
+    }
+
+    #[test]
+    fn cumulative_delta_test() {
+        let mut book = OrderBook::new(5, "btcusdt".to_string()).with_trade_window_secs(10);
+        assert_eq!(book.cumulative_delta(), Some(ZERO));
+        let mut apply = |quantity: &str, time: u64, buyer_maker: bool| {
+            let trade: AggTrade = serde_json::from_str(&format!(
+                r#"{{"e":"aggTrade","E":{time},"s":"BTCUSDT","a":1,"p":"100","q":"{quantity}","f":1,"l":1,"T":{time},"m":{buyer_maker}}}"#
+            ))
+            .unwrap();
+            book.apply_agg_trade(&trade).unwrap();
+            (book.cumulative_delta(), book.window_delta())
+        };
+        let delta =
+            |cumulative: &str, window: &str| (Some(number(cumulative)), Some(number(window)));
+
+        // taker buys
+        assert_eq!(apply("2", 1_000, false), delta("2", "2"));
+        assert_eq!(apply("0.5", 2_000, false), delta("2.5", "2.5"));
+        // taker sells to buyer makers
+        assert_eq!(apply("4", 3_000, true), delta("-1.5", "-1.5"));
+        // the first two trades leave the window, not the cumulative delta
+        assert_eq!(apply("1", 12_500, false), delta("-0.5", "-3"));
+
+        book.reset_cumulative_delta();
+        assert_eq!(book.cumulative_delta(), Some(ZERO));
+        assert_eq!(book.window_delta(), Some(number("-3")));
+    }
+
+    #[test]
+    fn apply_mark_price_test() {
+        let mut book = OrderBook::new(1, "btcusdt".to_string());
+        assert_eq!(book.get_mark(), None);
+        assert!(!book.to_string().contains("mark:"));
+
+        let text = r#"{"e":"markPriceUpdate","E":1562305380000,"s":"BTCUSDT","p":"11794.15","i":"11784.62","P":"11784.25","r":"0.00038167","T":1562306400000}"#;
+        let update: MarkPriceUpdate = serde_json::from_str(text).unwrap();
+        book.apply_mark_price(&update).unwrap();
+        assert_eq!(
+            book.get_mark(),
+            Some(Mark {
+                price: 11794.15,
+                funding_rate: 0.00038167,
+                next_funding_time: 1562306400000
+            })
+        );
+
+        let text = r#"{"e":"markPriceUpdate","E":1562305381000,"s":"BTCUSDT","p":"11795","r":"-0.0001","T":1562306400000}"#;
+        let update: MarkPriceUpdate = serde_json::from_str(text).unwrap();
+        book.apply_mark_price(&update).unwrap();
+        assert_eq!(book.get_mark().unwrap().price, 11795.0);
+        assert_eq!(book.get_mark().unwrap().funding_rate, -0.0001);
+        assert!(book
+            .to_string()
+            .contains("|  mark:       11795  |     |  funding:   -0.0100%  |"));
+    }
+
+    #[test]
+    fn apply_ticker_24hr_test() {
+        let mut book = OrderBook::new(1, "btcusdt".to_string());
+        assert_eq!(book.get_day_stats(), None);
+        assert!(!book.to_string().contains("24h"));
+
+        let text = r#"{"e":"24hrTicker","E":123456789,"s":"BTCUSDT","p":"-150.5","P":"-0.250","w":"60100","c":"60000.5","Q":"1","o":"60151","h":"61000","l":"59500.25","v":"12345.678","q":"741000000","O":0,"C":86400000,"F":0,"L":18150,"n":18151}"#;
+        let ticker: Ticker24hr = serde_json::from_str(text).unwrap();
+        book.apply_ticker_24hr(&ticker).unwrap();
+        assert_eq!(
+            book.get_day_stats(),
+            Some(DayStats {
+                last_price: 60000.5,
+                price_change_percent: -0.25,
+                high: 61000.0,
+                low: 59500.25,
+                volume: 12345.678
+            })
+        );
+        assert!(book
+            .to_string()
+            .contains("| 24h: last 60000.5 -0.25% h 61000 l 59500.25 v 12345.678\n"));
+    }
+
+    #[test]
+    fn display_precision_test() {
+        let full_book: FullBook = serde_json::from_str(
+            r#"{"lastUpdateId":1,"bids":[["0.5","10"]],"asks":[["0.5001","2.5"]]}"#,
+        )
+        .unwrap();
+        let mut book = OrderBook::new(1, "ethbtc".to_string());
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+        assert!(book
+            .to_string()
+            .contains("|        10|       0.5|     |       2.5|    0.5001|"));
+
+        let mut book = OrderBook::new(1, "ethbtc".to_string()).with_precision(Precision {
+            price_decimals: Some(4),
+            quantity_decimals: Some(3),
+        });
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+        assert!(book
+            .to_string()
+            .contains("|    10.000|    0.5000|     |     2.500|    0.5001|"));
+    }
+
+    #[test]
+    fn display_one_sided_test() {
+        let full_book: FullBook =
+            serde_json::from_str(r#"{"lastUpdateId":1,"bids":[["100","1"],["99","2"]],"asks":[]}"#)
+                .unwrap();
+        let mut book = OrderBook::new(2, "btcusdt".to_string());
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+        let table = book.to_string();
+        assert!(!table.contains("NaN"));
+        assert!(table.contains("|  mid:          ---  |     |  last:         ---  |"));
+        assert!(table.contains("| spread bps:      --- |     |  ema:          ---  |"));
+        assert!(table.contains("|         1|       100|"));
+        assert!(table.contains("|         2|        99|"));
+
+        let table = OrderBook::new(2, "btcusdt".to_string()).to_string();
+        assert!(!table.contains("NaN"));
+        assert!(table.contains("|  mid:          ---  |"));
+    }
+
+    #[test]
+    fn apply_kline_test() {
+        let mut book = OrderBook::new(1, "btcusdt".to_string());
+        assert_eq!(book.get_candle(), None);
+        let kline = |t: u64, close: &str, closed: bool| -> KlineUpdate {
+            serde_json::from_str(&format!(
+                r#"{{"e":"kline","E":{t},"s":"BTCUSDT","k":{{"t":{t},"T":{},"s":"BTCUSDT","i":"1m","o":"10","c":"{close}","h":"12","l":"9","v":"3.5","n":7,"x":{closed}}}}}"#,
+                t + 59999
+            ))
+            .unwrap()
+        };
+
+        book.apply_kline(&kline(60000, "11", false)).unwrap();
+        assert_eq!(
+            book.get_candle(),
+            Some(Candle {
+                open_time: 60000,
+                open: 10.0,
+                high: 12.0,
+                low: 9.0,
+                close: 11.0,
+                volume: 3.5,
+                closed: false
+            })
+        );
+        assert!(book
+            .to_string()
+            .contains("| candle: o 10 h 12 l 9 c 11 v 3.5"));
+
+        book.apply_kline(&kline(60000, "11.5", true)).unwrap();
+        assert!(book.get_candle().unwrap().closed);
+        assert_eq!(book.get_candle().unwrap().close, 11.5);
+        assert!(book.to_string().contains("| candle (closed): o 10"));
+
+        // next candle replaces the closed one, late updates of the previous are ignored
+        book.apply_kline(&kline(120000, "12", false)).unwrap();
+        book.apply_kline(&kline(60000, "11.5", true)).unwrap();
+        assert_eq!(book.get_candle().unwrap().open_time, 120000);
+        assert!(!book.get_candle().unwrap().closed);
+    }
+
+    #[test]
+    fn apply_partial_depth_test() {
+        let mut book = OrderBook::new(5, "btcusdt".to_string());
+        let update: PartialDepthUpdate = serde_json::from_str(
+            r#"{"lastUpdateId":160,"bids":[["100","1"],["99","2"]],"asks":[["101","3"],["102","4"]]}"#,
+        )
+        .unwrap();
+        assert_eq!(book.apply_partial_depth(&update), Ok(ApplyResult::Applied));
+        assert_eq!(book.get_last_update_id(), 160);
+        assert_eq!(book.get_mid(), Some(number("100.5")));
+        assert_eq!(book.get_mid_ema(), Some(100.5));
+
+        // levels missing from the next update are gone, there's no diff
+        let update: PartialDepthUpdate = serde_json::from_str(
+            r#"{"lastUpdateId":165,"E":7,"bids":[["98","5"]],"asks":[["103","6"],["101.5","7"]]}"#,
+        )
+        .unwrap();
+        assert_eq!(book.apply_partial_depth(&update), Ok(ApplyResult::Applied));
+        let snapshot = book.snapshot();
+        assert_eq!(
+            snapshot.bids,
+            vec![Level {
+                quantity: number("5.0"),
+                price: number("98.0")
+            }]
+        );
+        assert_eq!(
+            snapshot.asks,
+            vec![
+                Level {
+                    quantity: number("7.0"),
+                    price: number("101.5")
+                },
+                Level {
+                    quantity: number("6.0"),
+                    price: number("103.0")
+                }
+            ]
+        );
+        assert_eq!(book.get_last_update_id(), 165);
+        assert_eq!(book.get_event_time(), Some(7));
+
+        // older and invalid updates are skipped
+        let update: PartialDepthUpdate =
+            serde_json::from_str(r#"{"lastUpdateId":150,"bids":[],"asks":[]}"#).unwrap();
+        assert_eq!(
+            book.apply_partial_depth(&update),
+            Ok(ApplyResult::AlreadyApplied)
+        );
+        let update: PartialDepthUpdate =
+            serde_json::from_str(r#"{"lastUpdateId":170,"bids":[["x","1"]],"asks":[]}"#).unwrap();
+        assert!(book.apply_partial_depth(&update).is_err());
+        assert_eq!(book.snapshot().bids, snapshot.bids);
+        assert_eq!(book.snapshot().asks, snapshot.asks);
+        assert_eq!(book.get_last_update_id(), 165);
+    }
+
+    #[test]
+    fn apply_book_ticker_test() {
+        let mut book: OrderBook = OrderBook::default();
+        assert_eq!(book.get_ticker_best_bid(), None);
+        assert_eq!(book.get_ticker_best_ask(), None);
+
+        let text = r#"{"e":"bookTicker","u":400900217,"E":1568014460893,"T":1568014460891,"s":"BNBUSDT","b":"25.35","B":"31.21","a":"25.36","A":"40.66"}"#;
+        let ticker: BookTicker = serde_json::from_str(text).unwrap();
+        book.apply_book_ticker(&ticker).unwrap();
+
+        assert_eq!(
+            book.get_ticker_best_bid().unwrap(),
+            Level {
+                quantity: number("31.21"),
+                price: number("25.35")
+            }
+        );
+        assert_eq!(
+            book.get_ticker_best_ask().unwrap(),
+            Level {
+                quantity: number("40.66"),
+                price: number("25.36")
+            }
+        );
+    }
+
+    #[test]
+    fn apply_websocket_update_sequence_test() {
+        fn update(first: u64, last: u64, previous: u64, bid_price: &str) -> BookDepthUpdate {
+            BookDepthUpdate {
+                e: "depthUpdate".to_string(),
+                E: 0,
+                T: 0,
+                s: "BTCUSDT".to_string(),
+                U: first,
+                u: last,
+                pu: Some(previous),
+                b: vec![LevelApi {
+                    price: bid_price.to_string(),
+                    quantity: "1".to_string(),
+                }],
+                a: vec![],
+            }
+        }
+
+        let mut book = OrderBook::new(5, "btcusdt".to_string());
+        book.apply_full_book_from_http_api(&FullBook {
+            last_update_id: 100,
+            bids: vec![],
+            asks: vec![],
+        })
+        .unwrap();
+
+        // 1) events finished before the full book are dropped
+        assert_eq!(
+            book.apply_depth_book_update_from_websocket(&update(90, 95, 89, "1")),
+            Ok(ApplyResult::AlreadyApplied)
+        );
+        assert_eq!(book.bid.len(), 0);
+        assert_eq!(book.last_update_id, 100);
+
+        // 2) first processed event brackets lastUpdateId
+        assert_eq!(
+            book.apply_depth_book_update_from_websocket(&update(96, 105, 95, "2")),
+            Ok(ApplyResult::Applied)
+        );
+        assert_eq!(book.last_update_id, 105);
+
+        // 3) next events are chained by pu, U > lastUpdateId is fine now
+        assert_eq!(
+            book.apply_depth_book_update_from_websocket(&update(106, 110, 105, "3")),
+            Ok(ApplyResult::Applied)
+        );
+        assert_eq!(book.last_update_id, 110);
+        assert_eq!(book.get_best_bid().unwrap().price, number("3.0"));
+        assert_eq!(book.bid.len(), 2);
+
+        // 4) gap in the sequence requires a new full book
+        assert_eq!(
+            book.apply_depth_book_update_from_websocket(&update(112, 115, 111, "4")),
+            Err(ApplyError::SequenceGap {
+                expected: 110,
+                got: 111
+            })
+        );
+        assert_eq!(book.last_update_id, 110);
+    }
+
+    #[test]
+    fn apply_websocket_update_first_event_after_full_book_test() {
+        let mut book = OrderBook::new(5, "btcusdt".to_string());
+        book.apply_full_book_from_http_api(&FullBook {
+            last_update_id: 100,
+            bids: vec![],
+            asks: vec![],
+        })
+        .unwrap();
+
+        // first event started after lastUpdateId, some updates are missed
+        let late_update = BookDepthUpdate {
+            e: "depthUpdate".to_string(),
+            E: 0,
+            T: 0,
+            s: "BTCUSDT".to_string(),
+            U: 102,
+            u: 105,
+            pu: Some(101),
+            b: vec![],
+            a: vec![],
+        };
+        assert_eq!(
+            book.apply_depth_book_update_from_websocket(&late_update),
+            Err(ApplyError::NeedsSnapshot {
+                last_update_id: 100,
+                first: 102,
+                last: 105
+            })
+        );
+    }
+
+    #[test]
+    fn apply_websocket_update_first_and_second_event_test() {
+        fn update(first: u64, last: u64, previous: u64) -> BookDepthUpdate {
+            BookDepthUpdate {
+                e: "depthUpdate".to_string(),
+                E: 0,
+                T: 0,
+                s: "BTCUSDT".to_string(),
+                U: first,
+                u: last,
+                pu: Some(previous),
+                b: vec![],
+                a: vec![],
+            }
+        }
+        let full_book = FullBook {
+            last_update_id: 100,
+            bids: vec![],
+            asks: vec![],
+        };
+        let mut book = OrderBook::new(5, "btcusdt".to_string()).with_market(Market::Futures);
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+        assert!(book.is_just_initialised);
 
-        // 1) our original book is too old with last_update_id == 0, update should return false
-        assert_eq!(succ, Err(()));
+        // already applied and rejected events keep waiting for the first one
+        assert_eq!(
+            book.apply_depth_book_update_from_websocket(&update(80, 90, 0)),
+            Ok(ApplyResult::AlreadyApplied)
+        );
+        assert!(book.is_just_initialised);
+        assert!(book
+            .apply_depth_book_update_from_websocket(&update(101, 105, 100))
+            .is_err());
+        assert!(book.is_just_initialised);
 
-        // 2) if book already applied update, then nothing should be done
-        book.last_update_id = 100501;
+        // first event pu isn't chained to anything, 0 is fine
+        assert_eq!(
+            book.apply_depth_book_update_from_websocket(&update(95, 105, 0)),
+            Ok(ApplyResult::Applied)
+        );
+        assert!(!book.is_just_initialised);
 
-        let succ = book.apply_depth_book_update_from_websocket(&ws_book);
+        // second event has to be chained by pu
+        assert_eq!(
+            book.apply_depth_book_update_from_websocket(&update(106, 110, 0)),
+            Err(ApplyError::SequenceGap {
+                expected: 105,
+                got: 0
+            })
+        );
+        assert!(!book.is_just_initialised);
+        assert_eq!(
+            book.apply_depth_book_update_from_websocket(&update(106, 110, 105)),
+            Ok(ApplyResult::Applied)
+        );
 
-        assert_eq!(succ, Ok(()));
-        assert_eq!(book.bid.len(), 0);
-        assert_eq!(book.ask.len(), 0);
-        assert_eq!(book.last_update_id, 100501);
+        // new full book waits for the first event again
+        book.apply_full_book_from_http_api(&FullBook {
+            last_update_id: 200,
+            ..full_book
+        })
+        .unwrap();
+        assert!(book.is_just_initialised);
+        assert_eq!(
+            book.apply_depth_book_update_from_websocket(&update(199, 201, 0)),
+            Ok(ApplyResult::Applied)
+        );
+        assert!(!book.is_just_initialised);
+    }
 
-        // 3) we are in range for update between U <= last_update_id <= u
+    #[test]
+    fn first_event_valid_test() {
+        fn update(first: u64, last: u64) -> BookDepthUpdate {
+            BookDepthUpdate {
+                e: "depthUpdate".to_string(),
+                E: 0,
+                T: 0,
+                s: "BTCUSDT".to_string(),
+                U: first,
+                u: last,
+                pu: None,
+                b: vec![],
+                a: vec![],
+            }
+        }
+        let full_book = FullBook {
+            last_update_id: 100,
+            bids: vec![],
+            asks: vec![],
+        };
 
-        book.last_update_id = 100499;
+        let mut spot = OrderBook::new(5, "btcusdt".to_string()).with_market(Market::Spot);
+        spot.apply_full_book_from_http_api(&full_book).unwrap();
+        assert!(spot.first_event_valid(&update(101, 101)));
+        assert!(spot.first_event_valid(&update(100, 101)));
+        assert!(spot.first_event_valid(&update(90, 110)));
+        assert!(!spot.first_event_valid(&update(102, 110)));
+        assert!(!spot.first_event_valid(&update(90, 100)));
 
-        let succ = book.apply_depth_book_update_from_websocket(&ws_book);
+        let mut futures = OrderBook::new(5, "btcusdt".to_string()).with_market(Market::Futures);
+        futures.apply_full_book_from_http_api(&full_book).unwrap();
+        assert!(futures.first_event_valid(&update(100, 100)));
+        assert!(futures.first_event_valid(&update(90, 100)));
+        assert!(futures.first_event_valid(&update(100, 110)));
+        assert!(!futures.first_event_valid(&update(101, 110)));
+        assert!(!futures.first_event_valid(&update(90, 99)));
 
-        assert_eq!(succ, Ok(()));
-        assert_eq!(book.bid.len(), 3);
-        assert_eq!(book.ask.len(), 3);
-        assert_eq!(book.last_update_id, 100500);
+        // spot event starting right after lastUpdateId + 1 asks for a new full book
+        assert_eq!(
+            spot.apply_depth_book_update_from_websocket(&update(102, 110)),
+            Err(ApplyError::NeedsSnapshot {
+                last_update_id: 100,
+                first: 102,
+                last: 110
+            })
+        );
+        assert_eq!(
+            spot.apply_depth_book_update_from_websocket(&update(101, 110)),
+            Ok(ApplyResult::Applied)
+        );
+    }
+
+    #[test]
+    fn apply_websocket_update_spot_sequence_test() {
+        fn update(first: u64, last: u64) -> BookDepthUpdate {
+            BookDepthUpdate {
+                e: "depthUpdate".to_string(),
+                E: 0,
+                T: 0,
+                s: "BNBBTC".to_string(),
+                U: first,
+                u: last,
+                pu: None,
+                b: vec![],
+                a: vec![],
+            }
+        }
+
+        let mut book = OrderBook::new(5, "bnbbtc".to_string()).with_market(Market::Spot);
+        book.apply_full_book_from_http_api(&FullBook {
+            last_update_id: 100,
+            bids: vec![],
+            asks: vec![],
+        })
+        .unwrap();
 
+        // 1) event finished at lastUpdateId is already in the full book
         assert_eq!(
-            book.get_best_bid().unwrap(),
+            book.apply_depth_book_update_from_websocket(&update(95, 100)),
+            Ok(ApplyResult::AlreadyApplied)
+        );
+
+        // 2) first processed event brackets lastUpdateId + 1
+        assert_eq!(
+            book.apply_depth_book_update_from_websocket(&update(101, 105)),
+            Ok(ApplyResult::Applied)
+        );
+
+        // 3) next events start right after the previous one
+        assert_eq!(
+            book.apply_depth_book_update_from_websocket(&update(106, 110)),
+            Ok(ApplyResult::Applied)
+        );
+        assert_eq!(book.last_update_id, 110);
+
+        // 4) gap
+        assert_eq!(
+            book.apply_depth_book_update_from_websocket(&update(112, 115)),
+            Err(ApplyError::SequenceGap {
+                expected: 111,
+                got: 112
+            })
+        );
+
+        // futures rule is used for futures book
+        let mut book = OrderBook::new(5, "btcusdt".to_string()).with_market(Market::Futures);
+        book.apply_full_book_from_http_api(&FullBook {
+            last_update_id: 100,
+            bids: vec![],
+            asks: vec![],
+        })
+        .unwrap();
+        assert_eq!(
+            book.apply_depth_book_update_from_websocket(&update(101, 105)),
+            Err(ApplyError::NeedsSnapshot {
+                last_update_id: 100,
+                first: 101,
+                last: 105
+            })
+        );
+    }
+
+    #[test]
+    fn btree_book_matches_vec_book_test() {
+        let mut vec_book = OrderBook::new(3, "btcusdt".to_string());
+        let mut btree_book = BTreeOrderBook::with_side(3, "btcusdt".to_string());
+        let full_book: FullBook = serde_json::from_str(
+            r#"{"lastUpdateId":10,"bids":[["10","1"],["9","1"],["8","1"]],"asks":[["11","1"],["12","1"],["13","1"]]}"#,
+        )
+        .unwrap();
+        vec_book.apply_full_book_from_http_api(&full_book).unwrap();
+        btree_book
+            .apply_full_book_from_http_api(&full_book)
+            .unwrap();
+
+        let updates = [
+            r#"{"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":5,"u":15,"pu":4,"b":[["9.5","2"],["10","3"]],"a":[["10.5","1"]]}"#,
+            r#"{"e":"depthUpdate","E":2,"T":2,"s":"BTCUSDT","U":16,"u":20,"pu":15,"b":[["7","1"]],"a":[["11","4"],["14","1"]]}"#,
+        ];
+        for update in updates {
+            let update: BookDepthUpdate = serde_json::from_str(update).unwrap();
+            assert_eq!(
+                vec_book.apply_depth_book_update_from_websocket(&update),
+                btree_book.apply_depth_book_update_from_websocket(&update)
+            );
+        }
+
+        let vec_snapshot = vec_book.snapshot();
+        let btree_snapshot = btree_book.snapshot();
+        assert_eq!(vec_snapshot.bids, btree_snapshot.bids);
+        assert_eq!(vec_snapshot.asks, btree_snapshot.asks);
+        assert_eq!(btree_snapshot.bids.len(), 3);
+        assert_eq!(btree_book.get_best_bid().unwrap().price, number("10.0"));
+        assert_eq!(btree_book.get_best_ask().unwrap().price, number("10.5"));
+        assert_eq!(vec_book.to_string(), btree_book.to_string());
+    }
+
+    #[test]
+    fn unsorted_full_book_test() {
+        let mut book: OrderBook = OrderBook::new(3, "btcusdt".to_string());
+        let full_book: FullBook = serde_json::from_str(
+            r#"{"lastUpdateId":10,"bids":[["8","1"],["10","2"],["9","3"],["7","4"]],"asks":[["13","1"],["11","2"],["14","3"],["12","4"]]}"#,
+        )
+        .unwrap();
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+
+        let (bids, asks) = book.get_depth(5);
+        let prices = |levels: Vec<Level>| -> Vec<Number> {
+            levels.iter().map(|level| level.price).collect()
+        };
+        assert_eq!(
+            prices(bids),
+            vec![number("10.0"), number("9.0"), number("8.0")]
+        );
+        assert_eq!(
+            prices(asks),
+            vec![number("11.0"), number("12.0"), number("13.0")]
+        );
+        assert_eq!(book.get_best_bid().unwrap().quantity, number("2.0"));
+
+        // binary search of later updates finds the levels
+        let update: BookDepthUpdate = serde_json::from_str(
+            r#"{"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":5,"u":15,"pu":4,"b":[["9","5"]],"a":[["12","6"]]}"#,
+        )
+        .unwrap();
+        book.apply_depth_book_update_from_websocket(&update)
+            .unwrap();
+        let (bids, asks) = book.get_depth(5);
+        assert_eq!(
+            bids[1],
             Level {
-                quantity: 1.0,
-                price: 5.0
+                price: number("9.0"),
+                quantity: number("5.0")
             }
         );
         assert_eq!(
-            book.get_best_ask().unwrap(),
+            asks[1],
+            Level {
+                price: number("12.0"),
+                quantity: number("6.0")
+            }
+        );
+        assert_eq!(asks.len(), 3);
+
+        let mut levels = vec![
             Level {
-                quantity: 1.0,
-                price: 6.0
+                price: number("1.0"),
+                quantity: number("1.0"),
+            },
+            Level {
+                price: number("2.0"),
+                quantity: number("1.0"),
+            },
+        ];
+        assert!(sort_levels(&mut levels, true));
+        assert!(!sort_levels(&mut levels, false));
+        assert_eq!(levels[0].price, number("2.0"));
+    }
+
+    #[test]
+    fn diff_test() {
+        let book = |bids: &[[&str; 2]], asks: &[[&str; 2]]| test_book("btcusdt", bids, asks);
+        let first = book(&[["10", "1"], ["9", "2"]], &[["11", "1"]]);
+        assert!(first == book(&[["9", "2"], ["10", "1"]], &[["11", "1"]]));
+
+        // one level differs
+        let second = book(&[["10", "1"], ["9", "3"]], &[["11", "1"]]);
+        assert!(first != second);
+        assert_eq!(
+            first.diff(&second),
+            vec![LevelChange::Modified {
+                side: Side::Bid,
+                price: number("9.0"),
+                old_quantity: number("2.0"),
+                new_quantity: number("3.0")
+            }]
+        );
+        assert_eq!(first.diff(&first), vec![]);
+
+        let third = book(&[["9.5", "4"], ["9", "2"]], &[["11", "1"], ["12", "5"]]);
+        let level = |price, quantity| Level {
+            price: number(price),
+            quantity: number(quantity),
+        };
+        assert_eq!(
+            first.diff(&third),
+            vec![
+                LevelChange::Removed(Side::Bid, level("10.0", "1.0")),
+                LevelChange::Added(Side::Bid, level("9.5", "4.0")),
+                LevelChange::Added(Side::Ask, level("12.0", "5.0")),
+            ]
+        );
+        assert_eq!(
+            third.diff(&first),
+            vec![
+                LevelChange::Added(Side::Bid, level("10.0", "1.0")),
+                LevelChange::Removed(Side::Bid, level("9.5", "4.0")),
+                LevelChange::Removed(Side::Ask, level("12.0", "5.0")),
+            ]
+        );
+
+        // quantities are compared as reconcile does, computed and parsed ones are equal
+        let computed = [Level {
+            price: number("10.0"),
+            quantity: number("0.1") + number("0.2"),
+        }];
+        let parsed = [level("10.0", "0.3")];
+        assert_eq!(
+            side_changes(Side::Bid, computed.into_iter(), parsed.into_iter(), false),
+            vec![]
+        );
+    }
+
+    /// levels ordered by price, then by quantity
+    fn sorted_levels() -> Vec<Level> {
+        let level = |price, quantity| Level {
+            price: number(price),
+            quantity: number(quantity),
+        };
+        vec![
+            level("-1.0", "5.0"),
+            level("0.5", "1.0"),
+            level("10.0", "1.0"),
+            level("10.0", "2.0"),
+            level("99.99", "0.1"),
+            level("100.0", "3.0"),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn level_sort_test(mut levels in Just(sorted_levels()).prop_shuffle()) {
+            let sorted = sorted_levels();
+            levels.sort();
+            prop_assert_eq!(&levels, &sorted);
+            // bids go from the highest price
+            levels.sort_by(|a, b| b.cmp(a));
+            prop_assert_eq!(levels, sorted.iter().rev().copied().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn level_ord_test() {
+        let level = |price, quantity| Level {
+            price: number(price),
+            quantity: number(quantity),
+        };
+        let sorted = sorted_levels();
+        assert_eq!(sorted.iter().max(), Some(&level("100.0", "3.0")));
+        assert!(level("10.0", "1.0") < level("10.0", "2.0"));
+        assert_eq!(level("10.0", "1.0"), level("10.0", "1.0"));
+
+        // levels are found by price only, whichever the quantity
+        assert_eq!(look_for_level(number("10.0"), &sorted[..3], true), Ok(2));
+        assert_eq!(look_for_level(number("5.0"), &sorted, true), Err(2));
+        let bids: Vec<Level> = sorted.iter().rev().copied().collect();
+        assert_eq!(look_for_level(number("99.99"), &bids, false), Ok(1));
+        assert_eq!(look_for_level(number("50.0"), &bids, false), Err(2));
+    }
+
+    #[test]
+    fn invalid_number_test() {
+        let mut book = OrderBook::new(5, "btcusdt".to_string());
+        let full_book: FullBook =
+            serde_json::from_str(r#"{"lastUpdateId":10,"bids":[["10","1"]],"asks":[["x","1"]]}"#)
+                .unwrap();
+        assert_eq!(
+            book.apply_full_book_from_http_api(&full_book),
+            Err(ApplyError::InvalidNumber("x".to_string()))
+        );
+        assert!(book.get_best_bid().is_err());
+
+        let full_book: FullBook =
+            serde_json::from_str(r#"{"lastUpdateId":10,"bids":[["10","1"]],"asks":[]}"#).unwrap();
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+
+        // the valid bid isn't applied either
+        let update: BookDepthUpdate = serde_json::from_str(
+            r#"{"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":5,"u":15,"pu":4,"b":[["9","1"]],"a":[["abc","1"]]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            book.apply_depth_book_update_from_websocket(&update),
+            Err(ApplyError::InvalidNumber("abc".to_string()))
+        );
+        assert_eq!(book.get_last_update_id(), 10);
+        assert_eq!(book.snapshot().bids.len(), 1);
+    }
+
+    #[test]
+    fn parse_number_test() {
+        assert_eq!(parse_number("1E-5"), Ok(number("0.00001")));
+        assert_eq!(parse_number("1e-5"), Ok(number("0.00001")));
+        assert_eq!(parse_number("2.5E+3"), Ok(number("2500")));
+        assert_eq!(parse_number("0.00001000"), Ok(number("0.00001")));
+        assert_eq!(parse_number("100.00000000"), Ok(number("100")));
+        assert_eq!(parse_number(" 10.5 "), Ok(number("10.5")));
+        assert_eq!(parse_number("\t10.5\n"), Ok(number("10.5")));
+        for invalid in ["", " ", "abc", "1,5", "NaN", "inf", "-inf"] {
+            assert_eq!(
+                parse_number(invalid),
+                Err(ApplyError::InvalidNumber(invalid.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn mid_ema_test() {
+        let mut book = OrderBook::new(5, "btcusdt".to_string()).with_ema_alpha(0.5);
+        let full_book: FullBook =
+            serde_json::from_str(r#"{"lastUpdateId":1,"bids":[["9","1"]],"asks":[["11","1"]]}"#)
+                .unwrap();
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+        assert_eq!(book.get_mid_ema(), None);
+
+        // the first applied update starts the average
+        let update: BookDepthUpdate = serde_json::from_str(
+            r#"{"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":1,"u":2,"pu":0,"b":[],"a":[]}"#,
+        )
+        .unwrap();
+        book.apply_depth_book_update_from_websocket(&update)
+            .unwrap();
+        assert_eq!(book.get_mid_ema(), Some(10.0));
+
+        // mid jumps to 20 and stays there, the average converges to it
+        let mut emas = vec![];
+        for _ in 0..4 {
+            book.bid = vec![Level {
+                price: number("19.0"),
+                quantity: number("1.0"),
+            }];
+            book.ask = vec![Level {
+                price: number("21.0"),
+                quantity: number("1.0"),
+            }];
+            book.trim();
+            book.update_mid_ema();
+            emas.push(book.get_mid_ema().unwrap());
+        }
+        assert_eq!(emas, vec![15.0, 17.5, 18.75, 19.375]);
+        assert!(book.to_string().contains("|  ema:    19.375000  |"));
+    }
+
+    #[test]
+    fn get_spread_bps_test() {
+        let mut book = OrderBook::new(5, "btcusdt".to_string());
+        assert_eq!(book.get_spread_bps(), None);
+
+        let full_book: FullBook =
+            serde_json::from_str(r#"{"lastUpdateId":1,"bids":[["99","1"]],"asks":[["101","1"]]}"#)
+                .unwrap();
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+        // spread 2 on mid 100
+        assert_eq!(book.get_spread_bps(), Some(200.0));
+        assert!(book.to_string().contains("| spread bps:   200.00 |"));
+
+        let full_book: FullBook =
+            serde_json::from_str(r#"{"lastUpdateId":1,"bids":[["-1","1"]],"asks":[["1","1"]]}"#)
+                .unwrap();
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+        assert_eq!(book.get_spread_bps(), None);
+    }
+
+    #[test]
+    fn cached_top_test() {
+        let mut book = OrderBook::new(5, "btcusdt".to_string());
+        assert_eq!(book.get_mid(), None);
+        assert_eq!(book.get_microprice(), None);
+
+        let full_book: FullBook = serde_json::from_str(
+            r#"{"lastUpdateId":1,"bids":[["99","3"],["98","1"]],"asks":[["101","1"]]}"#,
+        )
+        .unwrap();
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+        assert_eq!(book.get_mid(), Some(number("100")));
+        assert_eq!(book.get_spread(), Some(number("2")));
+        // 3 bid against 1 ask pushes it towards the ask
+        assert_eq!(book.get_microprice(), Some(number("100.5")));
+
+        // a better ask, equal best quantities
+        let update: BookDepthUpdate = serde_json::from_str(
+            r#"{"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":1,"u":2,"pu":0,"b":[["99","2"]],"a":[["100","2"]]}"#,
+        )
+        .unwrap();
+        book.apply_depth_book_update_from_websocket(&update)
+            .unwrap();
+        assert_eq!(book.get_mid(), book.compute_top().map(|top| top.mid));
+        assert_eq!(book.get_mid(), Some(number("99.5")));
+        assert_eq!(book.get_spread(), Some(number("1")));
+        assert_eq!(book.get_microprice(), Some(number("99.5")));
+    }
+
+    #[test]
+    fn get_depth_test() {
+        let mut book = OrderBook::new(5, "btcusdt".to_string());
+        let full_book: FullBook = serde_json::from_str(
+            r#"{"lastUpdateId":1,"bids":[["10","1"],["9","2"],["8","3"]],"asks":[["11","4"]]}"#,
+        )
+        .unwrap();
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+
+        let (bids, asks) = book.get_depth(2);
+        assert_eq!(
+            bids.iter().map(|level| level.price).collect::<Vec<_>>(),
+            vec![number("10.0"), number("9.0")]
+        );
+        assert_eq!(asks.len(), 1);
+        assert_eq!(asks[0].quantity, number("4.0"));
+
+        // capped at available depth
+        let (bids, asks) = book.get_depth(10);
+        assert_eq!((bids.len(), asks.len()), (3, 1));
+        assert_eq!(bids[2].price, number("8.0"));
+    }
+
+    #[test]
+    fn total_quantity_and_notional_test() {
+        let mut book = OrderBook::new(5, "btcusdt".to_string());
+        assert_eq!(book.total_quantity(Side::Bid), Some(number("0.0")));
+        assert_eq!(book.total_notional(Side::Ask), Some(number("0.0")));
+
+        let full_book: FullBook = serde_json::from_str(
+            r#"{"lastUpdateId":1,"bids":[["10","1"],["9","2"],["8","0.5"]],"asks":[["11","4"],["12","1.5"]]}"#,
+        )
+        .unwrap();
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+
+        assert_eq!(book.total_quantity(Side::Bid), Some(number("3.5")));
+        assert_eq!(book.total_notional(Side::Bid), Some(number("32.0")));
+        assert_eq!(book.total_quantity(Side::Ask), Some(number("5.5")));
+        assert_eq!(book.total_notional(Side::Ask), Some(number("62.0")));
+    }
+
+    #[test]
+    fn liquidity_within_bps_test() {
+        let mut book = OrderBook::new(5, "btcusdt".to_string());
+        assert_eq!(
+            book.liquidity_within_bps(Side::Bid, 100.0),
+            Some(number("0.0"))
+        );
+
+        // mid 100, 50 bps band is [99.5, 100.5]
+        let full_book: FullBook = serde_json::from_str(
+            r#"{"lastUpdateId":1,"bids":[["99.9","1"],["99.5","2"],["99","4"]],"asks":[["100.1","3"],["100.4","5"],["101","7"]]}"#,
+        )
+        .unwrap();
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+
+        assert_eq!(
+            book.liquidity_within_bps(Side::Bid, 50.0),
+            Some(number("3.0"))
+        );
+        assert_eq!(
+            book.liquidity_within_bps(Side::Ask, 50.0),
+            Some(number("8.0"))
+        );
+        assert_eq!(
+            book.liquidity_within_bps(Side::Ask, 5.0),
+            Some(number("0.0"))
+        );
+        assert_eq!(
+            book.liquidity_within_bps(Side::Bid, 1000.0),
+            Some(number("7.0"))
+        );
+    }
+
+    #[test]
+    fn large_levels_test() {
+        let mut book = OrderBook::new(10, "btcusdt".to_string());
+        assert!(book.large_levels(Side::Bid, 2.0).is_empty());
+
+        // bid average is 10.5, ask average is 2
+        let full_book: FullBook = serde_json::from_str(
+            r#"{"lastUpdateId":1,"bids":[["10","1"],["9","2"],["8","38"],["7","1"]],"asks":[["11","2"],["12","2"]]}"#,
+        )
+        .unwrap();
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+
+        assert_eq!(
+            book.large_levels(Side::Bid, 3.0),
+            vec![Level {
+                price: number("8.0"),
+                quantity: number("38.0")
+            }]
+        );
+        assert!(book.large_levels(Side::Bid, 4.0).is_empty());
+        assert!(book.large_levels(Side::Ask, 1.0).is_empty());
+        assert_eq!(book.large_levels(Side::Ask, 0.5).len(), 2);
+    }
+
+    #[test]
+    fn volume_profile_test() {
+        let mut book = OrderBook::new(10, "btcusdt".to_string());
+        assert!(book.volume_profile(Side::Bid, number("1")).is_empty());
+
+        let full_book: FullBook = serde_json::from_str(
+            r#"{"lastUpdateId":1,"bids":[["99.9","1"],["99.5","2"],["98.7","4"],["96","0.5"]],"asks":[["100.1","3"],["100.4","5"],["101","7"],["102.5","1"]]}"#,
+        )
+        .unwrap();
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+
+        assert_eq!(
+            book.volume_profile(Side::Bid, number("1")),
+            vec![
+                (number("99.0"), number("3.0")),
+                (number("98.0"), number("4.0")),
+                (number("96.0"), number("0.5"))
+            ]
+        );
+        assert_eq!(
+            book.volume_profile(Side::Ask, number("1")),
+            vec![
+                (number("100.0"), number("8.0")),
+                (number("101.0"), number("7.0")),
+                (number("102.0"), number("1.0"))
+            ]
+        );
+        assert_eq!(
+            book.volume_profile(Side::Ask, number("5")),
+            vec![(number("100.0"), number("16.0"))]
+        );
+        assert_eq!(
+            book.volume_profile(Side::Bid, number("5")),
+            vec![(number("95.0"), number("7.5"))]
+        );
+        assert!(book.volume_profile(Side::Bid, ZERO).is_empty());
+    }
+
+    #[test]
+    fn to_colored_table_test() {
+        let mut book = OrderBook::new(2, "btcusdt".to_string());
+        let full_book: FullBook = serde_json::from_str(
+            r#"{"lastUpdateId":1,"bids":[["10","1"],["9","2"]],"asks":[["11","4"]]}"#,
+        )
+        .unwrap();
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+
+        let plain = book.to_string();
+        assert!(!plain.contains('\x1b'));
+
+        let colored = book.to_colored_table(&|_, level| level.price == number("9.0"));
+        assert!(colored.contains("|\x1b[32m         1|        10\x1b[0m|"));
+        assert!(colored.contains("|\x1b[1m\x1b[32m         2|         9\x1b[0m|"));
+        assert!(colored.contains("|\x1b[31m         4|        11\x1b[0m|"));
+        // same table without the colors
+        let stripped = [ANSI_GREEN, ANSI_RED, ANSI_BOLD, ANSI_RESET]
+            .iter()
+            .fold(colored, |table, code| table.replace(code, ""));
+        assert_eq!(stripped, plain);
+    }
+
+    /// ticks of 0.1 price, bids are below 50, asks above, so a correct book is never crossed
+    fn ticks(is_bid: bool) -> std::ops::RangeInclusive<u32> {
+        match is_bid {
+            true => 1..=500,
+            false => 501..=1000,
+        }
+    }
+
+    /// (tick, quantity in 0.001) levels as binance sends them
+    fn api_levels(levels: impl IntoIterator<Item = (u32, u32)>) -> Vec<LevelApi> {
+        levels
+            .into_iter()
+            .map(|(tick, quantity)| LevelApi {
+                price: format!("{:.1}", tick as f64 / 10.0),
+                quantity: format!("{:.3}", quantity as f64 / 1000.0),
+            })
+            .collect()
+    }
+
+    /// side of a full book, prices are unique and quantities aren't zero
+    fn full_book_side(is_bid: bool) -> impl Strategy<Value = BTreeMap<u32, u32>> {
+        prop::collection::btree_map(ticks(is_bid), 1..10_000u32, 0..30)
+    }
+
+    fn full_book(
+        last_update_id: u64,
+        bids: &BTreeMap<u32, u32>,
+        asks: &BTreeMap<u32, u32>,
+    ) -> FullBook {
+        FullBook {
+            last_update_id,
+            bids: api_levels(bids.iter().rev().map(|(tick, quantity)| (*tick, *quantity))),
+            asks: api_levels(asks.iter().map(|(tick, quantity)| (*tick, *quantity))),
+        }
+    }
+
+    /// levels of an update side, zero quantity removes the level, one of 5 is zero
+    fn update_side(is_bid: bool) -> impl Strategy<Value = Vec<(u32, u32)>> {
+        let quantity = prop_oneof![1 => Just(0), 4 => 1..10_000u32];
+        prop::collection::vec((ticks(is_bid), quantity), 0..10)
+    }
+
+    fn update(
+        first: u64,
+        last: u64,
+        previous: u64,
+        bids: &[(u32, u32)],
+        asks: &[(u32, u32)],
+    ) -> BookDepthUpdate {
+        BookDepthUpdate {
+            e: "depthUpdate".to_string(),
+            E: 1,
+            T: 1,
+            s: "BTCUSDT".to_string(),
+            U: first,
+            u: last,
+            pu: Some(previous),
+            b: api_levels(bids.iter().copied()),
+            a: api_levels(asks.iter().copied()),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn snapshot_consistent_test(
+            bids in full_book_side(true),
+            asks in full_book_side(false),
+            updates in prop::collection::vec((update_side(true), update_side(false)), 0..100),
+        ) {
+            let mut book = OrderBook::new(10, "btcusdt".to_string());
+            book.apply_full_book_from_http_api(&full_book(100, &bids, &asks))
+                .unwrap();
+            for (step, (bids, asks)) in updates.iter().enumerate() {
+                let last_update_id = 100 + step as u64;
+                book.apply_depth_book_update_from_websocket(&update(
+                    last_update_id,
+                    last_update_id + 1,
+                    last_update_id,
+                    bids,
+                    asks,
+                ))
+                .unwrap();
+
+                let snapshot = book.snapshot();
+                prop_assert_eq!(snapshot.best_bid, snapshot.bids.first().copied(), "{}", step);
+                prop_assert_eq!(snapshot.best_ask, snapshot.asks.first().copied(), "{}", step);
+                match (snapshot.best_bid, snapshot.best_ask) {
+                    (Some(bid), Some(ask)) => {
+                        let mid = (bid.price + ask.price) / Number::from(2);
+                        prop_assert!(numbers_equal(snapshot.mid.unwrap(), mid), "{}", step);
+                        prop_assert_eq!(snapshot.spread, Some(ask.price - bid.price), "{}", step);
+                    }
+                    _ => prop_assert_eq!((snapshot.mid, snapshot.spread), (None, None), "{}", step),
+                }
             }
+        }
+    }
+
+    /// sides are strictly ordered, so without duplicated prices, trimmed and not crossed
+    fn assert_book_invariants<S: BookSide>(book: &OrderBook<S>, case: &str) {
+        let bids: Vec<Level> = book.bid.levels(false).collect();
+        let asks: Vec<Level> = book.ask.levels(true).collect();
+        assert!(
+            bids.windows(2).all(|pair| pair[0].price > pair[1].price),
+            "{case}: bids aren't strictly descending {bids:?}"
+        );
+        assert!(
+            asks.windows(2).all(|pair| pair[0].price < pair[1].price),
+            "{case}: asks aren't strictly ascending {asks:?}"
         );
+        assert!(
+            bids.len() <= book.levels as usize,
+            "{case}: bids aren't trimmed"
+        );
+        assert!(
+            asks.len() <= book.levels as usize,
+            "{case}: asks aren't trimmed"
+        );
+        assert!(
+            bids.iter()
+                .chain(asks.iter())
+                .all(|level| !numbers_equal(level.quantity, ZERO)),
+            "{case}: zero quantity level {bids:?} {asks:?}"
+        );
+        if let (Some(bid), Some(ask)) = (bids.first(), asks.first()) {
+            assert!(
+                bid.price < ask.price,
+                "{case}: book is crossed {bid:?} {ask:?}"
+            );
+        }
+        assert_eq!(book.top, book.compute_top(), "{case}: cached top is stale");
+    }
+
+    /// full book or depth update applied to the book of a sequence
+    #[derive(Clone, Debug)]
+    enum Step {
+        /// full book of a resync, its lastUpdateId is after the skipped ids
+        FullBook {
+            skipped: u64,
+            bids: BTreeMap<u32, u32>,
+            asks: BTreeMap<u32, u32>,
+        },
+        /// update of the ids after the last one, the first update after a full book
+        /// overlaps its lastUpdateId
+        Update {
+            overlap: u64,
+            span: u64,
+            bids: Vec<(u32, u32)>,
+            asks: Vec<(u32, u32)>,
+        },
+    }
+
+    /// one of 20 steps is a full book
+    fn step() -> impl Strategy<Value = Step> {
+        prop_oneof![
+            1 => (0..10u64, full_book_side(true), full_book_side(false))
+                .prop_map(|(skipped, bids, asks)| Step::FullBook { skipped, bids, asks }),
+            19 => (0..3u64, 0..5u64, update_side(true), update_side(false))
+                .prop_map(|(overlap, span, bids, asks)| Step::Update { overlap, span, bids, asks }),
+        ]
+    }
+
+    /// applies the full book and the steps, the book invariants are kept after each of them
+    fn apply_sequence<S: BookSide>(
+        levels: u32,
+        mut last_update_id: u64,
+        bids: &BTreeMap<u32, u32>,
+        asks: &BTreeMap<u32, u32>,
+        steps: &[Step],
+    ) {
+        let mut book: OrderBook<S> = OrderBook::with_side(levels, "btcusdt".to_string());
+        book.apply_full_book_from_http_api(&full_book(last_update_id, bids, asks))
+            .unwrap();
+        let mut is_just_initialised = true;
+
+        for (step, action) in steps.iter().enumerate() {
+            let case = format!("step {step}");
+            match action {
+                Step::FullBook {
+                    skipped,
+                    bids,
+                    asks,
+                } => {
+                    last_update_id += skipped;
+                    book.apply_full_book_from_http_api(&full_book(last_update_id, bids, asks))
+                        .unwrap();
+                    is_just_initialised = true;
+                }
+                Step::Update {
+                    overlap,
+                    span,
+                    bids,
+                    asks,
+                } => {
+                    // the first update after full book contains its lastUpdateId, the others chain
+                    let first = match is_just_initialised {
+                        true => last_update_id.saturating_sub(*overlap).max(1),
+                        false => last_update_id + 1,
+                    };
+                    let last = (first + span).max(last_update_id);
+                    assert_eq!(
+                        book.apply_depth_book_update_from_websocket(&update(
+                            first,
+                            last,
+                            last_update_id,
+                            bids,
+                            asks
+                        )),
+                        Ok(ApplyResult::Applied),
+                        "{case}"
+                    );
+                    last_update_id = last;
+                    is_just_initialised = false;
+                }
+            }
+            assert_book_invariants(&book, &case);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn apply_sequences_test(
+            levels in 1..=20u32,
+            last_update_id in 1..1000u64,
+            bids in full_book_side(true),
+            asks in full_book_side(false),
+            steps in prop::collection::vec(step(), 0..50),
+        ) {
+            apply_sequence::<Vec<Level>>(levels, last_update_id, &bids, &asks, &steps);
+            apply_sequence::<BTreeMap<PriceKey, Number>>(
+                levels,
+                last_update_id,
+                &bids,
+                &asks,
+                &steps,
+            );
+        }
     }
 }
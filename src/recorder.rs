@@ -0,0 +1,178 @@
+use crate::rotating_file::RotatingFile;
+use flate2::read::MultiGzDecoder;
+use std::io::Read;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+/// Records raw websocket text frames as "<receive epoch ms> <frame>" lines for replay
+pub struct Recorder {
+    writer: Mutex<RecorderWriter>,
+}
+
+struct RecorderWriter {
+    file: RotatingFile,
+    // timestamps never go back even if system clock does
+    last_timestamp: u128,
+    // recording stops at the first failed write, e.g. the disk is full, watching goes on
+    failed: bool,
+}
+
+impl Recorder {
+    /// files are rotated above max size bytes if any,
+    /// and gzip compressed to the [record_path] if compress is set
+    pub fn create(path: &str, max_size: Option<u64>, compress: bool) -> std::io::Result<Self> {
+        Ok(Self {
+            writer: Mutex::new(RecorderWriter {
+                file: RotatingFile::create(record_path(path, compress), max_size, compress)?,
+                last_timestamp: 0,
+                failed: false,
+            }),
+        })
+    }
+
+    pub fn record(&self, frame: &str) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        let mut writer = self.writer.lock().unwrap();
+        if writer.failed {
+            return;
+        }
+        writer.last_timestamp = writer.last_timestamp.max(now);
+        let timestamp = writer.last_timestamp;
+        let result = writer.file.write_line(&format!("{} {}", timestamp, frame));
+        writer.stop_on_error(result);
+    }
+
+    pub fn flush(&self) {
+        let mut writer = self.writer.lock().unwrap();
+        if !writer.failed {
+            let result = writer.file.flush();
+            writer.stop_on_error(result);
+        }
+    }
+
+    /// false once recording is stopped by a failed write
+    pub fn is_recording(&self) -> bool {
+        !self.writer.lock().unwrap().failed
+    }
+}
+
+impl RecorderWriter {
+    fn stop_on_error(&mut self, result: std::io::Result<()>) {
+        if let Err(e) = result {
+            error!("Failed to record frames ({e}), recording is stopped");
+            self.failed = true;
+        }
+    }
+}
+
+/// file frames are recorded to, compressed recordings get a .gz suffix like compressed snapshots
+pub fn record_path(path: &str, compress: bool) -> String {
+    match compress && !path.ends_with(".gz") {
+        true => format!("{path}.gz"),
+        false => path.to_string(),
+    }
+}
+
+/// reads a recording, gzip compressed ones are decompressed,
+/// an unfinished one, e.g. of a crashed recorder, is read up to its last flushed line
+pub fn read_recording(path: &str) -> std::io::Result<String> {
+    let mut bytes = std::fs::read(path)?;
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut recording = vec![];
+        match MultiGzDecoder::new(bytes.as_slice()).read_to_end(&mut recording) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                let end = recording.iter().rposition(|&byte| byte == b'\n');
+                recording.truncate(end.map_or(0, |end| end + 1));
+            }
+            Err(e) => return Err(e),
+        }
+        bytes = recording;
+    }
+    String::from_utf8(bytes).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn record_test() {
+        let path =
+            std::env::temp_dir().join(format!("binance_watcher_record_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let recorder = Recorder::create(path, None, false).unwrap();
+        recorder.record(r#"{"e":"depthUpdate","u":1}"#);
+        recorder.record(r#"{"e":"depthUpdate","u":2}"#);
+        recorder.record(r#"{"e":"aggTrade","p":"1"}"#);
+        recorder.flush();
+
+        let content = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 3);
+
+        let timestamps: Vec<u128> = lines
+            .iter()
+            .map(|line| line.split_once(' ').unwrap().0.parse().unwrap())
+            .collect();
+        assert!(timestamps.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(lines[2].ends_with(r#" {"e":"aggTrade","p":"1"}"#));
+    }
+
+    #[test]
+    fn record_compressed_test() {
+        let path =
+            std::env::temp_dir().join(format!("binance_watcher_record_{}.rec", std::process::id()));
+        let path = path.to_str().unwrap();
+        let compressed_path = format!("{path}.gz");
+        assert_eq!(record_path(path, true), compressed_path);
+        assert_eq!(record_path(&compressed_path, true), compressed_path);
+        assert_eq!(record_path(path, false), path);
+        let frames = [
+            r#"{"e":"depthUpdate","u":1}"#,
+            r#"{"e":"depthUpdate","u":2}"#,
+            r#"{"e":"aggTrade","p":"1"}"#,
+        ];
+        let recorded = |recording: String| -> Vec<String> {
+            recording
+                .lines()
+                .map(|line| line.split_once(' ').unwrap().1.to_string())
+                .collect()
+        };
+
+        let recorder = Recorder::create(path, None, true).unwrap();
+        recorder.record(frames[0]);
+        recorder.record(frames[1]);
+        recorder.flush();
+        recorder.record(frames[2]);
+        // flushed frames of an unfinished recording are read
+        let recording = read_recording(&compressed_path).unwrap();
+        assert_eq!(recorded(recording), &frames[..2]);
+
+        recorder.flush();
+        drop(recorder);
+        assert!(!std::path::Path::new(path).exists());
+        let recording = read_recording(&compressed_path).unwrap();
+        std::fs::remove_file(&compressed_path).unwrap();
+        assert_eq!(recorded(recording), frames);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn record_full_disk_test() {
+        let recorder = Recorder::create("/dev/full", None, false).unwrap();
+        assert!(recorder.is_recording());
+        // larger than the write buffer, so it's written at once
+        recorder.record(&"x".repeat(16 * 1024));
+        assert!(!recorder.is_recording());
+        recorder.record(r#"{"e":"depthUpdate","u":1}"#);
+        recorder.flush();
+        assert!(!recorder.is_recording());
+    }
+}
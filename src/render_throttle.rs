@@ -0,0 +1,105 @@
+use crate::number::to_f64;
+use crate::order_book::OrderBook;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+/// Per-symbol rate limiter for book rendering, updates are still applied continuously.
+/// Books which haven't changed since their last render aren't rendered again.
+pub struct RenderThrottle {
+    delay: Duration,
+    last_render: HashMap<String, Instant>,
+    // fingerprints of the last rendered books
+    last_fingerprint: HashMap<String, u64>,
+}
+
+impl RenderThrottle {
+    pub fn new(delay_ms: u32) -> Self {
+        Self {
+            delay: Duration::from_millis(delay_ms as u64),
+            last_render: HashMap::new(),
+            last_fingerprint: HashMap::new(),
+        }
+    }
+
+    /// true if the book has changed since its last render and the delay has passed
+    pub fn should_render_book(&mut self, book: &OrderBook, now: Instant) -> bool {
+        let fingerprint = book_fingerprint(book);
+        if self.last_fingerprint.get(book.get_symbol()) == Some(&fingerprint) {
+            return false;
+        }
+        if !self.should_render(book.get_symbol(), now) {
+            return false;
+        }
+        self.last_fingerprint
+            .insert(book.get_symbol().to_string(), fingerprint);
+        true
+    }
+
+    /// true if the symbol hasn't been rendered for at least the delay, remembers the render time
+    pub fn should_render(&mut self, symbol: &str, now: Instant) -> bool {
+        match self.last_render.get(symbol) {
+            Some(last) if now.duration_since(*last) < self.delay => false,
+            _ => {
+                self.last_render.insert(symbol.to_string(), now);
+                true
+            }
+        }
+    }
+}
+
+/// hash of what's rendered: levels and last trade
+fn book_fingerprint(book: &OrderBook) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let (bids, asks) = book.get_depth(usize::MAX);
+    for level in bids.iter().chain(&asks) {
+        to_f64(level.price).to_bits().hash(&mut hasher);
+        to_f64(level.quantity).to_bits().hash(&mut hasher);
+    }
+    // sides of different lengths with the same levels differ
+    bids.len().hash(&mut hasher);
+    book.get_last_trade()
+        .map(|trade| to_f64(trade).to_bits())
+        .hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::order_book::test_book;
+
+    #[test]
+    fn should_render_test() {
+        let mut throttle = RenderThrottle::new(1000);
+        let start = Instant::now();
+
+        // first render is always allowed
+        assert!(throttle.should_render("btcusdt", start));
+        // suppressed within the window
+        assert!(!throttle.should_render("btcusdt", start + Duration::from_millis(500)));
+        // other symbols are throttled independently
+        assert!(throttle.should_render("ethusdt", start + Duration::from_millis(500)));
+        // allowed again after the window
+        assert!(throttle.should_render("btcusdt", start + Duration::from_millis(1000)));
+        assert!(!throttle.should_render("btcusdt", start + Duration::from_millis(1999)));
+    }
+
+    #[test]
+    fn should_render_book_test() {
+        let mut throttle = RenderThrottle::new(1000);
+        let start = Instant::now();
+        let book = test_book("btcusdt", &[["10", "1"]], &[["11", "1"]]);
+
+        assert!(throttle.should_render_book(&book, start));
+        // identical book isn't rendered again, even after the delay
+        let book = test_book("btcusdt", &[["10", "1"]], &[["11", "1"]]);
+        assert!(!throttle.should_render_book(&book, start + Duration::from_millis(2000)));
+
+        // changed book is rendered after the delay only
+        let book = test_book("btcusdt", &[["10", "2"]], &[["11", "1"]]);
+        assert!(!throttle.should_render_book(&book, start + Duration::from_millis(500)));
+        assert!(throttle.should_render_book(&book, start + Duration::from_millis(2500)));
+    }
+}
@@ -0,0 +1,250 @@
+use crate::console_arguments::OutputFormat;
+use crate::order_book::{Level, OrderBook, Side};
+use crate::render_throttle::RenderThrottle;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time::MissedTickBehavior;
+
+/// Latest versions of the books updated since the last render, shared by handlers and render task
+#[derive(Default)]
+pub struct PendingRenders {
+    books: Mutex<BTreeMap<String, OrderBook>>,
+}
+
+impl PendingRenders {
+    /// replaces the pending version of the book
+    pub fn publish(&self, book: &OrderBook) {
+        self.books
+            .lock()
+            .unwrap()
+            .insert(book.get_symbol().to_string(), book.clone());
+    }
+
+    /// pending books ordered by symbol, nothing is pending afterwards
+    fn take(&self) -> Vec<OrderBook> {
+        let books = std::mem::take(&mut *self.books.lock().unwrap());
+        books.into_values().collect()
+    }
+}
+
+/// How books are rendered by the render task
+#[derive(Clone, Copy, Debug)]
+pub struct RenderOptions {
+    pub delay_ms: u32,
+    pub format: OutputFormat,
+    // clear the screen before books rendered together
+    pub live: bool,
+    pub color: bool,
+}
+
+/// colors are used if requested, unless NO_COLOR is set or output isn't a terminal
+pub fn colors_enabled(requested: bool, no_color: bool, is_terminal: bool) -> bool {
+    requested && !no_color && is_terminal
+}
+
+/// ANSI sequence clearing the terminal and moving the cursor home, empty if disabled
+pub fn clear_screen(enabled: bool) -> &'static str {
+    if enabled {
+        "\x1b[2J\x1b[H"
+    } else {
+        ""
+    }
+}
+
+/// renders pending books every delay ms, so output doesn't slow updates down,
+/// runs until the task is aborted
+pub async fn render_task(
+    pending: Arc<PendingRenders>,
+    options: RenderOptions,
+    mut output: impl FnMut(String),
+) {
+    let delay = Duration::from_millis(options.delay_ms.max(1) as u64);
+    let mut interval = tokio::time::interval(delay);
+    // a late render isn't followed by a burst of renders to catch up
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    // the interval is the throttle, it only skips unchanged books
+    let mut throttle = RenderThrottle::new(0);
+    // last rendered books to highlight changed levels
+    let mut rendered: HashMap<String, OrderBook> = HashMap::new();
+    loop {
+        interval.tick().await;
+        let mut is_first = true;
+        for book in pending.take() {
+            if !throttle.should_render_book(&book, Instant::now()) {
+                continue;
+            }
+            let text = match (options.format, options.color) {
+                (OutputFormat::Table, true) => {
+                    let previous = rendered.get(book.get_symbol());
+                    book.to_colored_table(&|side, level| is_level_changed(previous, side, level))
+                }
+                (format, _) => render_book(&book, format),
+            };
+            // in live mode the screen is cleared before books rendered together
+            let clear = clear_screen(options.live && is_first);
+            output(format!("{clear}{text}"));
+            is_first = false;
+            if options.color {
+                rendered.insert(book.get_symbol().to_string(), book);
+            }
+        }
+    }
+}
+
+/// true if the previous render had another quantity at the level price, or no such level
+fn is_level_changed(previous: Option<&OrderBook>, side: Side, level: &Level) -> bool {
+    let Some(previous) = previous else {
+        return false;
+    };
+    let (bids, asks) = previous.get_depth(usize::MAX);
+    let levels = match side {
+        Side::Bid => bids,
+        Side::Ask => asks,
+    };
+    !levels
+        .iter()
+        .any(|previous| previous.price == level.price && previous.quantity == level.quantity)
+}
+
+pub fn render_book(book: &OrderBook, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Table => book.to_string(),
+        OutputFormat::Json => {
+            serde_json::to_string(&book.snapshot()).expect("Failed to serialize book")
+        }
+        OutputFormat::Csv => book.to_csv_line(),
+        OutputFormat::Compact => book.to_compact_line(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::messages::BookDepthUpdate;
+    use crate::number::number;
+    use crate::order_book::test_book;
+
+    #[test]
+    fn render_book_test() {
+        let mut book = test_book("btcusdt", &[["1", "3"]], &[["2", "4"]]);
+
+        let table = render_book(&book, OutputFormat::Table);
+        assert!(table.contains("ORDER BOOK : btcusdt"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&render_book(&book, OutputFormat::Json)).unwrap();
+        assert_eq!(json["symbol"], "btcusdt");
+        assert_eq!(json["mid"], 1.5);
+        assert_eq!(json["bids"][0]["price"], 1.0);
+        assert_eq!(json["asks"][0]["quantity"], 4.0);
+
+        let csv = render_book(&book, OutputFormat::Csv);
+        let header: Vec<&str> = OrderBook::csv_header().split(',').collect();
+        let values: Vec<&str> = csv.split(',').collect();
+        assert_eq!(header.len(), values.len());
+        assert_eq!(values, vec!["btcusdt", "1", "1.5", "", "1", "3", "2", "4"]);
+
+        let compact = render_book(&book, OutputFormat::Compact);
+        assert_eq!(
+            compact,
+            "BTCUSDT bid=1x3 ask=2x4 mid=1.5 spread=6666.67bps ts=-"
+        );
+
+        let update: BookDepthUpdate = serde_json::from_str(
+            r#"{"e":"depthUpdate","E":1700000000123,"T":1,"s":"BTCUSDT","U":1,"u":11,"pu":0,"b":[["1.5","2"]],"a":[]}"#,
+        )
+        .unwrap();
+        book.apply_depth_book_update_from_websocket(&update)
+            .unwrap();
+        let compact = render_book(&book, OutputFormat::Compact);
+        assert_eq!(
+            compact,
+            "BTCUSDT bid=1.5x2 ask=2x4 mid=1.75 spread=2857.14bps ts=1700000000123"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn render_task_cadence_test() {
+        let pending = Arc::new(PendingRenders::default());
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let renderer = tokio::spawn(render_task(
+            pending.clone(),
+            RenderOptions {
+                delay_ms: 100,
+                format: OutputFormat::Csv,
+                live: false,
+                color: false,
+            },
+            move |text| sender.send((tokio::time::Instant::now(), text)).unwrap(),
+        ));
+
+        // the book changes far more often than it's rendered
+        let started = tokio::time::Instant::now();
+        for quantity in 1..=110 {
+            let book = test_book("btcusdt", &[["10", &quantity.to_string()]], &[]);
+            pending.publish(&book);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        renderer.abort();
+
+        let mut renders = vec![];
+        while let Ok(render) = receiver.try_recv() {
+            renders.push(render);
+        }
+        // rendered at once, then every 100ms of the 550ms
+        let times: Vec<Duration> = renders.iter().map(|(at, _)| *at - started).collect();
+        assert_eq!(
+            times,
+            (0..6)
+                .map(|tick| Duration::from_millis(tick * 100))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn clear_screen_test() {
+        assert_eq!(clear_screen(true), "\x1b[2J\x1b[H");
+        assert_eq!(clear_screen(false), "");
+    }
+
+    #[test]
+    fn colors_enabled_test() {
+        assert!(colors_enabled(true, false, true));
+        assert!(!colors_enabled(false, false, true));
+        // NO_COLOR and pipes disable colors
+        assert!(!colors_enabled(true, true, true));
+        assert!(!colors_enabled(true, false, false));
+    }
+
+    #[test]
+    fn is_level_changed_test() {
+        let book = test_book("btcusdt", &[["10", "1"]], &[["11", "1"]]);
+
+        let level = |price, quantity| Level {
+            price: number(price),
+            quantity: number(quantity),
+        };
+        assert!(!is_level_changed(None, Side::Bid, &level("10.0", "2.0")));
+        assert!(!is_level_changed(
+            Some(&book),
+            Side::Bid,
+            &level("10.0", "1.0")
+        ));
+        assert!(is_level_changed(
+            Some(&book),
+            Side::Bid,
+            &level("10.0", "2.0")
+        ));
+        assert!(is_level_changed(
+            Some(&book),
+            Side::Bid,
+            &level("9.0", "1.0")
+        ));
+        assert!(is_level_changed(
+            Some(&book),
+            Side::Ask,
+            &level("10.0", "1.0")
+        ));
+    }
+}
@@ -0,0 +1,248 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// Buffered lines writer moving on to `{name}.1`, `{name}.2`, ... files
+/// once the current one would get above the max size,
+/// a compressed file is one gzip stream finished on rotation or drop
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: Option<u64>,
+    // appending keeps the content of existing files
+    append: bool,
+    compress: bool,
+    output: Output,
+    rotations: u32,
+}
+
+enum Output {
+    Plain(CountingFile),
+    // lines are compressed into the file as deflate blocks fill up or on flush
+    Gzip(GzEncoder<CountingFile>),
+}
+
+/// bytes written to the file so far, compressed ones for gzip output
+struct CountingFile {
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl Write for CountingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Output {
+    fn size(&self) -> u64 {
+        match self {
+            Output::Plain(file) => file.size,
+            Output::Gzip(encoder) => encoder.get_ref().size,
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Output::Plain(file) => file,
+            Output::Gzip(encoder) => encoder,
+        }
+    }
+
+    /// writes the gzip trailer, the file is complete then
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            Output::Plain(mut file) => file.flush(),
+            Output::Gzip(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl RotatingFile {
+    /// truncates existing files
+    pub fn create(
+        path: impl Into<PathBuf>,
+        max_size: Option<u64>,
+        compress: bool,
+    ) -> std::io::Result<Self> {
+        Self::open(path.into(), max_size, false, compress)
+    }
+
+    /// appends to existing files, compressed ones get another gzip member
+    pub fn append(
+        path: impl Into<PathBuf>,
+        max_size: Option<u64>,
+        compress: bool,
+    ) -> std::io::Result<Self> {
+        Self::open(path.into(), max_size, true, compress)
+    }
+
+    fn open(
+        path: PathBuf,
+        max_size: Option<u64>,
+        append: bool,
+        compress: bool,
+    ) -> std::io::Result<Self> {
+        let output = open_file(&path, append, compress)?;
+        Ok(Self {
+            path,
+            max_size,
+            append,
+            compress,
+            output,
+            rotations: 0,
+        })
+    }
+
+    /// writes the line and a new line, lines are never split over files
+    pub fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.is_full(line.len() as u64 + 1) {
+            self.rotate()?;
+        }
+        let writer = self.output.writer();
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(b"\n")
+    }
+
+    /// compressed lines are sync flushed, readable before the file is finished
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.output.writer().flush()
+    }
+
+    fn is_full(&self, len: u64) -> bool {
+        let Some(max_size) = self.max_size else {
+            return false;
+        };
+        let size = self.output.size();
+        match self.output {
+            // the compressed size of the line isn't known before it's compressed
+            Output::Gzip(_) => size >= max_size,
+            Output::Plain(_) => size > 0 && size + len > max_size,
+        }
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.rotations += 1;
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", self.rotations));
+        let output = open_file(&PathBuf::from(path), self.append, self.compress)?;
+        std::mem::replace(&mut self.output, output).finish()
+    }
+}
+
+fn open_file(path: &PathBuf, append: bool, compress: bool) -> std::io::Result<Output> {
+    let file = if append {
+        OpenOptions::new().create(true).append(true).open(path)?
+    } else {
+        File::create(path)?
+    };
+    let file = CountingFile {
+        size: file.metadata()?.len(),
+        file: BufWriter::new(file),
+    };
+    Ok(match compress {
+        true => Output::Gzip(GzEncoder::new(file, Compression::default())),
+        false => Output::Plain(file),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn rotating_file_test() {
+        let dir =
+            std::env::temp_dir().join(format!("binance_watcher_rotation_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("frames.txt");
+
+        let mut file = RotatingFile::create(&path, Some(10), false).unwrap();
+        // 5 bytes lines, 2 per file
+        for line in ["0001", "0002", "0003", "0004", "0005"] {
+            file.write_line(line).unwrap();
+        }
+        // longer than the max size, still written whole
+        file.write_line("0123456789abc").unwrap();
+        file.flush().unwrap();
+
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("frames.txt"), "0001\n0002\n");
+        assert_eq!(read("frames.txt.1"), "0003\n0004\n");
+        assert_eq!(read("frames.txt.2"), "0005\n");
+        assert_eq!(read("frames.txt.3"), "0123456789abc\n");
+        assert!(!dir.join("frames.txt.4").exists());
+
+        // appending counts what is already in the file
+        let mut file = RotatingFile::append(dir.join("frames.txt.2"), Some(10), false).unwrap();
+        file.write_line("0006").unwrap();
+        file.write_line("0007").unwrap();
+        file.flush().unwrap();
+        assert_eq!(read("frames.txt.2"), "0005\n0006\n");
+        assert_eq!(read("frames.txt.2.1"), "0007\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compressed_file_test() {
+        let dir = std::env::temp_dir().join(format!(
+            "binance_watcher_compressed_rotation_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("frames.gz");
+        // reads a gzip stream up to its end or up to what is flushed of an unfinished one
+        let read = |name: &str| {
+            let bytes = std::fs::read(dir.join(name)).unwrap();
+            let mut lines = vec![];
+            let result = GzDecoder::new(bytes.as_slice()).read_to_end(&mut lines);
+            (String::from_utf8(lines).unwrap(), result.is_ok())
+        };
+
+        // flushed lines are readable, the stream is finished on drop only
+        let mut file = RotatingFile::create(&path, None, true).unwrap();
+        file.write_line("0001").unwrap();
+        file.flush().unwrap();
+        assert_eq!(read("frames.gz"), ("0001\n".to_string(), false));
+        file.write_line("0002").unwrap();
+        file.flush().unwrap();
+        file.flush().unwrap();
+        drop(file);
+        // all lines in one gzip member, flushes without lines add nothing
+        assert_eq!(read("frames.gz"), ("0001\n0002\n".to_string(), true));
+
+        // compressed bytes count, 10 lines of 1001 bytes fit in a few files of 64 bytes
+        let line = "x".repeat(1000);
+        let mut file = RotatingFile::create(&path, Some(64), true).unwrap();
+        for _ in 0..10 {
+            file.write_line(&line).unwrap();
+            file.flush().unwrap();
+        }
+        drop(file);
+        let mut names = vec!["frames.gz".to_string()];
+        names.extend((1..10).map(|rotation| format!("frames.gz.{rotation}")));
+        let files: Vec<String> = names
+            .iter()
+            .filter(|name| dir.join(name).exists())
+            .map(|name| {
+                let (lines, finished) = read(name);
+                assert!(finished, "{name}");
+                lines
+            })
+            .collect();
+        assert!(files.len() > 1 && files.len() < 10, "{}", files.len());
+        assert_eq!(files.concat(), format!("{line}\n").repeat(10));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+use crate::order_book::{BookSnapshot, OrderBook};
+use crate::rotating_file::RotatingFile;
+use axum::extract::{Path, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tracing::error;
+
+/// Latest snapshot of every book, written to files periodically and served over http
+#[derive(Default)]
+pub struct LatestSnapshots {
+    books: Mutex<BTreeMap<String, BookSnapshot>>,
+}
+
+impl LatestSnapshots {
+    pub fn publish(&self, book: &OrderBook) {
+        self.books
+            .lock()
+            .unwrap()
+            .insert(book.get_symbol().to_string(), book.snapshot());
+    }
+
+    /// the symbol isn't watched anymore, its frozen book is neither served nor written
+    pub fn remove(&self, symbol: &str) {
+        self.books.lock().unwrap().remove(symbol);
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<BookSnapshot> {
+        self.books.lock().unwrap().get(symbol).cloned()
+    }
+
+    /// snapshots ordered by symbol
+    fn snapshots(&self) -> Vec<BookSnapshot> {
+        self.books.lock().unwrap().values().cloned().collect()
+    }
+}
+
+/// Appends snapshots as json lines to `{symbol}.ndjson` files of the directory,
+/// or gzip compressed `{symbol}.ndjson.gz` ones
+pub struct SnapshotWriter {
+    dir: PathBuf,
+    max_size: Option<u64>,
+    compress: bool,
+    files: HashMap<String, RotatingFile>,
+    // symbols written since the last flush
+    written: HashSet<String>,
+}
+
+/// snapshot line with the epoch ms it's taken at
+#[derive(Serialize)]
+struct TimedSnapshot<'a> {
+    ts: u128,
+    #[serde(flatten)]
+    snapshot: &'a BookSnapshot,
+}
+
+impl SnapshotWriter {
+    /// files are rotated above max size bytes if any, and gzip compressed if compress is set
+    pub fn create(dir: &str, max_size: Option<u64>, compress: bool) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: PathBuf::from(dir),
+            max_size,
+            compress,
+            files: HashMap::new(),
+            written: HashSet::new(),
+        })
+    }
+
+    pub fn write(&mut self, snapshot: &BookSnapshot, ts: u128) -> std::io::Result<()> {
+        let file = match self.files.get_mut(&snapshot.symbol) {
+            Some(file) => file,
+            None => {
+                let extension = if self.compress { "ndjson.gz" } else { "ndjson" };
+                let path = self.dir.join(format!("{}.{}", snapshot.symbol, extension));
+                let file = RotatingFile::append(path, self.max_size, self.compress)?;
+                self.files.entry(snapshot.symbol.clone()).or_insert(file)
+            }
+        };
+        self.written.insert(snapshot.symbol.clone());
+        let line = serde_json::to_string(&TimedSnapshot { ts, snapshot }).unwrap();
+        file.write_line(&line)
+    }
+
+    /// flushes files written since the last flush,
+    /// files of symbols not written meanwhile, e.g. removed ones, are closed
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        let written = std::mem::take(&mut self.written);
+        self.files.retain(|symbol, _| written.contains(symbol));
+        self.files.values_mut().try_for_each(|file| file.flush())
+    }
+}
+
+/// writes the latest snapshot of every book at the interval
+pub async fn snapshot_task(
+    latest: Arc<LatestSnapshots>,
+    mut writer: SnapshotWriter,
+    interval: Duration,
+) {
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    loop {
+        interval.tick().await;
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        let result = latest
+            .snapshots()
+            .iter()
+            .try_for_each(|snapshot| writer.write(snapshot, ts))
+            .and_then(|_| writer.flush());
+        if let Err(e) = result {
+            error!("Failed to write snapshots ({e})");
+        }
+    }
+}
+
+/// serves GET /book/{symbol} with the book json, unknown symbols and any other path are 404
+pub async fn serve_books(listener: TcpListener, latest: Arc<LatestSnapshots>) {
+    let router = Router::new()
+        .route("/book/{symbol}", get(book))
+        .with_state(latest);
+    if let Err(e) = axum::serve(listener, router).await {
+        error!("Books server stopped ({e})");
+    }
+}
+
+async fn book(
+    State(latest): State<Arc<LatestSnapshots>>,
+    Path(symbol): Path<String>,
+) -> impl IntoResponse {
+    let snapshot = latest
+        .get(&symbol.to_lowercase())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok::<_, StatusCode>((
+        [(CONTENT_TYPE, "application/json")],
+        serde_json::to_string(&snapshot).unwrap(),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::order_book::test_book;
+    use std::io::Read;
+
+    #[test]
+    fn snapshot_writer_test() {
+        let dir =
+            std::env::temp_dir().join(format!("binance_watcher_snapshots_{}", std::process::id()));
+        let latest = LatestSnapshots::default();
+        latest.publish(&test_book("btcusdt", &[["10", "1"]], &[["11", "2"]]));
+
+        // files are appended, also by another writer
+        for ts in [1, 2] {
+            let mut writer = SnapshotWriter::create(dir.to_str().unwrap(), None, false).unwrap();
+            for snapshot in latest.snapshots() {
+                writer.write(&snapshot, ts).unwrap();
+            }
+            writer.flush().unwrap();
+        }
+
+        let content = std::fs::read_to_string(dir.join("btcusdt.ndjson")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["ts"], 1);
+        assert_eq!(lines[1]["ts"], 2);
+        assert_eq!(lines[1]["symbol"], "btcusdt");
+        assert_eq!(lines[1]["last_update_id"], 1);
+        assert_eq!(lines[1]["mid"], 10.5);
+        assert_eq!(
+            lines[1]["asks"],
+            serde_json::json!([{"quantity": 2.0, "price": 11.0}])
+        );
+    }
+
+    #[test]
+    fn compressed_snapshot_writer_test() {
+        let dir = std::env::temp_dir().join(format!(
+            "binance_watcher_compressed_snapshots_{}",
+            std::process::id()
+        ));
+        let snapshot = |symbol: &str| test_book(symbol, &[["10", "1"]], &[]).snapshot();
+        let read = |name: &str| {
+            let mut content = String::new();
+            let file = std::fs::File::open(dir.join(name)).unwrap();
+            flate2::read::MultiGzDecoder::new(file)
+                .read_to_string(&mut content)
+                .unwrap();
+            content.lines().count()
+        };
+
+        let mut writer = SnapshotWriter::create(dir.to_str().unwrap(), None, true).unwrap();
+        writer.write(&snapshot("btcusdt"), 1).unwrap();
+        writer.write(&snapshot("ethusdt"), 1).unwrap();
+        writer.flush().unwrap();
+        // files not written since the last flush are closed
+        writer.write(&snapshot("btcusdt"), 2).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.files.len(), 1);
+        assert_eq!(read("ethusdt.ndjson.gz"), 1);
+        drop(writer);
+
+        // appended by another writer as another gzip member
+        let mut writer = SnapshotWriter::create(dir.to_str().unwrap(), None, true).unwrap();
+        writer.write(&snapshot("btcusdt"), 3).unwrap();
+        drop(writer);
+        assert_eq!(read("btcusdt.ndjson.gz"), 3);
+        assert!(!dir.join("btcusdt.ndjson").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn serve_books_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let latest = Arc::new(LatestSnapshots::default());
+        tokio::spawn(serve_books(listener, latest.clone()));
+
+        latest.publish(&test_book("btcusdt", &[["10", "1"]], &[["11", "2"]]));
+
+        let response = reqwest::get(format!("{url}/book/BTCUSDT")).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!(body["symbol"], "btcusdt");
+        assert_eq!(body["last_update_id"], 1);
+        assert_eq!(body["mid"], 10.5);
+
+        for path in ["/book/ethusdt", "/books", "/book/btcusdt/asks"] {
+            let status = reqwest::get(format!("{url}{path}")).await.unwrap().status();
+            assert_eq!(status, reqwest::StatusCode::NOT_FOUND);
+        }
+
+        latest.remove("btcusdt");
+        assert!(latest.snapshots().is_empty());
+        let status = reqwest::get(format!("{url}/book/btcusdt"))
+            .await
+            .unwrap()
+            .status();
+        assert_eq!(status, reqwest::StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,293 @@
+use crate::number::Number;
+use crate::order_book::OrderBook;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// latest latencies kept per symbol, min/avg/max are rolling over them
+const LATENCY_WINDOW: usize = 1000;
+
+/// Counters of a symbol over the whole run, kept across reconnections
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct SymbolStats {
+    pub depth_updates: u64,
+    /// full books applied, the initial one included
+    pub resyncs: u64,
+    /// depth updates which didn't follow the previous one
+    pub gaps: u64,
+    /// from event time to applied depth update
+    pub latency: Latency,
+    pub mid: Option<Number>,
+    pub spread: Option<Number>,
+}
+
+/// Latency of applied depth updates in ms, from binance event time to local processing
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct Latency {
+    /// latencies of the run
+    pub count: u64,
+    /// event times ahead of the local clock, counted as 0 ms latency
+    pub skewed: u64,
+    // latest latencies, oldest first
+    window: VecDeque<u64>,
+}
+
+impl Latency {
+    fn record(&mut self, event_time: u64, now: u64) {
+        if event_time > now {
+            self.skewed += 1;
+        }
+        self.count += 1;
+        self.push(now.saturating_sub(event_time));
+    }
+
+    fn push(&mut self, latency: u64) {
+        if self.window.len() == LATENCY_WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back(latency);
+    }
+
+    /// latencies of the other are the latest ones
+    fn merge(&mut self, other: &Latency) {
+        self.count += other.count;
+        self.skewed += other.skewed;
+        for latency in &other.window {
+            self.push(*latency);
+        }
+    }
+
+    pub fn min(&self) -> Option<u64> {
+        self.window.iter().min().copied()
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        self.window.iter().max().copied()
+    }
+
+    pub fn avg(&self) -> Option<f64> {
+        match self.window.len() {
+            0 => None,
+            len => Some(self.window.iter().sum::<u64>() as f64 / len as f64),
+        }
+    }
+}
+
+/// Stats of the symbols watched by one or more handlers
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct WatchStats {
+    symbols: BTreeMap<String, SymbolStats>,
+}
+
+impl WatchStats {
+    /// counts an applied depth update and keeps the book's latest mid and spread
+    pub fn record_depth_update(&mut self, book: &OrderBook) {
+        let stats = self.record_book(book);
+        stats.depth_updates += 1;
+    }
+
+    /// counts an applied full book and keeps the book's latest mid and spread
+    pub fn record_resync(&mut self, book: &OrderBook) {
+        let stats = self.record_book(book);
+        stats.resyncs += 1;
+    }
+
+    /// latency of an applied depth update of the event time, now is epoch ms
+    pub fn record_latency(&mut self, symbol: &str, event_time: u64, now: u64) {
+        let stats = self.symbols.entry(symbol.to_string()).or_default();
+        stats.latency.record(event_time, now);
+    }
+
+    /// counts a depth update which didn't follow the previous one
+    pub fn record_gap(&mut self, symbol: &str) {
+        self.symbols.entry(symbol.to_string()).or_default().gaps += 1;
+    }
+
+    fn record_book(&mut self, book: &OrderBook) -> &mut SymbolStats {
+        let stats = self
+            .symbols
+            .entry(book.get_symbol().to_string())
+            .or_default();
+        stats.mid = book.get_mid();
+        stats.spread = book.get_spread();
+        stats
+    }
+
+    pub fn get(&self, symbol: &str) -> Option<&SymbolStats> {
+        self.symbols.get(symbol)
+    }
+
+    /// adds stats of another handler, counters are summed and the latest book values are taken
+    pub fn merge(&mut self, other: WatchStats) {
+        for (symbol, other) in other.symbols {
+            let stats = self.symbols.entry(symbol).or_default();
+            stats.depth_updates += other.depth_updates;
+            stats.resyncs += other.resyncs;
+            stats.gaps += other.gaps;
+            stats.latency.merge(&other.latency);
+            stats.mid = other.mid.or(stats.mid);
+            stats.spread = other.spread.or(stats.spread);
+        }
+    }
+
+    /// table printed on shutdown
+    pub fn summary(&self, duration: Duration) -> String {
+        let mut text = String::new();
+        writeln!(text, "====SUMMARY====").unwrap();
+        writeln!(text, "run duration: {:.1}s", duration.as_secs_f64()).unwrap();
+        writeln!(
+            text,
+            "| {:<12} | {:>13} | {:>7} | {:>6} | {:>14} | {:>14} | {:>22} | {:>6} |",
+            "symbol",
+            "depth updates",
+            "resyncs",
+            "gaps",
+            "mid",
+            "spread",
+            "latency ms min/avg/max",
+            "skewed"
+        )
+        .unwrap();
+        for (symbol, stats) in &self.symbols {
+            writeln!(
+                text,
+                "| {:<12} | {:>13} | {:>7} | {:>6} | {:>14} | {:>14} | {:>22} | {:>6} |",
+                symbol,
+                stats.depth_updates,
+                stats.resyncs,
+                stats.gaps,
+                format_optional(stats.mid),
+                format_optional(stats.spread),
+                format_latency(&stats.latency),
+                stats.latency.skewed
+            )
+            .unwrap();
+        }
+        write!(text, "====END SUMMARY====").unwrap();
+        text
+    }
+}
+
+fn format_latency(latency: &Latency) -> String {
+    match (latency.min(), latency.avg(), latency.max()) {
+        (Some(min), Some(avg), Some(max)) => format!("{min}/{avg:.1}/{max}"),
+        _ => "-".to_string(),
+    }
+}
+
+fn format_optional(value: Option<Number>) -> String {
+    value.map_or("-".to_string(), |value| value.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::number::number;
+    use crate::order_book::test_book;
+
+    #[test]
+    fn merge_test() {
+        let mut first = WatchStats::default();
+        first.record_resync(&test_book("btcusdt", &[["10", "1"]], &[["11", "1"]]));
+        first.record_depth_update(&test_book("btcusdt", &[["10", "1"]], &[["12", "1"]]));
+        first.record_depth_update(&test_book("btcusdt", &[["10", "1"]], &[["12", "1"]]));
+        let mut second = WatchStats::default();
+        second.record_resync(&test_book("ethusdt", &[["1", "1"]], &[["2", "1"]]));
+        second.record_resync(&test_book("ethusdt", &[["1", "1"]], &[["3", "1"]]));
+        second.record_depth_update(&test_book("ethusdt", &[["1", "1"]], &[["3", "1"]]));
+
+        second.record_gap("ethusdt");
+        first.merge(second);
+        // the same symbol in two handlers is summed, e.g. after a handler restart
+        let mut third = WatchStats::default();
+        third.record_depth_update(&test_book("btcusdt", &[["10", "1"]], &[["14", "1"]]));
+        first.merge(third);
+
+        assert_eq!(
+            first.get("btcusdt"),
+            Some(&SymbolStats {
+                depth_updates: 3,
+                resyncs: 1,
+                gaps: 0,
+                latency: Latency::default(),
+                mid: Some(number("12.0")),
+                spread: Some(number("4.0")),
+            })
+        );
+        assert_eq!(
+            first.get("ethusdt"),
+            Some(&SymbolStats {
+                depth_updates: 1,
+                resyncs: 2,
+                gaps: 1,
+                latency: Latency::default(),
+                mid: Some(number("2.0")),
+                spread: Some(number("2.0")),
+            })
+        );
+        assert_eq!(first.get("bnbusdt"), None);
+    }
+
+    #[test]
+    fn latency_test() {
+        let mut stats = WatchStats::default();
+        stats.record_latency("btcusdt", 1_000, 1_040);
+        stats.record_latency("btcusdt", 2_000, 2_010);
+        // event time ahead of the local clock
+        stats.record_latency("btcusdt", 3_005, 3_000);
+
+        let latency = &stats.get("btcusdt").unwrap().latency;
+        assert_eq!(
+            (latency.count, latency.skewed, latency.min(), latency.max()),
+            (3, 1, Some(0), Some(40))
+        );
+        assert_eq!(latency.avg(), Some(50.0 / 3.0));
+
+        let mut other = WatchStats::default();
+        other.record_latency("btcusdt", 1_000, 1_100);
+        stats.merge(other);
+        let latency = &stats.get("btcusdt").unwrap().latency;
+        assert_eq!(
+            (latency.count, latency.min(), latency.max()),
+            (4, Some(0), Some(100))
+        );
+        assert!(stats.summary(Duration::ZERO).contains("0/37.5/100"));
+    }
+
+    #[test]
+    fn rolling_latency_test() {
+        let mut stats = WatchStats::default();
+        // an early spike leaves the window
+        stats.record_latency("btcusdt", 0, 500);
+        for time in 1..=LATENCY_WINDOW as u64 {
+            stats.record_latency("btcusdt", time, time + 10);
+        }
+        let latency = &stats.get("btcusdt").unwrap().latency;
+        assert_eq!(latency.count, LATENCY_WINDOW as u64 + 1);
+        assert_eq!(
+            (latency.min(), latency.avg(), latency.max()),
+            (Some(10), Some(10.0), Some(10))
+        );
+
+        // merged latencies are the latest
+        let mut other = WatchStats::default();
+        other.record_latency("btcusdt", 0, 20);
+        stats.merge(other);
+        let latency = &stats.get("btcusdt").unwrap().latency;
+        assert_eq!((latency.min(), latency.max()), (Some(10), Some(20)));
+    }
+
+    #[test]
+    fn summary_test() {
+        let mut stats = WatchStats::default();
+        stats.record_depth_update(&test_book("btcusdt", &[["10", "1"]], &[["11", "1"]]));
+        stats.record_gap("btcusdt");
+        stats.record_gap("btcusdt");
+        let summary = stats.summary(Duration::from_millis(1500));
+        assert!(summary.contains("run duration: 1.5s"));
+        assert!(summary.contains(&format!(
+            "| {:<12} | {:>13} | {:>7} | {:>6} | {:>14} | {:>14} | {:>22} | {:>6} |",
+            "btcusdt", 1, 0, 2, 10.5, 1, "-", 0
+        )));
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Subscription requests sent on a connection, waiting for their acks by request id
+#[derive(Default)]
+pub struct PendingAcks {
+    requests: HashMap<String, PendingAck>,
+}
+
+struct PendingAck {
+    symbol: String,
+    sent_at: Instant,
+}
+
+impl PendingAcks {
+    pub fn sent(&mut self, id: &str, symbol: &str, now: Instant) {
+        self.requests.insert(
+            id.to_string(),
+            PendingAck {
+                symbol: symbol.to_string(),
+                sent_at: now,
+            },
+        );
+    }
+
+    /// symbol of the acknowledged request, None if the id isn't waited for
+    pub fn acknowledge(&mut self, id: &str) -> Option<String> {
+        self.requests.remove(id).map(|request| request.symbol)
+    }
+
+    /// when the oldest request isn't acknowledged in time
+    pub fn next_deadline(&self, timeout: Duration) -> Option<Instant> {
+        self.requests
+            .values()
+            .map(|request| request.sent_at + timeout)
+            .min()
+    }
+
+    /// symbols of the requests not acknowledged in time, they aren't waited for anymore
+    pub fn expire(&mut self, now: Instant, timeout: Duration) -> Vec<String> {
+        let expired: Vec<String> = self
+            .requests
+            .iter()
+            .filter(|(_, request)| request.sent_at + timeout <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+        let mut symbols: Vec<String> = expired
+            .iter()
+            .filter_map(|id| self.acknowledge(id))
+            .collect();
+        symbols.sort();
+        symbols
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pending_acks_test() {
+        let mut acks = PendingAcks::default();
+        let timeout = Duration::from_secs(5);
+        let start = Instant::now();
+        assert_eq!(acks.next_deadline(timeout), None);
+
+        acks.sent("btcusdt_1", "btcusdt", start);
+        acks.sent("ethusdt_2", "ethusdt", start + Duration::from_secs(1));
+        acks.sent("bnbusdt_3", "bnbusdt", start + Duration::from_secs(2));
+        assert_eq!(acks.next_deadline(timeout), Some(start + timeout));
+
+        assert_eq!(acks.acknowledge("btcusdt_1"), Some("btcusdt".to_string()));
+        assert_eq!(acks.acknowledge("btcusdt_1"), None);
+        assert_eq!(acks.acknowledge("unknown"), None);
+        assert_eq!(
+            acks.next_deadline(timeout),
+            Some(start + Duration::from_secs(1) + timeout)
+        );
+
+        assert!(acks.expire(start + timeout, timeout).is_empty());
+        assert_eq!(
+            acks.expire(start + Duration::from_secs(7), timeout),
+            vec!["bnbusdt".to_string(), "ethusdt".to_string()]
+        );
+        assert_eq!(acks.next_deadline(timeout), None);
+    }
+}
@@ -0,0 +1,3349 @@
+use crate::alerts::{post_alerts, Alert, BookAlerts};
+use crate::commands::{CommandRouter, SymbolCommand};
+use crate::console_arguments::{Command, Config, StreamType};
+use crate::deflate_socket::{connect_deflate, MessageSink, MessageStream};
+use crate::depth_buffer::BookResync;
+use crate::exchange_info::{fetch_exchange_info, precisions, validate_symbols};
+use crate::messages::{
+    ApiError, CombinedStreamMessage, ForceOrderUpdate, FullBook, FullBookEvent, StreamMessage,
+    Subscription, INVALID_SYMBOL_CODE,
+};
+use crate::metrics::{serve_metrics, Metrics};
+use crate::order_book::{ApplyError, ApplyResult, BookEvent, OrderBook, Precision};
+use crate::recorder::{read_recording, Recorder};
+use crate::render_throttle::RenderThrottle;
+use crate::renderer::{colors_enabled, render_book, render_task, PendingRenders, RenderOptions};
+use crate::snapshots::{serve_books, snapshot_task, LatestSnapshots, SnapshotWriter};
+use crate::stats::WatchStats;
+use crate::subscriptions::PendingAcks;
+use futures_util::future::try_join_all;
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::io::{IsTerminal, Write};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{broadcast, mpsc, Semaphore};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tracing::{error, info, info_span, warn, Instrument};
+
+/// book events kept for slow subscribers, the oldest are lost beyond it
+const BOOK_EVENTS_CAPACITY: usize = 1024;
+
+/// Watches order books of the configured instruments until stopped
+#[derive(Clone)]
+pub struct Watcher {
+    config: Arc<Config>,
+    is_app_running: Arc<AtomicBool>,
+    events: broadcast::Sender<BookEvent>,
+    commands: Arc<Mutex<CommandRouter>>,
+    alerts: Arc<BookAlerts>,
+    liquidations: broadcast::Sender<ForceOrderUpdate>,
+}
+
+impl Watcher {
+    pub fn new(config: Config) -> Self {
+        let (events, _) = broadcast::channel(BOOK_EVENTS_CAPACITY);
+        let (liquidations, _) = broadcast::channel(BOOK_EVENTS_CAPACITY);
+        let alerts = Arc::new(BookAlerts::new(
+            config.spread_alert_bps,
+            config.stale_book_secs.map(Duration::from_secs),
+        ));
+        Watcher {
+            config: Arc::new(config),
+            is_app_running: Arc::new(AtomicBool::new(true)),
+            events,
+            commands: Arc::new(Mutex::new(CommandRouter::default())),
+            alerts,
+            liquidations,
+        }
+    }
+
+    /// receives alerts raised after subscription, e.g. spread above --spread-alert-bps
+    /// or crossed book
+    pub fn subscribe_alerts(&self) -> broadcast::Receiver<Alert> {
+        self.alerts.subscribe()
+    }
+
+    /// receives liquidations of the watched symbols, streamed with --liquidations
+    pub fn subscribe_liquidations(&self) -> broadcast::Receiver<ForceOrderUpdate> {
+        self.liquidations.subscribe()
+    }
+
+    /// adds or removes a watched symbol on the running connections
+    pub fn command(&self, command: SymbolCommand) -> Result<(), String> {
+        self.commands.lock().unwrap().route(command)
+    }
+
+    /// receives an event on every book update applied after subscription
+    pub fn subscribe(&self) -> broadcast::Receiver<BookEvent> {
+        self.events.subscribe()
+    }
+
+    /// book events as a stream, events lost by a lagging consumer are skipped
+    pub fn updates(&self) -> impl Stream<Item = BookEvent> {
+        futures_util::stream::unfold(self.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// asks all connections to close, they finish on the next received frame
+    pub fn stop(&self) {
+        self.is_app_running.store(false, Ordering::SeqCst);
+    }
+
+    /// watches until stopped or SIGTERM, or replays the recording if replay is configured,
+    /// errors on invalid symbols with strict, no valid symbol, or connections not closed after stop
+    pub async fn run(&self) -> Result<(), String> {
+        let started = Instant::now();
+        let config = &self.config;
+        if let Some(Command::Replay { path, speed }) = &config.command {
+            return replay(config, path, *speed).await.map(|_| ());
+        }
+        if config.dry_run {
+            print!("{}", dry_run_plan(config));
+            return Ok(());
+        }
+        // registered before anything is spawned, the listener is dropped with the run
+        let _terminate = AbortOnDrop(tokio::spawn(stop_on_terminate(self.clone())?));
+
+        // one http client (and its connection pool) shared by all handlers
+        let http_client = reqwest::Client::new();
+
+        // symbols are checked and books are displayed with their precision
+        let exchange_info = fetch_exchange_info(&http_client, &config.api_url())
+            .await
+            .inspect_err(|e| warn!("Failed to fetch exchange info ({e}), symbols aren't validated"))
+            .ok();
+        let precisions = exchange_info.as_ref().map(precisions).unwrap_or_default();
+        let instruments = match &exchange_info {
+            Some(info) => {
+                let (valid, errors) = validate_symbols(info, &config.instruments);
+                for e in &errors {
+                    error!("Skipping symbol: {e}");
+                }
+                if config.strict && !errors.is_empty() {
+                    return Err("Invalid symbols with --strict".to_string());
+                }
+                valid
+            }
+            None => config.instruments.clone(),
+        };
+        if instruments.is_empty() {
+            return Err("No valid symbols to watch".to_string());
+        }
+
+        // metrics are always collected, served only if port is set
+        let metrics = Arc::new(Metrics::default());
+        if let Some(port) = config.metrics_port {
+            let address = SocketAddr::new(config.http_bind(), port);
+            let listener = TcpListener::bind(address)
+                .await
+                .map_err(|e| format!("Can't bind metrics address {address}: {e}"))?;
+            tokio::spawn(serve_metrics(listener, metrics.clone()));
+            info!("Metrics are served on http://{address}/metrics");
+        }
+
+        // alerts are posted as they are raised
+        if let Some(url) = &config.webhook_url {
+            tokio::spawn(post_alerts(
+                http_client.clone(),
+                url.clone(),
+                self.alerts.subscribe(),
+            ));
+        }
+        if config.stale_book_secs.is_some() {
+            tokio::spawn(check_stale_books(self.alerts.clone()));
+        }
+
+        // latest books are written periodically and served over http
+        let snapshots = (config.snapshot_dir.is_some() || config.http_port.is_some())
+            .then(|| Arc::new(LatestSnapshots::default()));
+        if let (Some(dir), Some(latest)) = (&config.snapshot_dir, &snapshots) {
+            let writer = SnapshotWriter::create(dir, config.max_file_size, config.compress)
+                .map_err(|e| format!("Can't create snapshot dir {dir}: {e}"))?;
+            let interval = Duration::from_secs(config.snapshot_interval_secs);
+            tokio::spawn(snapshot_task(latest.clone(), writer, interval));
+        }
+        if let (Some(port), Some(latest)) = (config.http_port, &snapshots) {
+            let address = SocketAddr::new(config.http_bind(), port);
+            let listener = TcpListener::bind(address)
+                .await
+                .map_err(|e| format!("Can't bind http address {address}: {e}"))?;
+            tokio::spawn(serve_books(listener, latest.clone()));
+            info!("Books are served on http://{address}/book/{{symbol}}");
+        }
+
+        // raw frames recording for replay
+        let recorder = config
+            .record
+            .as_ref()
+            .map(|path| {
+                Recorder::create(path, config.max_file_size, config.compress)
+                    .map(Arc::new)
+                    .map_err(|e| format!("Can't create record file {path}: {e}"))
+            })
+            .transpose()?;
+
+        // books are rendered apart from updates
+        let renders = Arc::new(PendingRenders::default());
+        let options = RenderOptions {
+            delay_ms: config.delay,
+            format: config.output_format(),
+            live: config.live,
+            color: colors_enabled(
+                config.color,
+                std::env::var_os("NO_COLOR").is_some(),
+                std::io::stdout().is_terminal(),
+            ),
+        };
+        // per update json lines replace rendered books, so the output could be piped
+        let render_output = !config.ndjson;
+        let renderer = tokio::spawn(render_task(renders.clone(), options, move |text| {
+            if render_output {
+                println!("{text}")
+            }
+        }));
+        let update_lines = config.ndjson.then(|| {
+            Arc::new(|line: String| {
+                let mut stdout = std::io::stdout().lock();
+                let _ = writeln!(stdout, "{line}");
+            }) as LineOutput
+        });
+
+        let context = HandlerContext {
+            is_app_running: self.is_app_running.clone(),
+            config: config.clone(),
+            http_client,
+            metrics,
+            recorder: recorder.clone(),
+            events: self.events.clone(),
+            renders,
+            http_requests: Arc::new(Semaphore::new(config.max_concurrent_requests as usize)),
+            alerts: self.alerts.clone(),
+            snapshots,
+            liquidations: self.liquidations.clone(),
+            update_lines,
+            precisions: Arc::new(precisions),
+            commands: self.commands.clone(),
+        };
+
+        // sockets/handlers vector of futures to join at the end of the program
+        let mut handlers = vec![];
+
+        // run a bunch of symbols per socket
+        for (connection, chunk_of_instruments) in instruments
+            .chunks(config.instruments_per_connection())
+            .enumerate()
+        {
+            // connections are opened one by one, not to hit binance rate limits
+            if connection > 0 {
+                tokio::time::sleep(Duration::from_millis(config.stagger)).await;
+            }
+            // spawn a new connection/handler, if there is a bunch of instruments to allocate
+            // logs of the handler are prefixed with the connection and its symbols
+            let span = info_span!(
+                "connection",
+                id = connection,
+                symbols = chunk_of_instruments.join(",")
+            );
+            let commands = self
+                .commands
+                .lock()
+                .unwrap()
+                .add_connection(chunk_of_instruments);
+            let handle = tokio::spawn(
+                watch_instruments(context.clone(), chunk_of_instruments.to_vec(), commands)
+                    .instrument(span),
+            );
+
+            handlers.push(handle)
+        }
+        info!("Binance connections handlers started: {}", handlers.len());
+
+        // wait for handler/socket closure
+        // handlers stuck after stop, e.g. on a hung http request, don't keep the program alive
+        let shutdown_timeout = Duration::from_secs(config.shutdown_timeout);
+        let Some(stats) = join_with_shutdown_timeout(
+            join_handlers(handlers),
+            &self.is_app_running,
+            shutdown_timeout,
+        )
+        .await
+        else {
+            if let Some(recorder) = recorder {
+                recorder.flush();
+            }
+            return Err(format!(
+                "Connections haven't closed within {shutdown_timeout:?} after stop"
+            ));
+        };
+        let stats = match stats {
+            Ok(stats) => stats,
+            Err(e) => {
+                // the other handlers are stopped, the program exits anyway
+                self.stop();
+                renderer.abort();
+                if let Some(recorder) = recorder {
+                    recorder.flush();
+                }
+                return Err(e);
+            }
+        };
+        match config.books_only_stdout() {
+            true => eprintln!("{}", stats.summary(started.elapsed())),
+            false => println!("{}", stats.summary(started.elapsed())),
+        }
+        renderer.abort();
+        if let Some(recorder) = recorder {
+            recorder.flush();
+        }
+        Ok(())
+    }
+}
+
+/// watches the configured instruments until ctrl-c or SIGTERM, see [Watcher] to stop it otherwise
+pub async fn run(config: Config) -> Result<(), String> {
+    let watcher = Watcher::new(config);
+    let stopper = watcher.clone();
+    // e.g. the embedding app has its own handler already
+    ctrlc::set_handler(move || stopper.stop())
+        .map_err(|e| format!("Can't set Ctrl-C handler: {e}"))?;
+    read_commands(watcher.clone());
+    watcher.run().await
+}
+
+/// adds or removes watched symbols by stdin lines like `add ethusdt` or `remove btcusdt`,
+/// read in a thread, so pending read doesn't block the runtime shutdown
+fn read_commands(watcher: Watcher) {
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines().map_while(Result::ok) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match line.parse().and_then(|command| watcher.command(command)) {
+                Ok(()) => info!("Command '{line}' accepted"),
+                Err(e) => warn!("Skipping command '{line}' ({e})"),
+            }
+        }
+    });
+}
+
+/// stops the watcher on SIGTERM sent by systemd or docker,
+/// the signal is handled from the call, not from the first poll
+#[cfg(unix)]
+fn stop_on_terminate(watcher: Watcher) -> Result<impl Future<Output = ()>, String> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate =
+        signal(SignalKind::terminate()).map_err(|e| format!("Can't set SIGTERM handler: {e}"))?;
+    Ok(async move {
+        if terminate.recv().await.is_some() {
+            info!("SIGTERM received, stopping");
+            watcher.stop();
+        }
+    })
+}
+
+/// stops the watcher when the console is closed or windows shuts down
+#[cfg(windows)]
+fn stop_on_terminate(watcher: Watcher) -> Result<impl Future<Output = ()>, String> {
+    use tokio::signal::windows::{ctrl_close, ctrl_shutdown};
+    let mut close = ctrl_close().map_err(|e| format!("Can't set console close handler: {e}"))?;
+    let mut shutdown = ctrl_shutdown().map_err(|e| format!("Can't set shutdown handler: {e}"))?;
+    Ok(async move {
+        tokio::select! {
+            _ = close.recv() => {}
+            _ = shutdown.recv() => {}
+        }
+        info!("Close or shutdown received, stopping");
+        watcher.stop();
+    })
+}
+
+/// subscriptions not acknowledged in time are logged, e.g. typo'd symbols
+const SUBSCRIPTION_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// full book requests rate limited by binance are given up after the attempts
+const RATE_LIMITED_ATTEMPTS: u32 = 3;
+
+/// pause after rate limited full book request, if binance hasn't sent Retry-After
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// pause before retrying a full book request failed by network or binance server errors
+const SNAPSHOT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// pause before connecting again after a failed connection
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// period to check whether the app is stopped, while handlers are running
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// waits for the handlers, once the app is stopped they are given the timeout to finish,
+/// None if they haven't
+async fn join_with_shutdown_timeout<T>(
+    handlers: impl Future<Output = T>,
+    is_app_running: &AtomicBool,
+    timeout: Duration,
+) -> Option<T> {
+    let watchdog = async {
+        while is_app_running.load(Ordering::SeqCst) {
+            tokio::time::sleep(STOP_CHECK_INTERVAL).await;
+        }
+        tokio::time::sleep(timeout).await;
+    };
+    tokio::select! {
+        result = handlers => Some(result),
+        _ = watchdog => None,
+    }
+}
+
+/// merged stats of the handlers, fails once one of them has panicked
+async fn join_handlers(
+    handlers: Vec<tokio::task::JoinHandle<WatchStats>>,
+) -> Result<WatchStats, String> {
+    let handlers_stats = try_join_all(handlers)
+        .await
+        .map_err(|e| format!("Connection handler failed: {e}"))?;
+    let mut stats = WatchStats::default();
+    for handler_stats in handlers_stats {
+        stats.merge(handler_stats);
+    }
+    Ok(stats)
+}
+
+/// period to check whether books are stale
+const STALE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// alerts on books which haven't been updated for a while
+async fn check_stale_books(alerts: Arc<BookAlerts>) {
+    let mut interval = tokio::time::interval(STALE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        for alert in alerts.check_stale(get_epoch_ms()) {
+            warn!(symbol = alert.symbol.as_str(), "{}", alert.message());
+        }
+    }
+}
+
+/// Everything shared by connections handlers
+#[derive(Clone)]
+struct HandlerContext {
+    is_app_running: Arc<AtomicBool>,
+    config: Arc<Config>,
+    http_client: reqwest::Client,
+    metrics: Arc<Metrics>,
+    recorder: Option<Arc<Recorder>>,
+    events: broadcast::Sender<BookEvent>,
+    renders: Arc<PendingRenders>,
+    // limits full book requests in flight over all connections
+    http_requests: Arc<Semaphore>,
+    alerts: Arc<BookAlerts>,
+    snapshots: Option<Arc<LatestSnapshots>>,
+    liquidations: broadcast::Sender<ForceOrderUpdate>,
+    // receives a json line per applied depth update with --ndjson
+    update_lines: Option<LineOutput>,
+    // display precision by symbol, from exchangeInfo
+    precisions: Arc<HashMap<String, Precision>>,
+    // symbols of connections, told about symbols dropped by handlers
+    commands: Arc<Mutex<CommandRouter>>,
+}
+
+/// line output shared by connections handlers
+type LineOutput = Arc<dyn Fn(String) + Send + Sync>;
+
+/// keeps a connection for the symbols, reconnects if it's dead until ctrl-c,
+/// returns stats of the symbols over all connections
+async fn watch_instruments(
+    context: HandlerContext,
+    mut symbols: Vec<String>,
+    mut commands: mpsc::UnboundedReceiver<SymbolCommand>,
+) -> WatchStats {
+    let mut stats = WatchStats::default();
+    let config = &context.config;
+
+    let connect_timeout = Duration::from_millis(config.connect_timeout as u64);
+    loop {
+        if !context.is_app_running.load(Ordering::SeqCst) {
+            return stats;
+        }
+        // combined streams are passed in url, otherwise subscribed after connection,
+        // symbols may be changed by commands since the last connection
+        let url = if config.combined {
+            let streams: Vec<String> = symbols
+                .iter()
+                .flat_map(|symbol| symbol_topics(symbol, config))
+                .collect();
+            config.combined_stream_url(&streams)
+        } else {
+            config.ws_api_url()
+        };
+        let connection = connect_to_binance(&url, connect_timeout, config.compress_ws).await;
+        let (write, read) = match connection {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Failed to connect to binance ({e}), connecting again");
+                context.metrics.record_reconnect();
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        let exit = handle_updates(
+            &context,
+            &mut symbols,
+            &mut stats,
+            &mut commands,
+            read,
+            write,
+        )
+        .await;
+        match exit {
+            HandlerExit::Stopped => return stats,
+            HandlerExit::Disconnected => {
+                warn!("Reconnecting to binance");
+                context.metrics.record_reconnect();
+            }
+        }
+    }
+}
+
+async fn handle_updates(
+    context: &HandlerContext,
+    symbols: &mut Vec<String>,
+    stats: &mut WatchStats,
+    commands: &mut mpsc::UnboundedReceiver<SymbolCommand>,
+    read: impl Stream<Item = Frame> + Unpin + Send + 'static,
+    mut write: impl Sink<Message, Error = tungstenite::Error> + Unpin,
+) -> HandlerExit {
+    let config = &context.config;
+
+    // the socket is read apart from applying, so a slow apply doesn't pile frames up unbounded
+    let (frames_sender, mut frames) = mpsc::channel(config.frame_queue_capacity());
+    let _reader = AbortOnDrop(tokio::spawn(queue_frames(
+        read,
+        frames_sender,
+        context.metrics.clone(),
+    )));
+    let mut read = futures_util::stream::poll_fn(move |cx| frames.poll_recv(cx));
+
+    // init books map
+    let mut order_books: HashMap<String, OrderBook> = symbols
+        .iter()
+        .map(|symbol| {
+            (
+                symbol.clone(),
+                new_book(symbol, config, &context.precisions),
+            )
+        })
+        .collect();
+    // only received frames move it, other events don't keep a silent socket alive
+    let read_timeout = Duration::from_millis(config.read_timeout as u64);
+    let mut last_frame_at = tokio::time::Instant::now();
+
+    // topic subscription, combined streams are already subscribed via url
+    let mut acks = PendingAcks::default();
+    for symbol in symbols.iter().filter(|_| !config.combined) {
+        if let Err(e) = send_subscription(&mut write, &mut acks, config, "SUBSCRIBE", symbol).await
+        {
+            error!(symbol, "Failed to subscribe ({e})");
+            return HandlerExit::Disconnected;
+        }
+    }
+
+    // symbols waiting for the full book, their depth updates are buffered meanwhile,
+    // partial depth replaces books without full books
+    let mut resync = BookResync::default();
+    let mut full_book_request: Option<FullBookRequest> = None;
+    let mut next_full_book_request_at = tokio::time::Instant::now();
+    for symbol in symbols.iter().filter(|_| !config.partial_depth) {
+        resync.request(symbol);
+    }
+    let reconcile_secs = config.reconcile_interval.filter(|_| !config.partial_depth);
+    let mut reconcile_interval = reconcile_secs.map(|secs| {
+        let period = Duration::from_secs(secs);
+        tokio::time::interval_at(tokio::time::Instant::now() + period, period)
+    });
+
+    // todo: consider to place it in a separate method?
+    // [documentation]: [https://developers.binance.com/docs/derivatives/usds-margined-futures/websocket-market-streams/How-to-manage-a-local-order-book-correctly]
+    loop {
+        // stop on ctrl-c
+        if !context.is_app_running.load(Ordering::SeqCst) {
+            info!("Connection closing!");
+            return HandlerExit::Stopped;
+        }
+
+        // one full book request in flight at a time, socket is read concurrently,
+        // requests are staggered and limited over all connections
+        if full_book_request.is_none() {
+            resync.queue_due(tokio::time::Instant::now());
+            if let Some(symbol) = resync.next_request() {
+                let http_client = context.http_client.clone();
+                let http_requests = context.http_requests.clone();
+                let config = config.clone();
+                let start_at = next_full_book_request_at;
+                next_full_book_request_at = tokio::time::Instant::now().max(start_at)
+                    + Duration::from_millis(config.stagger);
+                full_book_request = Some(Box::pin(async move {
+                    tokio::time::sleep_until(start_at).await;
+                    let result = limited(
+                        &http_requests,
+                        fetch_full_book(
+                            &http_client,
+                            &config.api_url(),
+                            &symbol,
+                            config.snapshot_limit(&symbol),
+                            config.snapshot_retries,
+                        ),
+                    )
+                    .await;
+                    (symbol, result)
+                }));
+            }
+        }
+
+        let ack_deadline = acks.next_deadline(SUBSCRIPTION_ACK_TIMEOUT);
+        // delayed requests are due to queue only while no request is in flight
+        let retry_at = resync
+            .next_retry_at()
+            .filter(|_| full_book_request.is_none());
+        let event = tokio::select! {
+            (symbol, full_book) = async { full_book_request.as_mut().unwrap().await },
+                if full_book_request.is_some() => HandlerEvent::FullBook(symbol, full_book),
+            _ = async { reconcile_interval.as_mut().unwrap().tick().await },
+                if reconcile_interval.is_some() => HandlerEvent::Reconcile,
+            _ = async { tokio::time::sleep_until(ack_deadline.unwrap().into()).await },
+                if ack_deadline.is_some() => HandlerEvent::AckTimeout,
+            _ = async { tokio::time::sleep_until(retry_at.unwrap()).await },
+                if retry_at.is_some() => HandlerEvent::RetryDue,
+            Some(command) = commands.recv() => HandlerEvent::Command(command),
+            message = read.next() => {
+                last_frame_at = tokio::time::Instant::now();
+                HandlerEvent::Message(message)
+            }
+            _ = tokio::time::sleep_until(last_frame_at + read_timeout) => HandlerEvent::ReadTimeout,
+        };
+
+        match event {
+            HandlerEvent::AckTimeout => {
+                for symbol in acks.expire(Instant::now(), SUBSCRIPTION_ACK_TIMEOUT) {
+                    warn!(
+                        symbol = symbol.as_str(),
+                        "Subscription isn't acknowledged within {SUBSCRIPTION_ACK_TIMEOUT:?}"
+                    );
+                }
+            }
+            HandlerEvent::RetryDue => {
+                // queued on the next iteration
+            }
+            HandlerEvent::Reconcile => {
+                for symbol in symbols.iter() {
+                    resync.reconcile(symbol);
+                }
+            }
+            HandlerEvent::Command(SymbolCommand::Add(symbol)) => {
+                if symbols.contains(&symbol) {
+                    continue;
+                }
+                // symbols are kept for the next connection, even if this one is broken
+                symbols.push(symbol.clone());
+                if let Err(e) =
+                    send_subscription(&mut write, &mut acks, config, "SUBSCRIBE", &symbol).await
+                {
+                    error!(symbol = symbol.as_str(), "Failed to subscribe ({e})");
+                    return HandlerExit::Disconnected;
+                }
+                order_books.insert(
+                    symbol.clone(),
+                    new_book(&symbol, config, &context.precisions),
+                );
+                if !config.partial_depth {
+                    resync.request(&symbol);
+                }
+            }
+            HandlerEvent::Command(SymbolCommand::Remove(symbol)) => {
+                if !symbols.contains(&symbol) {
+                    continue;
+                }
+                symbols.retain(|watched| *watched != symbol);
+                order_books.remove(&symbol);
+                resync.forget(&symbol);
+                unpublish_book(context, &symbol);
+                if let Err(e) =
+                    send_subscription(&mut write, &mut acks, config, "UNSUBSCRIBE", &symbol).await
+                {
+                    error!(symbol = symbol.as_str(), "Failed to unsubscribe ({e})");
+                    return HandlerExit::Disconnected;
+                }
+            }
+            HandlerEvent::FullBook(
+                symbol,
+                Err(e @ (FullBookError::InvalidSymbol(_) | FullBookError::Rejected(_))),
+            ) => {
+                full_book_request = None;
+                error!(
+                    symbol = symbol.as_str(),
+                    "Binance refuses the full book ({e}), skipping the symbol"
+                );
+                symbols.retain(|watched| *watched != symbol);
+                context.commands.lock().unwrap().forget(&symbol);
+                order_books.remove(&symbol);
+                resync.forget(&symbol);
+                unpublish_book(context, &symbol);
+                if let Err(e) =
+                    send_subscription(&mut write, &mut acks, config, "UNSUBSCRIBE", &symbol).await
+                {
+                    error!(symbol = symbol.as_str(), "Failed to unsubscribe ({e})");
+                    return HandlerExit::Disconnected;
+                }
+            }
+            HandlerEvent::FullBook(symbol, Err(e)) => {
+                full_book_request = None;
+                // unless the symbol is removed meanwhile
+                if order_books.contains_key(&symbol) {
+                    let delay = resync.retry_failed(&symbol, tokio::time::Instant::now());
+                    warn!(
+                        symbol = symbol.as_str(),
+                        "Failed to get full book ({e}), requesting again after {delay:?}"
+                    );
+                }
+            }
+            HandlerEvent::FullBook(symbol, Ok(full_book)) => {
+                full_book_request = None;
+                let event = FullBookEvent::new(&symbol, full_book);
+                if let Some(recorder) = &context.recorder {
+                    recorder.record(&serde_json::to_string(&event).unwrap());
+                }
+                let stream_message = StreamMessage::FullBook(event);
+                let book = handle_stream_message(
+                    &mut order_books,
+                    &mut resync,
+                    stats,
+                    Some(symbol),
+                    stream_message,
+                );
+                if let Some(book) = book {
+                    stats.record_resync(book);
+                }
+                publish_book(context, book);
+            }
+            HandlerEvent::Message(Some(Ok(msg))) => match msg {
+                Message::Ping(vec) => {
+                    // send PONG (todo improve with fire and forget)
+                    if let Err(e) = write.send(Message::Pong(vec)).await {
+                        error!("Failed to send pong ({e})");
+                        return HandlerExit::Disconnected;
+                    }
+                }
+                Message::Text(text) => handle_text_frame(
+                    context,
+                    &mut order_books,
+                    &mut resync,
+                    &mut acks,
+                    stats,
+                    &text,
+                ),
+                Message::Binary(bytes) => match std::str::from_utf8(&bytes) {
+                    // binary frames are routed as text, if they are
+                    Ok(text) => handle_text_frame(
+                        context,
+                        &mut order_books,
+                        &mut resync,
+                        &mut acks,
+                        stats,
+                        text,
+                    ),
+                    Err(e) => warn!("Skipping binary frame which isn't utf-8 ({e})"),
+                },
+                Message::Close(frame) => {
+                    let reason = match frame {
+                        Some(frame) => format!("{} {}", frame.code, frame.reason),
+                        None => "no reason".to_string(),
+                    };
+                    warn!("Connection closed by binance ({reason})");
+                    return HandlerExit::Disconnected;
+                }
+                _ => {
+                    // pong frames
+                }
+            },
+            HandlerEvent::Message(Some(Err(e))) => {
+                error!("Error receiving message: {}", e);
+                return HandlerExit::Disconnected;
+            }
+            HandlerEvent::Message(None) => {
+                warn!("Connection closed by binance");
+                return HandlerExit::Disconnected;
+            }
+            HandlerEvent::ReadTimeout => {
+                warn!(
+                    "Nothing received for {:?}, connection is dead",
+                    read_timeout
+                );
+                return HandlerExit::Disconnected;
+            }
+        }
+    }
+}
+
+/// records, routes and applies the frame, malformed ones are skipped
+fn handle_text_frame(
+    context: &HandlerContext,
+    order_books: &mut HashMap<String, OrderBook>,
+    resync: &mut BookResync,
+    acks: &mut PendingAcks,
+    stats: &mut WatchStats,
+    text: &str,
+) {
+    if let Some(recorder) = &context.recorder {
+        recorder.record(text);
+    }
+    let routed =
+        route_message(text, context.config.combined).map(|(symbol, message)| {
+            match context.config.partial_depth {
+                true => (symbol, message.into_partial_depth()),
+                false => (symbol, message),
+            }
+        });
+    match routed {
+        Ok((_, StreamMessage::SubscribeAck(ack))) => match acks.acknowledge(&ack.id) {
+            Some(symbol) => match ack.error_message() {
+                Some(error) => warn!(symbol = symbol.as_str(), "Subscription failed ({error})"),
+                None => info!(symbol = symbol.as_str(), "Subscription acknowledged"),
+            },
+            None => warn!("Skipping ack of unknown request: {text}"),
+        },
+        Ok((symbol, StreamMessage::ForceOrder(update))) => {
+            let order = &update.o;
+            info!(
+                symbol = symbol.unwrap_or_default().as_str(),
+                "Liquidation: {} {} at {} (average {})", order.S, order.q, order.p, order.ap
+            );
+            // nobody may be subscribed, it's fine
+            let _ = context.liquidations.send(update);
+        }
+        Ok((symbol, stream_message)) => {
+            let is_depth_update = matches!(
+                stream_message,
+                StreamMessage::DepthUpdate(_) | StreamMessage::PartialDepth(_)
+            );
+            let book = handle_stream_message(order_books, resync, stats, symbol, stream_message);
+            if let Some(book) = book.filter(|_| is_depth_update) {
+                stats.record_depth_update(book);
+                if let Some(event_time) = book.get_event_time() {
+                    stats.record_latency(book.get_symbol(), event_time, get_epoch_ms() as u64);
+                }
+                if let Some(update_lines) = &context.update_lines {
+                    update_lines(serde_json::to_string(&book.update_record()).unwrap());
+                }
+            }
+            publish_book(context, book);
+        }
+        Err(e) => warn!("Skipping malformed frame ({e}): {text}"),
+    }
+}
+
+/// drops what's published of the book of a symbol which isn't watched anymore
+fn unpublish_book(context: &HandlerContext, symbol: &str) {
+    context.metrics.forget(symbol);
+    context.alerts.forget(symbol);
+    if let Some(snapshots) = &context.snapshots {
+        snapshots.remove(symbol);
+    }
+}
+
+/// exposes the updated book via metrics, subscribers and output
+fn publish_book(context: &HandlerContext, book: Option<&OrderBook>) {
+    if let Some(book) = book {
+        context.metrics.record_book_update(book);
+        // nobody may be subscribed, it's fine
+        let ts = get_epoch_ms();
+        let _ = context.events.send(book.event(ts));
+        context.renders.publish(book);
+        if let Some(snapshots) = &context.snapshots {
+            snapshots.publish(book);
+        }
+        for alert in context.alerts.check(book, ts) {
+            warn!(symbol = alert.symbol.as_str(), "{}", alert.message());
+        }
+    }
+}
+
+/// reconstructs books from frames recorded with --record, without any network,
+/// full books are taken from the recording as well
+async fn replay(
+    config: &Config,
+    path: &str,
+    speed: Option<f64>,
+) -> Result<HashMap<String, OrderBook>, String> {
+    let mut order_books: HashMap<String, OrderBook> = config
+        .instruments
+        .iter()
+        .map(|symbol| (symbol.clone(), new_book(symbol, config, &HashMap::new())))
+        .collect();
+    let mut render_throttle = RenderThrottle::new(config.delay);
+    let mut resync = BookResync::default();
+    for symbol in &config.instruments {
+        resync.request(symbol);
+    }
+
+    let recording =
+        read_recording(path).map_err(|e| format!("Can't read recording {path}: {e}"))?;
+    let mut previous_timestamp: Option<u64> = None;
+    for line in recording.lines().filter(|line| !line.is_empty()) {
+        // e.g. the last line truncated by a crash while recording
+        let Some((timestamp, text)) = line
+            .split_once(' ')
+            .and_then(|(timestamp, text)| Some((timestamp.parse::<u64>().ok()?, text)))
+        else {
+            warn!("Skipping malformed recorded line: {line}");
+            continue;
+        };
+
+        // keep recorded pace
+        if let (Some(speed), Some(previous)) = (speed, previous_timestamp) {
+            let pause = timestamp.saturating_sub(previous) as f64 / speed;
+            tokio::time::sleep(Duration::from_secs_f64(pause / 1000.0)).await;
+        }
+        previous_timestamp = Some(timestamp);
+
+        let (symbol, stream_message) = match route_message(text, config.combined) {
+            Ok((symbol, message)) if config.partial_depth => (symbol, message.into_partial_depth()),
+            Ok(routed) => routed,
+            Err(e) => {
+                warn!("Skipping malformed frame ({e}): {text}");
+                continue;
+            }
+        };
+        // full book of a reconcile is recorded after the updates buffered meanwhile,
+        // they are applied already, so the book isn't rewound to the older full book
+        if let (Some(symbol), StreamMessage::FullBook(event)) = (&symbol, &stream_message) {
+            let is_outdated = order_books.get(symbol).is_some_and(|book| {
+                !resync.needs_resync(symbol)
+                    && book.get_last_update_id() >= event.book.last_update_id
+            });
+            if is_outdated {
+                continue;
+            }
+        }
+        let book = handle_stream_message(
+            &mut order_books,
+            &mut resync,
+            &mut WatchStats::default(),
+            symbol,
+            stream_message,
+        );
+        if let Some(book) = book {
+            if render_throttle.should_render_book(book, Instant::now()) {
+                println!("{}", render_book(book, config.output_format()))
+            }
+        }
+        // full books are never requested, they are in the recording
+        while resync.next_request().is_some() {}
+    }
+
+    for book in order_books.values() {
+        println!("{}", render_book(book, config.output_format()))
+    }
+    Ok(order_books)
+}
+
+/// why handler has finished
+enum HandlerExit {
+    // ctrl-c
+    Stopped,
+    // connection is closed or dead, reconnection required
+    Disconnected,
+}
+
+/// anything the handler waits for
+enum HandlerEvent {
+    FullBook(String, Result<FullBook, FullBookError>),
+    Message(Option<Result<Message, tungstenite::Error>>),
+    ReadTimeout,
+    Reconcile,
+    AckTimeout,
+    RetryDue,
+    Command(SymbolCommand),
+}
+
+/// frame read from the socket
+type Frame = Result<Message, tungstenite::Error>;
+
+/// reads the socket into the queue until either is closed, frames are dropped while the queue
+/// is full, books with dropped depth updates are synced again on the next gap,
+/// returns the number of dropped frames
+async fn queue_frames<S: Stream<Item = Frame> + Unpin>(
+    mut read: S,
+    frames: mpsc::Sender<Frame>,
+    metrics: Arc<Metrics>,
+) -> u64 {
+    let mut dropped = 0;
+    let mut overflow = 0;
+    while let Some(frame) = read.next().await {
+        match frames.try_send(frame) {
+            Ok(()) if overflow > 0 => {
+                warn!("Frame queue has room again, {overflow} frames dropped");
+                overflow = 0;
+            }
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                if overflow == 0 {
+                    warn!(
+                        "Frame queue is full ({} frames), dropping frames",
+                        frames.max_capacity()
+                    );
+                }
+                overflow += 1;
+                dropped += 1;
+                metrics.record_dropped_frame();
+            }
+            Err(TrySendError::Closed(_)) => break,
+        }
+    }
+    dropped
+}
+
+/// aborts the task once dropped, whichever way its owner returns
+struct AbortOnDrop<T>(tokio::task::JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// full book request of a symbol in flight
+type FullBookRequest =
+    Pin<Box<dyn Future<Output = (String, Result<FullBook, FullBookError>)> + Send>>;
+
+/// empty book of the symbol configured by the config
+fn new_book(symbol: &str, config: &Config, precisions: &HashMap<String, Precision>) -> OrderBook {
+    // configured decimals win over the ones of the symbol
+    let precision = precisions.get(symbol).copied().unwrap_or_default();
+    let precision = Precision {
+        price_decimals: config.price_decimals.or(precision.price_decimals),
+        quantity_decimals: config.qty_decimals.or(precision.quantity_decimals),
+    };
+    OrderBook::builder()
+        .symbol(symbol)
+        .levels(config.levels_of(symbol))
+        .market(config.market)
+        .precision(precision)
+        .ema_alpha(config.ema_alpha)
+        .trade_window_secs(config.trade_window_secs)
+        // books are cloned for rendering, trades are kept only if displayed
+        .trade_tape_size(match config.tape {
+            true => config.trade_tape_size as usize,
+            false => 0,
+        })
+        .build()
+}
+
+/// subscribes to (or unsubscribes from) topics of the symbol, the ack is waited for,
+/// a failed send means the connection is broken
+async fn send_subscription(
+    write: &mut (impl Sink<Message, Error = tungstenite::Error> + Unpin),
+    acks: &mut PendingAcks,
+    config: &Config,
+    method: &str,
+    symbol: &str,
+) -> Result<(), tungstenite::Error> {
+    let id = format!("{}_{}", symbol, get_epoch_ms());
+    let text = serde_json::to_string(&Subscription {
+        method: method.to_string(),
+        params: symbol_topics(symbol, config),
+        id: id.clone(),
+    })
+    .unwrap();
+    acks.sent(&id, symbol, Instant::now());
+
+    match method {
+        "UNSUBSCRIBE" => info!(symbol, "Unsubscribe from topic: {text}"),
+        _ => info!(symbol, "Subscribe to topic: {text}"),
+    }
+    write.send(Message::Text(text.into())).await
+}
+
+/// connections, topics and urls of the configured instruments, as chunked by run,
+/// symbols aren't validated, it would take an http request
+pub fn dry_run_plan(config: &Config) -> String {
+    let mut plan = String::from("====DRY RUN====\n");
+    let api_url = config.api_url();
+    plan.push_str(&format!("exchange info: {}/exchangeInfo\n", api_url));
+    for (connection, symbols) in config
+        .instruments
+        .chunks(config.instruments_per_connection())
+        .enumerate()
+    {
+        let topics: Vec<String> = symbols
+            .iter()
+            .flat_map(|symbol| symbol_topics(symbol, config))
+            .collect();
+        let url = match config.combined {
+            true => config.combined_stream_url(&topics),
+            false => config.ws_api_url(),
+        };
+        plan.push_str(&format!("connection {}: {}\n", connection, url));
+        if !config.combined {
+            plan.push_str(&format!("  subscribe: {}\n", topics.join(",")));
+        }
+        for symbol in symbols.iter().filter(|_| !config.partial_depth) {
+            plan.push_str(&format!(
+                "  full book: {}\n",
+                full_book_url(&api_url, symbol, config.snapshot_limit(symbol))
+            ));
+        }
+    }
+    plan.push_str(&format!(
+        "connections: {}, instruments: {}\n",
+        config.actual_connection_count(),
+        config.instruments.len()
+    ));
+    plan.push_str("====END DRY RUN====\n");
+    plan
+}
+
+/// all topics watched per symbol
+fn symbol_topics(symbol: &str, config: &Config) -> Vec<String> {
+    config
+        .streams
+        .iter()
+        .map(|stream| match stream {
+            StreamType::Depth if config.partial_depth => format!(
+                "{}@depth{}{}",
+                symbol,
+                config.partial_depth_levels(symbol),
+                config.update_speed.depth_stream_suffix()
+            ),
+            StreamType::Depth => format!(
+                "{}@{}{}",
+                symbol,
+                "depth",
+                config.update_speed.depth_stream_suffix()
+            ),
+            StreamType::AggTrade => format!("{}@{}", symbol, "aggTrade"),
+            StreamType::BookTicker => format!("{}@{}", symbol, "bookTicker"),
+        })
+        .chain(
+            config
+                .mark_price
+                .then(|| format!("{}@{}", symbol, "markPrice")),
+        )
+        .chain(config.ticker24h.then(|| format!("{}@{}", symbol, "ticker")))
+        .chain(
+            config
+                .kline
+                .as_ref()
+                .map(|interval| format!("{}@kline_{}", symbol, interval)),
+        )
+        .chain(
+            config
+                .liquidations
+                .then(|| format!("{}@{}", symbol, "forceOrder")),
+        )
+        .collect()
+}
+
+/// parses text frame, combined streams are unwrapped from the envelope and routed by stream name
+fn route_message(
+    text: &str,
+    combined: bool,
+) -> Result<(Option<String>, StreamMessage), serde_json::Error> {
+    // acks and recorded full books aren't wrapped even for combined streams
+    if combined {
+        if let Ok(envelope) = serde_json::from_str::<CombinedStreamMessage>(text) {
+            let symbol = envelope.symbol().to_string();
+            let message = serde_json::from_value(envelope.data)?;
+            return Ok((Some(symbol), message));
+        }
+    }
+    let message: StreamMessage = read_str(text)?;
+    Ok((message.symbol(), message))
+}
+
+/// applies message to the book of the symbol, depth updates are buffered while the symbol
+/// waits for the full book and a gap in depth updates marks the symbol for resync
+fn handle_stream_message<'a>(
+    order_books: &'a mut HashMap<String, OrderBook>,
+    resync: &mut BookResync,
+    stats: &mut WatchStats,
+    symbol: Option<String>,
+    message: StreamMessage,
+) -> Option<&'a OrderBook> {
+    match (symbol, message) {
+        (Some(symbol), StreamMessage::DepthUpdate(update)) if resync.needs_resync(&symbol) => {
+            resync.buffer(&symbol, update);
+            None
+        }
+        (Some(symbol), StreamMessage::FullBook(event)) => {
+            let book = order_books.get_mut(&symbol)?;
+            match resync.complete(book, &event.book) {
+                Ok(mismatches) if !mismatches.is_empty() => {
+                    book.record_drift();
+                    let mismatches: Vec<String> =
+                        mismatches.iter().map(ToString::to_string).collect();
+                    warn!(
+                        symbol = symbol.as_str(),
+                        "Book drifted from the full book (drift #{}): {}",
+                        book.get_drift_count(),
+                        mismatches.join(", ")
+                    );
+                    Some(book)
+                }
+                Ok(_) => Some(book),
+                Err(e) => {
+                    error!(
+                        symbol = symbol.as_str(),
+                        "Failed to apply full book ({e}), requesting again"
+                    );
+                    None
+                }
+            }
+        }
+        (symbol, message) => {
+            let gap_symbol = symbol.clone().unwrap_or_default();
+            let is_depth_update = matches!(message, StreamMessage::DepthUpdate(_));
+            apply_stream_message(order_books, symbol, message).unwrap_or_else(|e| {
+                if let ApplyError::SequenceGap { .. } = e {
+                    stats.record_gap(&gap_symbol);
+                }
+                if is_depth_update {
+                    warn!(
+                        symbol = gap_symbol.as_str(),
+                        "Failed to apply depth update ({e}), requesting full book"
+                    );
+                    resync.request(&gap_symbol);
+                } else {
+                    warn!(symbol = gap_symbol.as_str(), "Skipping message ({e})");
+                }
+                None
+            })
+        }
+    }
+}
+
+/// applies message to the book of the symbol, returns the book if it has to be displayed
+fn apply_stream_message(
+    order_books: &mut HashMap<String, OrderBook>,
+    symbol: Option<String>,
+    message: StreamMessage,
+) -> Result<Option<&OrderBook>, ApplyError> {
+    let Some(book) = symbol.and_then(|symbol| order_books.get_mut(&symbol)) else {
+        // subscriptions acks and messages of not watched symbols
+        return Ok(None);
+    };
+    match message {
+        StreamMessage::AggTrade(trade) => {
+            book.apply_agg_trade(&trade)?;
+            Ok(None)
+        }
+        StreamMessage::DepthUpdate(book_update) => {
+            match book.apply_depth_book_update_from_websocket(&book_update)? {
+                ApplyResult::Applied => Ok(Some(book)),
+                ApplyResult::AlreadyApplied => Ok(None),
+            }
+        }
+        StreamMessage::BookTicker(ticker) => {
+            book.apply_book_ticker(&ticker)?;
+            Ok(None)
+        }
+        StreamMessage::MarkPrice(update) => {
+            book.apply_mark_price(&update)?;
+            Ok(None)
+        }
+        StreamMessage::Ticker24hr(ticker) => {
+            book.apply_ticker_24hr(&ticker)?;
+            Ok(None)
+        }
+        StreamMessage::Kline(update) => {
+            book.apply_kline(&update)?;
+            Ok(None)
+        }
+        StreamMessage::FullBook(event) => {
+            book.apply_full_book_from_http_api(&event.book)?;
+            Ok(Some(book))
+        }
+        StreamMessage::PartialDepth(update) => match book.apply_partial_depth(&update)? {
+            ApplyResult::Applied => Ok(Some(book)),
+            ApplyResult::AlreadyApplied => Ok(None),
+        },
+        StreamMessage::SubscribeAck(_) | StreamMessage::ForceOrder(_) | StreamMessage::Other(_) => {
+            Ok(None)
+        }
+    }
+}
+
+/// runs the request once a permit is available, so concurrent requests are limited
+async fn limited<F: Future>(semaphore: &Semaphore, request: F) -> F::Output {
+    let _permit = semaphore
+        .acquire()
+        .await
+        .expect("Semaphore is never closed");
+    request.await
+}
+
+fn full_book_url(api_url: &str, symbol: &str, limit: u32) -> String {
+    format!(
+        "{}/depth?symbol={}&limit={}",
+        api_url,
+        symbol.to_uppercase(),
+        limit
+    )
+}
+
+/// requests full book via http api, connections are reused by the client,
+/// network and server errors are retried the given times
+async fn fetch_full_book(
+    http_client: &reqwest::Client,
+    api_url: &str,
+    symbol: &str,
+    limit: u32,
+    retries: u32,
+) -> Result<FullBook, FullBookError> {
+    let url = full_book_url(api_url, symbol, limit);
+    let mut attempt = 1;
+    let mut retries_left = retries;
+    let response = loop {
+        let response = match http_client.get(&url).send().await {
+            Ok(response) if !response.status().is_server_error() => response,
+            result => {
+                let error = match result {
+                    Ok(response) => format!("server error ({})", response.status()),
+                    Err(e) => e.to_string(),
+                };
+                if retries_left == 0 {
+                    return Err(FullBookError::Failed(error));
+                }
+                retries_left -= 1;
+                warn!(
+                    symbol,
+                    "Full book request failed ({error}), retrying after {SNAPSHOT_RETRY_DELAY:?}"
+                );
+                tokio::time::sleep(SNAPSHOT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        // 429 is a warning, 418 is an ip ban for repeated 429s, both tell when to retry
+        let status = response.status();
+        if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::IM_A_TEAPOT {
+            break response;
+        }
+        if attempt == RATE_LIMITED_ATTEMPTS {
+            return Err(FullBookError::Failed(format!(
+                "rate limited ({status}) {attempt} times"
+            )));
+        }
+        let retry_after = retry_after(response.headers()).unwrap_or(DEFAULT_RETRY_AFTER);
+        warn!(
+            symbol,
+            "Full book request rate limited ({status}), retrying after {retry_after:?}"
+        );
+        tokio::time::sleep(retry_after).await;
+        attempt += 1;
+    };
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| FullBookError::Failed(e.to_string()))?;
+    parse_full_book(&body).map_err(|e| match e {
+        FullBookError::Failed(e) if status.is_client_error() => {
+            FullBookError::Rejected(format!("{status} {e}"))
+        }
+        e => e,
+    })
+}
+
+/// failed full book request
+#[derive(Debug, PartialEq)]
+enum FullBookError {
+    /// binance doesn't know the symbol, there is no point to request it again
+    InvalidSymbol(String),
+    /// binance answered with a client error other than rate limits, e.g. 403,
+    /// the same request would be refused again
+    Rejected(String),
+    Failed(String),
+}
+
+impl Display for FullBookError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FullBookError::InvalidSymbol(msg) => write!(f, "invalid symbol, {}", msg),
+            FullBookError::Rejected(e) => write!(f, "rejected, {}", e),
+            FullBookError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// full book or binance error of the http api body
+fn parse_full_book(body: &str) -> Result<FullBook, FullBookError> {
+    read_str::<FullBook>(body).map_err(|e| match read_str::<ApiError>(body) {
+        Ok(error) if error.code == INVALID_SYMBOL_CODE => FullBookError::InvalidSymbol(error.msg),
+        Ok(error) => FullBookError::Failed(format!("binance error {} {}", error.code, error.msg)),
+        Err(_) => FullBookError::Failed(e.to_string()),
+    })
+}
+
+/// pause asked by Retry-After header in seconds
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds = headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// opens websocket connection, a black hole network fails after the timeout,
+/// permessage-deflate is offered if compressed, tungstenite doesn't implement it
+async fn connect_to_binance(
+    url: &str,
+    timeout: Duration,
+    compress: bool,
+) -> Result<(MessageSink, MessageStream), String> {
+    let connect = async {
+        if compress {
+            return connect_deflate(url).await;
+        }
+        let (ws_stream, _) = connect_async(url).await.map_err(|e| e.to_string())?;
+        let (write, read) = ws_stream.split();
+        Ok((
+            Box::pin(write) as MessageSink,
+            Box::pin(read) as MessageStream,
+        ))
+    };
+    tokio::time::timeout(timeout, connect)
+        .await
+        .map_err(|_| format!("timed out after {timeout:?}"))?
+}
+
+// utils
+
+fn read_str<'a, T>(msg: &'a str) -> Result<T, serde_json::Error>
+where
+    T: Deserialize<'a>,
+{
+    serde_json::from_str::<'a, T>(msg)
+}
+
+fn get_epoch_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::console_arguments::test::parse;
+    use crate::console_arguments::UpdateSpeed;
+    use crate::messages::LevelApi;
+    use crate::number::number;
+    use crate::order_book::{BookSnapshot, Level, Side};
+    use std::sync::atomic::AtomicUsize;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+    use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Role};
+    use tokio_tungstenite::WebSocketStream;
+
+    #[test]
+    fn route_combined_message_test() {
+        let mut order_books: HashMap<String, OrderBook> = ["btcusdt", "ethusdt"]
+            .iter()
+            .map(|symbol| (symbol.to_string(), OrderBook::new(5, symbol.to_string())))
+            .collect();
+        for book in order_books.values_mut() {
+            book.apply_full_book_from_http_api(&FullBook {
+                last_update_id: 10,
+                bids: vec![],
+                asks: vec![LevelApi {
+                    price: "2".to_string(),
+                    quantity: "1".to_string(),
+                }],
+            })
+            .unwrap();
+        }
+
+        let text = r#"{"stream":"ethusdt@depth","data":{"e":"depthUpdate","E":1,"T":1,"s":"ETHUSDT","U":5,"u":15,"pu":0,"b":[],"a":[]}}"#;
+        let (symbol, message) = route_message(text, true).unwrap();
+        let book = apply_stream_message(&mut order_books, symbol, message)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(book.get_symbol(), "ethusdt");
+        assert_eq!(order_books["ethusdt"].get_last_update_id(), 15);
+        assert_eq!(order_books["btcusdt"].get_last_update_id(), 10);
+    }
+
+    #[test]
+    fn route_combined_message_numbers_test() {
+        let mut order_books: HashMap<String, OrderBook> = [(
+            "btcusdt".to_string(),
+            OrderBook::new(5, "btcusdt".to_string()),
+        )]
+        .into();
+        let text = r#"{"stream":"btcusdt@depth","data":{"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":5,"u":15,"pu":0,"b":[["59000.10","0.1"],["59000.00","0.2"]],"a":[["59000.30","0.00000003"]]}}"#;
+        order_books
+            .get_mut("btcusdt")
+            .unwrap()
+            .apply_full_book_from_http_api(&FullBook {
+                last_update_id: 10,
+                bids: vec![],
+                asks: vec![],
+            })
+            .unwrap();
+        let (symbol, message) = route_message(text, true).unwrap();
+        let book = apply_stream_message(&mut order_books, symbol, message)
+            .unwrap()
+            .unwrap();
+
+        // prices and quantities of the envelope data are parsed from strings as they are
+        let best_bid = book.get_best_bid().unwrap();
+        assert_eq!(best_bid.price, number("59000.1"));
+        assert_eq!(best_bid.quantity, number("0.1"));
+        assert_eq!(book.get_best_ask().unwrap().quantity, number("0.00000003"));
+        // exactly 0.2 with decimals
+        assert_eq!(
+            book.get_spread(),
+            Some(number("59000.30") - number("59000.10"))
+        );
+        assert_eq!(
+            book.total_quantity(Side::Bid),
+            Some(number("0.1") + number("0.2"))
+        );
+    }
+
+    /// keep-alive http server of the tests, the response is built from the request index,
+    /// counted from 0 over all connections
+    struct MockHttp {
+        api_url: String,
+        requests: Arc<AtomicUsize>,
+        connections: Arc<AtomicUsize>,
+    }
+
+    async fn mock_http(respond: impl Fn(usize) -> String + Send + Sync + 'static) -> MockHttp {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let respond = Arc::new(respond);
+        let (served, accepted) = (requests.clone(), connections.clone());
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let (served, respond) = (served.clone(), respond.clone());
+                tokio::spawn(async move {
+                    let mut buffer = [0u8; 4096];
+                    while let Ok(read) = socket.read(&mut buffer).await {
+                        if read == 0 {
+                            break;
+                        }
+                        let response = respond(served.fetch_add(1, Ordering::SeqCst));
+                        socket.write_all(response.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        MockHttp {
+            api_url,
+            requests,
+            connections,
+        }
+    }
+
+    fn http_response(status: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\ncontent-length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    #[tokio::test]
+    async fn fetch_full_book_reuses_connection_test() {
+        let http = mock_http(|_| {
+            http_response(
+                "200 OK",
+                r#"{"lastUpdateId":7,"bids":[["1","2"]],"asks":[]}"#,
+            )
+        })
+        .await;
+
+        let http_client = reqwest::Client::new();
+        for _ in 0..3 {
+            let book = fetch_full_book(&http_client, &http.api_url, "btcusdt", 5, 0)
+                .await
+                .unwrap();
+            assert_eq!(book.last_update_id, 7);
+        }
+
+        assert_eq!(http.requests.load(Ordering::SeqCst), 3);
+        assert_eq!(http.connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn fetch_full_book_rate_limited_test() {
+        // the first request is rate limited, the next ones get the book
+        let http = mock_http(|request| match request {
+            0 => "HTTP/1.1 429 Too Many Requests\r\nretry-after: 1\r\ncontent-length: 0\r\n\r\n"
+                .to_string(),
+            _ => http_response(
+                "200 OK",
+                r#"{"lastUpdateId":7,"bids":[["1","2"]],"asks":[]}"#,
+            ),
+        })
+        .await;
+
+        let started = Instant::now();
+        let book = fetch_full_book(&reqwest::Client::new(), &http.api_url, "btcusdt", 5, 0)
+            .await
+            .unwrap();
+        assert_eq!(book.last_update_id, 7);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, "30".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(30)));
+    }
+
+    #[tokio::test]
+    async fn fetch_full_book_rate_limit_attempts_test() {
+        // banned ip, every request is answered with 418
+        let http = mock_http(|_| {
+            "HTTP/1.1 418 I'm a teapot\r\nretry-after: 0\r\ncontent-length: 0\r\n\r\n".to_string()
+        })
+        .await;
+
+        let result = fetch_full_book(&reqwest::Client::new(), &http.api_url, "btcusdt", 5, 0).await;
+        assert_eq!(
+            result.err(),
+            Some(FullBookError::Failed(
+                "rate limited (418 I'm a teapot) 3 times".to_string()
+            ))
+        );
+        assert_eq!(http.requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn fetch_full_book_retries_test() {
+        // binance fails twice, the third request gets the book
+        let http = mock_http(|request| match request % 3 {
+            0 | 1 => http_response("503 Service Unavailable", ""),
+            _ => http_response(
+                "200 OK",
+                r#"{"lastUpdateId":7,"bids":[["1","2"]],"asks":[]}"#,
+            ),
+        })
+        .await;
+
+        let http_client = reqwest::Client::new();
+        let result = fetch_full_book(&http_client, &http.api_url, "btcusdt", 5, 1).await;
+        assert_eq!(
+            result.err(),
+            Some(FullBookError::Failed(
+                "server error (503 Service Unavailable)".to_string()
+            ))
+        );
+        assert_eq!(http.requests.load(Ordering::SeqCst), 2);
+
+        http.requests.store(0, Ordering::SeqCst);
+        let started = Instant::now();
+        let book = fetch_full_book(&http_client, &http.api_url, "btcusdt", 5, 2)
+            .await
+            .unwrap();
+        assert_eq!(book.last_update_id, 7);
+        assert_eq!(http.requests.load(Ordering::SeqCst), 3);
+        assert!(started.elapsed() >= SNAPSHOT_RETRY_DELAY * 2);
+    }
+
+    #[tokio::test]
+    async fn fetch_full_book_invalid_symbol_test() {
+        let body = r#"{"code":-1121,"msg":"Invalid symbol."}"#;
+        assert_eq!(
+            parse_full_book(body).err(),
+            Some(FullBookError::InvalidSymbol("Invalid symbol.".to_string()))
+        );
+        assert_eq!(
+            parse_full_book(r#"{"code":-1003,"msg":"Too many requests."}"#).err(),
+            Some(FullBookError::Failed(
+                "binance error -1003 Too many requests.".to_string()
+            ))
+        );
+        assert!(matches!(
+            parse_full_book("<html>"),
+            Err(FullBookError::Failed(_))
+        ));
+        assert_eq!(
+            parse_full_book(r#"{"lastUpdateId":7,"bids":[],"asks":[]}"#)
+                .unwrap()
+                .last_update_id,
+            7
+        );
+
+        // binance answers a mistyped symbol with 400, it isn't retried
+        let http = mock_http(move |_| http_response("400 Bad Request", body)).await;
+        let result = fetch_full_book(&reqwest::Client::new(), &http.api_url, "btcusdx", 5, 2).await;
+        assert_eq!(
+            result.err(),
+            Some(FullBookError::InvalidSymbol("Invalid symbol.".to_string()))
+        );
+        assert_eq!(http.requests.load(Ordering::SeqCst), 1);
+
+        // other client errors aren't retried either
+        let http = mock_http(|_| http_response("403 Forbidden", "")).await;
+        let result = fetch_full_book(&reqwest::Client::new(), &http.api_url, "btcusdt", 5, 2).await;
+        assert!(
+            matches!(&result, Err(FullBookError::Rejected(e)) if e.starts_with("403 Forbidden")),
+            "{:?}",
+            result.err()
+        );
+        assert_eq!(http.requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn failed_full_book_backoff_test() {
+        // binance fails the first two rounds, then serves the book
+        let requested_at = Arc::new(Mutex::new(vec![]));
+        let requests = requested_at.clone();
+        let http = mock_http(move |request| {
+            requests.lock().unwrap().push(Instant::now());
+            match request {
+                0 | 1 => http_response("503 Service Unavailable", ""),
+                _ => http_response("200 OK", r#"{"lastUpdateId":7,"bids":[],"asks":[]}"#),
+            }
+        })
+        .await;
+        let (client, _server) = tokio::io::duplex(4096);
+        let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+        let context = test_context(Config {
+            read_timeout: 10000,
+            streams: vec![StreamType::Depth],
+            api_url: Some(http.api_url.clone()),
+            ..Default::default()
+        });
+
+        let (write, read) = client.split();
+        let all_requested = async {
+            while http.requests.load(Ordering::SeqCst) < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let mut symbols = vec!["btcusdt".to_string()];
+        let mut stats = WatchStats::default();
+        let (_commands_sender, mut commands) = mpsc::unbounded_channel();
+        let handler = handle_updates(
+            &context,
+            &mut symbols,
+            &mut stats,
+            &mut commands,
+            read,
+            write,
+        );
+        tokio::time::timeout(Duration::from_secs(10), async {
+            tokio::select! {
+                _ = handler => panic!("handler exited"),
+                _ = all_requested => {}
+            }
+        })
+        .await
+        .expect("full book isn't requested again");
+
+        // the pause doubles per failed round
+        let requested_at = requested_at.lock().unwrap();
+        let pauses: Vec<Duration> = requested_at
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .collect();
+        assert!(pauses[0] >= Duration::from_secs(1), "{pauses:?}");
+        assert!(pauses[0] < Duration::from_millis(1500), "{pauses:?}");
+        assert!(pauses[1] >= Duration::from_secs(2), "{pauses:?}");
+        assert!(pauses[1] < Duration::from_millis(2500), "{pauses:?}");
+    }
+
+    #[test]
+    fn handle_stream_message_resync_test() {
+        let mut order_books: HashMap<String, OrderBook> = ["btcusdt", "ethusdt"]
+            .iter()
+            .map(|symbol| (symbol.to_string(), OrderBook::new(5, symbol.to_string())))
+            .collect();
+        let mut resync = BookResync::default();
+        let mut stats = WatchStats::default();
+        for book in order_books.values_mut() {
+            resync.request(book.get_symbol());
+            resync.next_request();
+            let full_book = FullBook {
+                last_update_id: 10,
+                bids: vec![],
+                asks: vec![],
+            };
+            resync.complete(book, &full_book).unwrap();
+        }
+
+        // out of range update, U > lastUpdateId
+        let text =
+            r#"{"e":"depthUpdate","E":1,"T":1,"s":"ETHUSDT","U":20,"u":25,"pu":19,"b":[],"a":[]}"#;
+        let (symbol, message) = route_message(text, false).unwrap();
+        assert!(
+            handle_stream_message(&mut order_books, &mut resync, &mut stats, symbol, message)
+                .is_none()
+        );
+
+        // only the failed symbol is resynced, the other one keeps applying updates
+        assert!(resync.needs_resync("ethusdt"));
+        assert!(!resync.needs_resync("btcusdt"));
+        assert_eq!(resync.next_request(), Some("ethusdt".to_string()));
+
+        let text =
+            r#"{"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":5,"u":15,"pu":4,"b":[],"a":[]}"#;
+        let (symbol, message) = route_message(text, false).unwrap();
+        let book =
+            handle_stream_message(&mut order_books, &mut resync, &mut stats, symbol, message)
+                .unwrap();
+        assert_eq!(book.get_last_update_id(), 15);
+
+        // updates of the resynced symbol are buffered until its full book
+        let text =
+            r#"{"e":"depthUpdate","E":1,"T":1,"s":"ETHUSDT","U":26,"u":30,"pu":25,"b":[],"a":[]}"#;
+        let (symbol, message) = route_message(text, false).unwrap();
+        assert!(
+            handle_stream_message(&mut order_books, &mut resync, &mut stats, symbol, message)
+                .is_none()
+        );
+        assert_eq!(order_books["ethusdt"].get_last_update_id(), 10);
+    }
+
+    #[test]
+    fn sequence_gap_stats_test() {
+        let mut book = OrderBook::new(5, "btcusdt".to_string());
+        book.apply_full_book_from_http_api(&FullBook {
+            last_update_id: 10,
+            bids: vec![],
+            asks: vec![],
+        })
+        .unwrap();
+        let mut order_books = HashMap::from([("btcusdt".to_string(), book)]);
+        let mut resync = BookResync::default();
+        let mut stats = WatchStats::default();
+        let mut handle = |text: &str| {
+            let (symbol, message) = route_message(text, false).unwrap();
+            handle_stream_message(&mut order_books, &mut resync, &mut stats, symbol, message)
+                .map(OrderBook::get_last_update_id)
+        };
+
+        let update = |first: u64, last: u64, previous: u64| {
+            format!(
+                r#"{{"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":{first},"u":{last},"pu":{previous},"b":[],"a":[]}}"#
+            )
+        };
+        assert_eq!(handle(&update(9, 11, 0)), Some(11));
+        assert_eq!(handle(&update(12, 13, 11)), Some(13));
+        // 14..=19 are missed
+        assert_eq!(handle(&update(20, 25, 19)), None);
+        // buffered until the full book, not another gap
+        assert_eq!(handle(&update(26, 27, 25)), None);
+        let full_book = FullBookEvent::new(
+            "btcusdt",
+            FullBook {
+                last_update_id: 30,
+                bids: vec![],
+                asks: vec![],
+            },
+        );
+        assert_eq!(
+            handle(&serde_json::to_string(&full_book).unwrap()),
+            Some(30)
+        );
+        assert_eq!(handle(&update(29, 31, 27)), Some(31));
+        assert_eq!(handle(&update(40, 41, 35)), None);
+
+        assert_eq!(stats.get("btcusdt").unwrap().gaps, 2);
+    }
+
+    /// socket stream of text frames "0", "1", ...
+    fn text_frames(count: u32) -> impl Stream<Item = Frame> + Unpin {
+        let frames = (0..count).map(|id| Message::Text(id.to_string().into()));
+        futures_util::stream::iter(frames.map(Ok))
+    }
+
+    #[tokio::test]
+    async fn queue_frames_overflow_test() {
+        let metrics = Arc::new(Metrics::default());
+        let (sender, mut frames) = mpsc::channel(3);
+        let socket = text_frames(10);
+
+        // nothing is applied meanwhile, the queue holds its capacity only
+        let dropped = queue_frames(socket, sender, metrics.clone()).await;
+
+        assert_eq!(dropped, 7);
+        let mut queued = vec![];
+        while let Some(Ok(frame)) = frames.recv().await {
+            queued.push(frame.into_text().unwrap().to_string());
+        }
+        assert_eq!(queued, vec!["0", "1", "2"]);
+        assert!(metrics
+            .render()
+            .contains("binance_watcher_dropped_frames_total 7\n"));
+
+        // a closed queue stops reading
+        let (sender, frames) = mpsc::channel(3);
+        drop(frames);
+        let socket = text_frames(10);
+        assert_eq!(queue_frames(socket, sender, metrics).await, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn read_timeout_test() {
+        // silent socket, reconcile ticks keep coming meanwhile
+        let context = test_context(Config {
+            read_timeout: 2500,
+            reconcile_interval: Some(1),
+            combined: true,
+            ..Default::default()
+        });
+        let write = futures_util::sink::drain().sink_map_err(|e| match e {});
+        let read = futures_util::stream::pending();
+        let started = tokio::time::Instant::now();
+        let exit = tokio::time::timeout(
+            Duration::from_secs(10),
+            handle_updates(
+                &context,
+                &mut vec![],
+                &mut WatchStats::default(),
+                &mut mpsc::unbounded_channel().1,
+                read,
+                write,
+            ),
+        )
+        .await
+        .expect("silent socket isn't noticed");
+        assert!(matches!(exit, HandlerExit::Disconnected));
+        assert_eq!(started.elapsed(), Duration::from_millis(2500));
+    }
+
+    #[tokio::test]
+    async fn replay_test() {
+        let path =
+            std::env::temp_dir().join(format!("binance_watcher_replay_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let recording = [
+            // buffered while the full book was requested
+            r#"1000 {"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":96,"u":105,"pu":95,"b":[["10","2"]],"a":[]}"#,
+            r#"1010 {"e":"fullBook","s":"btcusdt","lastUpdateId":100,"bids":[["10","1"],["9","1"]],"asks":[["11","1"]]}"#,
+            r#"1020 {"e":"depthUpdate","E":2,"T":2,"s":"BTCUSDT","U":106,"u":110,"pu":105,"b":[],"a":[["12","3"]]}"#,
+            "1025",
+            "x1026 {}",
+            r#"1030 {"e":"aggTrade","E":3,"s":"BTCUSDT","a":1,"p":"10.5","q":"1","f":1,"l":1,"T":3,"m":true}"#,
+            // truncated by a crash while recording
+            r#"1040 {"e":"aggTrade","E":4,"s":"BTC"#,
+        ];
+        std::fs::write(path, recording.join("\n")).unwrap();
+
+        let config = Config {
+            instruments: vec!["btcusdt".to_string()],
+            levels: 5,
+            delay: 1000,
+            ..Default::default()
+        };
+        let books = replay(&config, path, None).await.unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let book = &books["btcusdt"];
+        assert_eq!(book.get_last_update_id(), 110);
+        assert_eq!(
+            book.get_best_bid().unwrap(),
+            Level {
+                quantity: number("2.0"),
+                price: number("10.0")
+            }
+        );
+        assert_eq!(
+            book.get_best_ask().unwrap(),
+            Level {
+                quantity: number("1.0"),
+                price: number("11.0")
+            }
+        );
+        assert_eq!(book.get_last_trade(), Some(number("10.5")));
+        assert_eq!(book.snapshot().asks.len(), 2);
+
+        // the recording is gone
+        let Err(error) = replay(&config, path, None).await else {
+            panic!("missing recording replayed");
+        };
+        assert!(
+            error.starts_with(&format!("Can't read recording {path}: ")),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_reconcile_test() {
+        let path = std::env::temp_dir().join(format!(
+            "binance_watcher_replay_reconcile_{}.txt",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        let recording = [
+            r#"1000 {"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":96,"u":105,"pu":95,"b":[["10","2"]],"a":[]}"#,
+            r#"1010 {"e":"fullBook","s":"btcusdt","lastUpdateId":100,"bids":[["10","1"]],"asks":[["11","1"]]}"#,
+            r#"1020 {"e":"depthUpdate","E":2,"T":2,"s":"BTCUSDT","U":106,"u":110,"pu":105,"b":[["10","3"]],"a":[]}"#,
+            // buffered while the full book was requested to reconcile the book
+            r#"1030 {"e":"depthUpdate","E":3,"T":3,"s":"BTCUSDT","U":111,"u":112,"pu":110,"b":[["10","4"]],"a":[]}"#,
+            r#"1040 {"e":"depthUpdate","E":4,"T":4,"s":"BTCUSDT","U":113,"u":115,"pu":112,"b":[["10","5"]],"a":[]}"#,
+            r#"1050 {"e":"fullBook","s":"btcusdt","lastUpdateId":112,"bids":[["10","4"]],"asks":[["11","1"]]}"#,
+            r#"1060 {"e":"depthUpdate","E":5,"T":5,"s":"BTCUSDT","U":116,"u":118,"pu":115,"b":[["10","6"]],"a":[]}"#,
+        ];
+        std::fs::write(path, recording.join("\n")).unwrap();
+
+        let config = Config {
+            instruments: vec!["btcusdt".to_string()],
+            levels: 5,
+            delay: 1000,
+            ..Default::default()
+        };
+        let books = replay(&config, path, None).await.unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        // as the live book, which was never rewound
+        let book = &books["btcusdt"];
+        assert_eq!(book.get_last_update_id(), 118);
+        assert_eq!(
+            book.get_best_bid().unwrap(),
+            Level {
+                quantity: number("6.0"),
+                price: number("10.0")
+            }
+        );
+    }
+
+    #[test]
+    fn symbol_topics_update_speed_test() {
+        let mut config = Config {
+            update_speed: UpdateSpeed::Ms100,
+            streams: vec![
+                StreamType::Depth,
+                StreamType::AggTrade,
+                StreamType::BookTicker,
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            symbol_topics("btcusdt", &config),
+            vec![
+                "btcusdt@depth@100ms",
+                "btcusdt@aggTrade",
+                "btcusdt@bookTicker"
+            ]
+        );
+
+        config.update_speed = UpdateSpeed::Ms500;
+        assert_eq!(symbol_topics("btcusdt", &config)[0], "btcusdt@depth@500ms");
+
+        config.update_speed = UpdateSpeed::Ms250;
+        assert_eq!(symbol_topics("btcusdt", &config)[0], "btcusdt@depth");
+    }
+
+    #[test]
+    fn new_book_instrument_levels_test() {
+        let mut config = parse(&[
+            "binance_watcher",
+            "-l",
+            "20",
+            "-i",
+            "btcusdt:100",
+            "-i",
+            "dogeusdt:5",
+            "-i",
+            "ethusdt",
+        ])
+        .unwrap();
+        config.load_instruments().unwrap();
+        let levels: Vec<(String, u32)> = config
+            .instruments
+            .iter()
+            .map(|symbol| new_book(symbol, &config, &HashMap::new()))
+            .map(|book| (book.get_symbol().to_string(), book.get_levels()))
+            .collect();
+        assert_eq!(
+            levels,
+            vec![
+                ("btcusdt".to_string(), 100),
+                ("dogeusdt".to_string(), 5),
+                ("ethusdt".to_string(), 20),
+            ]
+        );
+    }
+
+    #[test]
+    fn new_book_precision_test() {
+        let full_book: FullBook =
+            serde_json::from_str(r#"{"lastUpdateId":1,"bids":[["0.5","10"]],"asks":[]}"#).unwrap();
+        let precisions = HashMap::from([(
+            "btcusdt".to_string(),
+            Precision {
+                price_decimals: Some(1),
+                quantity_decimals: Some(3),
+            },
+        )]);
+        let render = |config: Config| {
+            let config = Config {
+                levels: 1,
+                ..config
+            };
+            let mut book = new_book("btcusdt", &config, &precisions);
+            book.apply_full_book_from_http_api(&full_book).unwrap();
+            book.to_string()
+        };
+
+        let table = render(Config::default());
+        assert!(table.contains("|    10.000|       0.5|"));
+
+        let table = render(Config {
+            price_decimals: Some(2),
+            ..Default::default()
+        });
+        assert!(table.contains("|    10.000|      0.50|"));
+
+        let table = render(Config {
+            price_decimals: Some(6),
+            qty_decimals: Some(0),
+            ..Default::default()
+        });
+        assert!(table.contains("|        10|  0.500000|"));
+    }
+
+    #[test]
+    fn symbol_topics_streams_test() {
+        let config = Config {
+            streams: vec![StreamType::Depth],
+            ..Default::default()
+        };
+        assert_eq!(symbol_topics("btcusdt", &config), vec!["btcusdt@depth"]);
+
+        let config = Config {
+            streams: vec![StreamType::BookTicker, StreamType::AggTrade],
+            ..Default::default()
+        };
+        assert_eq!(
+            symbol_topics("btcusdt", &config),
+            vec!["btcusdt@bookTicker", "btcusdt@aggTrade"]
+        );
+
+        let config = Config {
+            streams: vec![StreamType::Depth],
+            mark_price: true,
+            kline: Some("5m".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            symbol_topics("btcusdt", &config),
+            vec!["btcusdt@depth", "btcusdt@markPrice", "btcusdt@kline_5m"]
+        );
+
+        let config = Config {
+            streams: vec![StreamType::Depth],
+            ticker24h: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            symbol_topics("btcusdt", &config),
+            vec!["btcusdt@depth", "btcusdt@ticker"]
+        );
+    }
+
+    #[test]
+    fn dry_run_plan_test() {
+        let config = Config {
+            connections: 2,
+            levels: 5,
+            instruments: vec![
+                "btcusdt".to_string(),
+                "ethusdt".to_string(),
+                "bnbusdt".to_string(),
+            ],
+            streams: vec![StreamType::Depth, StreamType::AggTrade],
+            ws_api_url: Some("wss://fstream.binance.com/ws".to_string()),
+            api_url: Some("https://fapi.binance.com/fapi/v1".to_string()),
+            dry_run: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            dry_run_plan(&config),
+            "====DRY RUN====\n\
+             exchange info: https://fapi.binance.com/fapi/v1/exchangeInfo\n\
+             connection 0: wss://fstream.binance.com/ws\n\
+             \x20 subscribe: btcusdt@depth,btcusdt@aggTrade,ethusdt@depth,ethusdt@aggTrade\n\
+             \x20 full book: https://fapi.binance.com/fapi/v1/depth?symbol=BTCUSDT&limit=5\n\
+             \x20 full book: https://fapi.binance.com/fapi/v1/depth?symbol=ETHUSDT&limit=5\n\
+             connection 1: wss://fstream.binance.com/ws\n\
+             \x20 subscribe: bnbusdt@depth,bnbusdt@aggTrade\n\
+             \x20 full book: https://fapi.binance.com/fapi/v1/depth?symbol=BNBUSDT&limit=5\n\
+             connections: 2, instruments: 3\n\
+             ====END DRY RUN====\n"
+        );
+
+        // combined streams are in the url
+        let config = Config {
+            instruments: vec!["btcusdt".to_string()],
+            combined: true,
+            ..config
+        };
+        assert!(dry_run_plan(&config).contains(
+            "connection 0: wss://fstream.binance.com/stream?streams=btcusdt@depth/btcusdt@aggTrade\n"
+        ));
+        assert!(!dry_run_plan(&config).contains("subscribe"));
+    }
+
+    #[tokio::test]
+    async fn dry_run_doesnt_connect_test() {
+        // both websocket and http api would be served by the listener
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let watcher = Watcher::new(Config {
+            instruments: vec!["btcusdt".to_string()],
+            ws_api_url: Some(format!("ws://{address}/ws")),
+            api_url: Some(format!("http://{address}")),
+            dry_run: true,
+            ..Default::default()
+        });
+        tokio::time::timeout(Duration::from_secs(5), watcher.run())
+            .await
+            .expect("dry run should return at once")
+            .unwrap();
+        let accepted = tokio::time::timeout(Duration::from_millis(200), listener.accept()).await;
+        assert!(accepted.is_err(), "dry run connected");
+    }
+
+    #[tokio::test]
+    async fn strict_invalid_symbol_test() {
+        let http = mock_http(|_| {
+            http_response(
+                "200 OK",
+                r#"{"symbols":[{"symbol":"BTCUSDT","status":"TRADING","filters":[]}]}"#,
+            )
+        })
+        .await;
+        let api_url = http.api_url.clone();
+        let watcher = Watcher::new(Config {
+            instruments: vec!["btcusdt".to_string(), "btcusdx".to_string()],
+            api_url: Some(api_url.clone()),
+            strict: true,
+            ..Default::default()
+        });
+        // the caller decides what to do, the process isn't exited
+        let result = tokio::time::timeout(Duration::from_secs(5), watcher.run())
+            .await
+            .expect("strict run should return at once");
+        assert_eq!(result, Err("Invalid symbols with --strict".to_string()));
+        assert_eq!(http.requests.load(Ordering::SeqCst), 1);
+
+        // nothing to watch isn't a success either, strict or not
+        let watcher = Watcher::new(Config {
+            instruments: vec!["btcusdx".to_string()],
+            api_url: Some(api_url),
+            ..Default::default()
+        });
+        let result = tokio::time::timeout(Duration::from_secs(5), watcher.run())
+            .await
+            .expect("run without symbols should return at once");
+        assert_eq!(result, Err("No valid symbols to watch".to_string()));
+    }
+
+    /// error of a btcusdt run failing at its setup, before any connection
+    async fn setup_error(config: Config) -> String {
+        let http = mock_http(|_| {
+            http_response(
+                "200 OK",
+                r#"{"symbols":[{"symbol":"BTCUSDT","status":"TRADING","filters":[]}]}"#,
+            )
+        })
+        .await;
+        let watcher = Watcher::new(Config {
+            instruments: vec!["btcusdt".to_string()],
+            api_url: Some(http.api_url),
+            ..config
+        });
+        tokio::time::timeout(Duration::from_secs(5), watcher.run())
+            .await
+            .expect("failed setup should return at once")
+            .unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn setup_errors_test() {
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let error = setup_error(Config {
+            metrics_port: Some(port),
+            ..Default::default()
+        })
+        .await;
+        assert!(
+            error.starts_with(&format!("Can't bind metrics address 127.0.0.1:{port}: ")),
+            "{error}"
+        );
+
+        let taken = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let error = setup_error(Config {
+            http_port: Some(port),
+            ..Default::default()
+        })
+        .await;
+        assert!(
+            error.starts_with(&format!("Can't bind http address 127.0.0.1:{port}: ")),
+            "{error}"
+        );
+
+        // a file is in the way of the dir
+        let file =
+            std::env::temp_dir().join(format!("binance_watcher_setup_{}.txt", std::process::id()));
+        std::fs::write(&file, "").unwrap();
+        let dir = format!("{}/snapshots", file.display());
+        let error = setup_error(Config {
+            snapshot_dir: Some(dir.clone()),
+            ..Default::default()
+        })
+        .await;
+        assert!(
+            error.starts_with(&format!("Can't create snapshot dir {dir}: ")),
+            "{error}"
+        );
+        let path = format!("{}/record.txt", file.display());
+        let error = setup_error(Config {
+            record: Some(path.clone()),
+            ..Default::default()
+        })
+        .await;
+        assert!(
+            error.starts_with(&format!("Can't create record file {path}: ")),
+            "{error}"
+        );
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    /// context of a handler under test, fields are overridden where a test needs it
+    fn test_context(config: Config) -> HandlerContext {
+        HandlerContext {
+            is_app_running: Arc::new(AtomicBool::new(true)),
+            config: Arc::new(config),
+            http_client: reqwest::Client::new(),
+            metrics: Arc::new(Metrics::default()),
+            recorder: None,
+            events: broadcast::channel(BOOK_EVENTS_CAPACITY).0,
+            renders: Arc::new(PendingRenders::default()),
+            http_requests: Arc::new(Semaphore::new(1)),
+            alerts: Arc::new(BookAlerts::new(None, None)),
+            snapshots: None,
+            liquidations: broadcast::channel(BOOK_EVENTS_CAPACITY).0,
+            update_lines: None,
+            precisions: Arc::new(HashMap::new()),
+            commands: Arc::new(Mutex::new(CommandRouter::default())),
+        }
+    }
+
+    #[test]
+    fn publish_book_event_test() {
+        let (events, mut receiver) = broadcast::channel(BOOK_EVENTS_CAPACITY);
+        let context = HandlerContext {
+            events,
+            ..test_context(Config {
+                delay: 1000,
+                ..Default::default()
+            })
+        };
+        let mut order_books: HashMap<String, OrderBook> = HashMap::from([(
+            "btcusdt".to_string(),
+            OrderBook::new(5, "btcusdt".to_string()),
+        )]);
+        let mut resync = BookResync::default();
+        let mut stats = WatchStats::default();
+        order_books
+            .get_mut("btcusdt")
+            .unwrap()
+            .apply_full_book_from_http_api(&FullBook {
+                last_update_id: 10,
+                bids: vec![LevelApi {
+                    price: "10".to_string(),
+                    quantity: "1".to_string(),
+                }],
+                asks: vec![LevelApi {
+                    price: "11".to_string(),
+                    quantity: "1".to_string(),
+                }],
+            })
+            .unwrap();
+
+        let text = r#"{"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":5,"u":15,"pu":4,"b":[["10","2"]],"a":[]}"#;
+        let (symbol, message) = route_message(text, false).unwrap();
+        let book =
+            handle_stream_message(&mut order_books, &mut resync, &mut stats, symbol, message);
+        publish_book(&context, book);
+
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(event.symbol, "btcusdt");
+        assert_eq!(event.mid, Some(number("10.5")));
+        assert_eq!(event.spread, Some(number("1.0")));
+        assert_eq!(
+            event.best_bid,
+            Some(Level {
+                quantity: number("2.0"),
+                price: number("10.0")
+            })
+        );
+        assert_eq!(
+            event.best_ask,
+            Some(Level {
+                quantity: number("1.0"),
+                price: number("11.0")
+            })
+        );
+        assert!(event.ts > 0);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn watcher_updates_test() {
+        let watcher = Watcher::new(Config {
+            delay: 1000,
+            ..Default::default()
+        });
+        let updates = watcher.updates();
+        let context = HandlerContext {
+            config: watcher.config.clone(),
+            is_app_running: watcher.is_app_running.clone(),
+            events: watcher.events.clone(),
+            ..test_context(Config::default())
+        };
+        let mut order_books: HashMap<String, OrderBook> = HashMap::from([(
+            "btcusdt".to_string(),
+            OrderBook::new(5, "btcusdt".to_string()),
+        )]);
+        let mut resync = BookResync::default();
+        let mut stats = WatchStats::default();
+        order_books
+            .get_mut("btcusdt")
+            .unwrap()
+            .apply_full_book_from_http_api(&FullBook {
+                last_update_id: 10,
+                bids: vec![],
+                asks: vec![LevelApi {
+                    price: "11".to_string(),
+                    quantity: "1".to_string(),
+                }],
+            })
+            .unwrap();
+
+        for text in [
+            r#"{"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":5,"u":15,"pu":4,"b":[["10","1"]],"a":[]}"#,
+            r#"{"e":"depthUpdate","E":2,"T":2,"s":"BTCUSDT","U":16,"u":20,"pu":15,"b":[["9","1"]],"a":[]}"#,
+        ] {
+            let (symbol, message) = route_message(text, false).unwrap();
+            let book =
+                handle_stream_message(&mut order_books, &mut resync, &mut stats, symbol, message);
+            publish_book(&context, book);
+        }
+
+        let events: Vec<BookEvent> = updates.take(2).collect().await;
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| event.symbol == "btcusdt"));
+        assert_eq!(events[0].mid, Some(number("10.5")));
+        assert_eq!(
+            events[1].best_bid,
+            Some(Level {
+                quantity: number("1.0"),
+                price: number("10.0")
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_frames_test() {
+        let mut order_books: HashMap<String, OrderBook> = HashMap::from([(
+            "btcusdt".to_string(),
+            OrderBook::new(5, "btcusdt".to_string()),
+        )]);
+        let mut resync = BookResync::default();
+        let mut stats = WatchStats::default();
+        resync.request("btcusdt");
+        resync.next_request();
+        let full_book = FullBook {
+            last_update_id: 10,
+            bids: vec![],
+            asks: vec![],
+        };
+        resync
+            .complete(order_books.get_mut("btcusdt").unwrap(), &full_book)
+            .unwrap();
+
+        // garbage frames aren't routed
+        assert!(route_message("garbage", false).is_err());
+        let text = r#"{"stream":"btcusdt@depth","data":{"e":"depthUpdate","U":"garbage"}}"#;
+        assert!(route_message(text, true).is_err());
+
+        // trades with invalid price are skipped, the book is kept
+        let text = r#"{"e":"aggTrade","E":3,"s":"BTCUSDT","a":1,"p":"abc","q":"1","f":1,"l":1,"T":3,"m":true}"#;
+        let (symbol, message) = route_message(text, false).unwrap();
+        assert!(
+            handle_stream_message(&mut order_books, &mut resync, &mut stats, symbol, message)
+                .is_none()
+        );
+        assert!(!resync.needs_resync("btcusdt"));
+
+        // depth update with invalid price isn't applied, the book is resynced
+        let text = r#"{"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":5,"u":15,"pu":4,"b":[["abc","1"]],"a":[]}"#;
+        let (symbol, message) = route_message(text, false).unwrap();
+        assert!(
+            handle_stream_message(&mut order_books, &mut resync, &mut stats, symbol, message)
+                .is_none()
+        );
+        assert_eq!(order_books["btcusdt"].get_last_update_id(), 10);
+        assert!(resync.needs_resync("btcusdt"));
+
+        // frames of not watched symbols are ignored
+        let text =
+            r#"{"e":"depthUpdate","E":1,"T":1,"s":"ETHUSDT","U":5,"u":15,"pu":4,"b":[],"a":[]}"#;
+        let (symbol, message) = route_message(text, false).unwrap();
+        assert!(
+            handle_stream_message(&mut order_books, &mut resync, &mut stats, symbol, message)
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn close_frame_test() {
+        let (client, server) = tokio::io::duplex(4096);
+        let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+        let mut server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+        server
+            .send(Message::Close(Some(CloseFrame {
+                code: CloseCode::Away,
+                reason: "maintenance".into(),
+            })))
+            .await
+            .unwrap();
+
+        let context = test_context(Config {
+            read_timeout: 1000,
+            ..Default::default()
+        });
+        let (write, read) = client.split();
+        let exit = handle_updates(
+            &context,
+            &mut vec![],
+            &mut WatchStats::default(),
+            &mut mpsc::unbounded_channel().1,
+            read,
+            write,
+        )
+        .await;
+        assert!(matches!(exit, HandlerExit::Disconnected));
+    }
+
+    #[tokio::test]
+    async fn join_handlers_test() {
+        let stats = join_handlers(vec![
+            tokio::spawn(async { WatchStats::default() }),
+            tokio::spawn(async { WatchStats::default() }),
+        ])
+        .await;
+        assert!(stats.is_ok());
+
+        let error = join_handlers(vec![
+            tokio::spawn(async { WatchStats::default() }),
+            tokio::spawn(async { panic!("broken handler") }),
+        ])
+        .await
+        .unwrap_err();
+        assert!(error.starts_with("Connection handler failed: "), "{error}");
+        assert!(error.contains("broken handler"), "{error}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_timeout_test() {
+        let is_app_running = AtomicBool::new(true);
+        let timeout = Duration::from_millis(300);
+
+        // handler finished in time
+        let finished = join_with_shutdown_timeout(async { 1 }, &is_app_running, timeout).await;
+        assert_eq!(finished, Some(1));
+
+        // handler stalled, e.g. on a hung http request, before and after stop
+        let stalled = std::future::pending::<()>();
+        let stop = async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            is_app_running.store(false, Ordering::SeqCst);
+        };
+        let started = tokio::time::Instant::now();
+        let (result, _) = tokio::join!(
+            join_with_shutdown_timeout(stalled, &is_app_running, timeout),
+            stop
+        );
+        assert_eq!(result, None);
+        // the stop is noticed on the next check, then the timeout runs
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(500));
+        assert!(elapsed <= Duration::from_millis(500) + STOP_CHECK_INTERVAL);
+    }
+
+    #[tokio::test]
+    async fn connect_timeout_test() {
+        // accepts tcp connections but never answers websocket handshake, like a black hole
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/ws", listener.local_addr().unwrap());
+        let _server = tokio::spawn(async move {
+            let mut sockets = vec![];
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        let timeout = Duration::from_millis(200);
+        // with permessage-deflate offered as well
+        for compress in [false, true] {
+            let started = Instant::now();
+            let result = connect_to_binance(&url, timeout, compress).await;
+            let elapsed = started.elapsed();
+            assert_eq!(result.err(), Some("timed out after 200ms".to_string()));
+            assert!(elapsed >= timeout);
+            assert!(elapsed < timeout * 3);
+        }
+    }
+
+    #[tokio::test]
+    async fn limited_requests_test() {
+        let semaphore = Semaphore::new(3);
+        let in_flight = AtomicUsize::new(0);
+        let max_in_flight = AtomicUsize::new(0);
+        let (in_flight, max_in_flight) = (&in_flight, &max_in_flight);
+        let request = |id: usize| {
+            limited(&semaphore, async move {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(current, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                id
+            })
+        };
+
+        let results = futures_util::future::join_all((0..20).map(request)).await;
+        assert_eq!(results, (0..20).collect::<Vec<_>>());
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 3);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn sigterm_test() {
+        let watcher = Watcher::new(Config::default());
+        let stopping = tokio::spawn(stop_on_terminate(watcher.clone()).unwrap());
+        let status = std::process::Command::new("kill")
+            .args(["-TERM", &std::process::id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        tokio::time::timeout(Duration::from_secs(5), stopping)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!watcher.is_app_running.load(Ordering::SeqCst));
+    }
+
+    /// lines logged by the fmt subscriber, without colors
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CapturedLogs {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    /// info logs of the current thread are captured until the guard is dropped
+    fn capture_logs() -> (CapturedLogs, tracing::subscriber::DefaultGuard) {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_max_level(tracing::Level::INFO)
+            .with_ansi(false)
+            .finish();
+        (logs, tracing::subscriber::set_default(subscriber))
+    }
+
+    #[tokio::test]
+    async fn subscription_log_test() {
+        let (logs, _default) = capture_logs();
+
+        let (client, server) = tokio::io::duplex(4096);
+        let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+        let mut server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+        server.send(Message::Close(None)).await.unwrap();
+
+        let context = test_context(Config {
+            read_timeout: 1000,
+            ..Default::default()
+        });
+        let (write, read) = client.split();
+        let span = info_span!("connection", id = 0);
+        handle_updates(
+            &context,
+            &mut vec!["btcusdt".to_string()],
+            &mut WatchStats::default(),
+            &mut mpsc::unbounded_channel().1,
+            read,
+            write,
+        )
+        .instrument(span)
+        .await;
+
+        let text = logs.text();
+        let subscription = text
+            .lines()
+            .find(|line| line.contains("Subscribe to topic"))
+            .unwrap();
+        assert!(subscription.contains(" INFO connection{id=0}: "));
+        assert!(subscription.ends_with(r#" symbol="btcusdt""#));
+    }
+
+    #[test]
+    fn ndjson_updates_test() {
+        let lines = Arc::new(Mutex::new(vec![]));
+        let output = lines.clone();
+        let context = HandlerContext {
+            update_lines: Some(Arc::new(move |line| output.lock().unwrap().push(line))),
+            ..test_context(Config::default())
+        };
+        let mut book = OrderBook::new(5, "btcusdt".to_string());
+        let full_book: FullBook =
+            serde_json::from_str(r#"{"lastUpdateId":10,"bids":[["10","1"]],"asks":[["11","2"]]}"#)
+                .unwrap();
+        book.apply_full_book_from_http_api(&full_book).unwrap();
+        let mut order_books = HashMap::from([("btcusdt".to_string(), book)]);
+        let mut resync = BookResync::default();
+        let mut acks = PendingAcks::default();
+        let mut stats = WatchStats::default();
+
+        for text in [
+            r#"{"e":"depthUpdate","E":1001,"T":1,"s":"BTCUSDT","U":9,"u":11,"pu":0,"b":[["10","3"]],"a":[]}"#,
+            r#"{"e":"aggTrade","E":1002,"s":"BTCUSDT","a":1,"p":"10.5","q":"1","f":1,"l":1,"T":1002,"m":true}"#,
+            r#"{"e":"depthUpdate","E":1003,"T":1,"s":"BTCUSDT","U":12,"u":13,"pu":11,"b":[],"a":[["10.5","1"]]}"#,
+            // already applied
+            r#"{"e":"depthUpdate","E":1001,"T":1,"s":"BTCUSDT","U":9,"u":11,"pu":0,"b":[["10","3"]],"a":[]}"#,
+        ] {
+            handle_text_frame(
+                &context,
+                &mut order_books,
+                &mut resync,
+                &mut acks,
+                &mut stats,
+                text,
+            );
+        }
+
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 2);
+        let records: Vec<serde_json::Value> = lines
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records[0]["symbol"], "btcusdt");
+        assert_eq!(records[0]["last_update_id"], 11);
+        assert_eq!(records[0]["best_bid"]["quantity"], 3.0);
+        assert_eq!(records[0]["event_time"], 1001);
+        assert_eq!(records[1]["last_update_id"], 13);
+        assert_eq!(records[1]["mid"], 10.25);
+        assert_eq!(records[1]["spread"], 0.5);
+        assert_eq!(records[1]["best_ask"]["price"], 10.5);
+        assert_eq!(records[1]["event_time"], 1003);
+    }
+
+    #[tokio::test]
+    async fn subscription_error_ack_test() {
+        let (logs, _default) = capture_logs();
+
+        let (client, server) = tokio::io::duplex(4096);
+        let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+        let mut server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+        let server = async move {
+            let subscription = server.next().await.unwrap().unwrap();
+            let subscription: Subscription =
+                serde_json::from_str(subscription.to_text().unwrap()).unwrap();
+            let ack = format!(
+                r#"{{"error":{{"code":2,"msg":"Invalid request: unknown symbol"}},"id":"{}"}}"#,
+                subscription.id
+            );
+            server.send(Message::Text(ack.into())).await.unwrap();
+            server.send(Message::Close(None)).await.unwrap();
+        };
+
+        let context = test_context(Config {
+            read_timeout: 1000,
+            ..Default::default()
+        });
+        let (write, read) = client.split();
+        let mut symbols = vec!["btcusdtt".to_string()];
+        let mut stats = WatchStats::default();
+        let (_commands_sender, mut commands) = mpsc::unbounded_channel();
+        tokio::join!(
+            handle_updates(
+                &context,
+                &mut symbols,
+                &mut stats,
+                &mut commands,
+                read,
+                write
+            ),
+            server
+        );
+
+        let text = logs.text();
+        let failure = text
+            .lines()
+            .find(|line| line.contains("Subscription failed"))
+            .unwrap();
+        assert!(failure.contains(" WARN "));
+        assert!(failure.contains(r#"(2 Invalid request: unknown symbol) symbol="btcusdtt""#));
+    }
+
+    #[tokio::test]
+    async fn symbol_commands_test() {
+        let (client, server) = tokio::io::duplex(4096);
+        let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+        let mut server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+        let server = async move {
+            let mut frames = vec![];
+            for _ in 0..3 {
+                let frame = server.next().await.unwrap().unwrap();
+                let frame: Subscription = serde_json::from_str(frame.to_text().unwrap()).unwrap();
+                frames.push((frame.method, frame.params));
+            }
+            server.send(Message::Close(None)).await.unwrap();
+            frames
+        };
+
+        let snapshots = Arc::new(LatestSnapshots::default());
+        snapshots.publish(&OrderBook::new(5, "btcusdt".to_string()));
+        let metrics = Arc::new(Metrics::default());
+        metrics.record_book_update(&OrderBook::new(5, "btcusdt".to_string()));
+        let context = HandlerContext {
+            snapshots: Some(snapshots.clone()),
+            metrics: metrics.clone(),
+            ..test_context(Config {
+                read_timeout: 1000,
+                streams: vec![StreamType::Depth],
+                ..Default::default()
+            })
+        };
+        let mut router = CommandRouter::default();
+        let mut symbols = vec!["btcusdt".to_string()];
+        let mut commands = router.add_connection(&symbols);
+        router.route("add ethusdt".parse().unwrap()).unwrap();
+        router.route("remove btcusdt".parse().unwrap()).unwrap();
+
+        let (write, read) = client.split();
+        let mut stats = WatchStats::default();
+        let (_, frames) = tokio::join!(
+            handle_updates(
+                &context,
+                &mut symbols,
+                &mut stats,
+                &mut commands,
+                read,
+                write
+            ),
+            server
+        );
+        let frame = |method: &str, topic: &str| (method.to_string(), vec![topic.to_string()]);
+        assert_eq!(
+            frames,
+            vec![
+                frame("SUBSCRIBE", "btcusdt@depth"),
+                frame("SUBSCRIBE", "ethusdt@depth"),
+                frame("UNSUBSCRIBE", "btcusdt@depth"),
+            ]
+        );
+        // symbols are kept for the next connection
+        assert_eq!(symbols, vec!["ethusdt".to_string()]);
+        // the removed book isn't served nor exported anymore
+        assert!(snapshots.get("btcusdt").is_none());
+        assert!(!metrics.render().contains(r#"symbol="btcusdt""#));
+    }
+
+    #[tokio::test]
+    async fn invalid_symbol_test() {
+        let binance = MockBinance::start(vec![r#"{"code":-1121,"msg":"Invalid symbol."}"#]).await;
+        let (client, server) = tokio::io::duplex(4096);
+        let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+        let mut server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+        let server = async move {
+            let mut methods = vec![];
+            for _ in 0..2 {
+                let frame = server.next().await.unwrap().unwrap();
+                let frame: Subscription = serde_json::from_str(frame.to_text().unwrap()).unwrap();
+                methods.push(frame.method);
+            }
+            server.send(Message::Close(None)).await.unwrap();
+            methods
+        };
+
+        let context = test_context(binance.config());
+        let mut symbols = vec!["btcusdx".to_string()];
+        let mut commands = context.commands.lock().unwrap().add_connection(&symbols);
+        let (write, read) = client.split();
+        let mut stats = WatchStats::default();
+        let (_, methods) = tokio::join!(
+            handle_updates(
+                &context,
+                &mut symbols,
+                &mut stats,
+                &mut commands,
+                read,
+                write
+            ),
+            server
+        );
+
+        assert_eq!(methods, vec!["SUBSCRIBE", "UNSUBSCRIBE"]);
+        assert_eq!(binance.depth_requests.load(Ordering::SeqCst), 1);
+        assert!(symbols.is_empty());
+        // the symbol could be added again, e.g. once the typo is fixed
+        let mut router = context.commands.lock().unwrap();
+        assert!(router
+            .route(SymbolCommand::Remove("btcusdx".to_string()))
+            .is_err());
+        assert!(router
+            .route(SymbolCommand::Add("btcusdx".to_string()))
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn broken_write_test() {
+        // frames are read from a live connection, sends go to a closed one
+        async fn run(symbols: &mut Vec<String>, command: Option<&str>, frame: Option<Message>) {
+            let (client, server) = tokio::io::duplex(4096);
+            let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+            let mut server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+            if let Some(frame) = frame {
+                server.send(frame).await.unwrap();
+            }
+            let (closed, _) = tokio::io::duplex(4096);
+            let closed = WebSocketStream::from_raw_socket(closed, Role::Client, None).await;
+
+            // combined streams aren't subscribed after connecting
+            let context = test_context(Config {
+                read_timeout: 5000,
+                streams: vec![StreamType::Depth],
+                combined: true,
+                ..Default::default()
+            });
+            let mut router = CommandRouter::default();
+            let mut commands = router.add_connection(symbols);
+            if let Some(command) = command {
+                router.route(command.parse().unwrap()).unwrap();
+            }
+            let (_, read) = client.split();
+            let (write, _) = closed.split();
+            let exit = tokio::time::timeout(
+                Duration::from_secs(1),
+                handle_updates(
+                    &context,
+                    symbols,
+                    &mut WatchStats::default(),
+                    &mut commands,
+                    read,
+                    write,
+                ),
+            )
+            .await
+            .expect("handler didn't exit");
+            assert!(matches!(exit, HandlerExit::Disconnected));
+            drop(server);
+        }
+
+        let mut symbols = vec![];
+        run(&mut symbols, Some("add ethusdt"), None).await;
+        // the symbol is subscribed by the next connection
+        assert_eq!(symbols, vec!["ethusdt".to_string()]);
+
+        run(&mut symbols, Some("remove ethusdt"), None).await;
+        assert!(symbols.is_empty());
+
+        run(&mut symbols, None, Some(Message::Ping(vec![1].into()))).await;
+    }
+
+    #[tokio::test]
+    async fn binary_frame_test() {
+        let (client, server) = tokio::io::duplex(4096);
+        let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+        let mut server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+        let frame = r#"{"e":"fullBook","s":"btcusdt","lastUpdateId":100,"bids":[["10","1"]],"asks":[["11","1"]]}"#;
+        server
+            .send(Message::Binary(frame.as_bytes().to_vec().into()))
+            .await
+            .unwrap();
+        server
+            .send(Message::Binary(vec![0xff, 0xfe].into()))
+            .await
+            .unwrap();
+        server.send(Message::Close(None)).await.unwrap();
+
+        let (events, mut receiver) = broadcast::channel(BOOK_EVENTS_CAPACITY);
+        let context = HandlerContext {
+            events,
+            ..test_context(Config {
+                read_timeout: 1000,
+                delay: 1000,
+                levels: 5,
+                combined: true,
+                // full book requests fail, the book comes from the binary frame
+                api_url: Some("http://127.0.0.1:1".to_string()),
+                ..Default::default()
+            })
+        };
+        let (write, read) = client.split();
+        let exit = handle_updates(
+            &context,
+            &mut vec!["btcusdt".to_string()],
+            &mut WatchStats::default(),
+            &mut mpsc::unbounded_channel().1,
+            read,
+            write,
+        )
+        .await;
+        assert!(matches!(exit, HandlerExit::Disconnected));
+
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(event.symbol, "btcusdt");
+        assert_eq!(event.mid, Some(number("10.5")));
+    }
+
+    #[tokio::test]
+    async fn compressed_frames_test() {
+        use soketto::connection::Mode;
+        use soketto::extension::deflate::Deflate;
+        use soketto::handshake::{server::Response, Server};
+        use tokio_util::compat::TokioAsyncReadCompatExt;
+
+        // binance accepting permessage-deflate, frames are sent compressed
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/ws", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut server = Server::new(socket.compat());
+            server.add_extension(Box::new(Deflate::new(Mode::Server)));
+            let key = server.receive_request().await.unwrap().key();
+            let accept = Response::Accept {
+                key,
+                protocol: None,
+            };
+            server.send_response(&accept).await.unwrap();
+            let extensions: Vec<_> = server.drain_extensions().collect();
+            assert!(extensions.iter().all(|extension| extension.is_enabled()));
+            let mut builder = server.into_builder();
+            builder.add_extensions(extensions);
+            let (mut sender, _receiver) = builder.finish();
+            let frame = |id: u64, bid: &str| {
+                format!(
+                    r#"{{"e":"fullBook","s":"btcusdt","lastUpdateId":{id},"bids":[["{bid}","1"]],"asks":[["11","1"]]}}"#
+                )
+            };
+            sender.send_text(frame(100, "10")).await.unwrap();
+            sender.send_binary(frame(101, "9")).await.unwrap();
+            sender.close().await.unwrap();
+        });
+
+        let (events, mut receiver) = broadcast::channel(BOOK_EVENTS_CAPACITY);
+        let context = HandlerContext {
+            events,
+            ..test_context(Config {
+                read_timeout: 1000,
+                delay: 1000,
+                levels: 5,
+                combined: true,
+                compress_ws: true,
+                // full book requests fail, books come from the frames
+                api_url: Some("http://127.0.0.1:1".to_string()),
+                ..Default::default()
+            })
+        };
+        let (write, read) = connect_to_binance(&url, Duration::from_secs(1), true)
+            .await
+            .unwrap();
+        let exit = handle_updates(
+            &context,
+            &mut vec!["btcusdt".to_string()],
+            &mut WatchStats::default(),
+            &mut mpsc::unbounded_channel().1,
+            read,
+            write,
+        )
+        .await;
+        assert!(matches!(exit, HandlerExit::Disconnected));
+        server.await.unwrap();
+
+        // text and binary frames are both decompressed and applied
+        assert_eq!(receiver.try_recv().unwrap().mid, Some(number("10.5")));
+        assert_eq!(receiver.try_recv().unwrap().mid, Some(number("10")));
+    }
+
+    /// in-process binance for end-to-end handler tests: the http api answers depth requests
+    /// with the scripted full books, the last one is repeated, the websocket acks the
+    /// subscription and runs the script steps, each one sends its frames and waits for the
+    /// book to get to the update id, the socket is closed afterwards
+    struct MockBinance {
+        api_url: String,
+        depth_requests: Arc<AtomicUsize>,
+    }
+
+    impl MockBinance {
+        async fn start(full_books: Vec<&'static str>) -> Self {
+            let http = mock_http(move |request| {
+                http_response("200 OK", full_books[request.min(full_books.len() - 1)])
+            })
+            .await;
+            MockBinance {
+                api_url: http.api_url,
+                depth_requests: http.requests,
+            }
+        }
+
+        /// depth only config against the mock http api
+        fn config(&self) -> Config {
+            Config {
+                read_timeout: 5000,
+                levels: 5,
+                streams: vec![StreamType::Depth],
+                api_url: Some(self.api_url.clone()),
+                ..Default::default()
+            }
+        }
+
+        /// runs the handler of the symbol against the script, returns its final book
+        async fn watch(
+            &self,
+            symbol: &str,
+            steps: Vec<(Vec<String>, u64)>,
+        ) -> (BookSnapshot, WatchStats) {
+            self.watch_with(self.config(), symbol, steps).await
+        }
+
+        async fn watch_with(
+            &self,
+            config: Config,
+            symbol: &str,
+            steps: Vec<(Vec<String>, u64)>,
+        ) -> (BookSnapshot, WatchStats) {
+            let (client, server) = tokio::io::duplex(65536);
+            let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+            let mut server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+            let snapshots = Arc::new(LatestSnapshots::default());
+            let book = snapshots.clone();
+            let book_symbol = symbol.to_string();
+            let server = async move {
+                let subscription = server.next().await.unwrap().unwrap();
+                let subscription: Subscription =
+                    serde_json::from_str(subscription.to_text().unwrap()).unwrap();
+                let ack = format!(r#"{{"result":null,"id":"{}"}}"#, subscription.id);
+                server.send(Message::Text(ack.into())).await.unwrap();
+                for (frames, update_id) in steps {
+                    for frame in frames {
+                        server.send(Message::Text(frame.into())).await.unwrap();
+                    }
+                    let started = Instant::now();
+                    while book
+                        .get(&book_symbol)
+                        .is_none_or(|book| book.last_update_id != update_id)
+                    {
+                        assert!(
+                            started.elapsed() < Duration::from_secs(5),
+                            "book isn't at {update_id}"
+                        );
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                }
+                server.send(Message::Close(None)).await.unwrap();
+            };
+
+            let context = HandlerContext {
+                snapshots: Some(snapshots.clone()),
+                ..test_context(config)
+            };
+            let (write, read) = client.split();
+            let mut stats = WatchStats::default();
+            let mut symbols = vec![symbol.to_string()];
+            let (_commands_sender, mut commands) = mpsc::unbounded_channel();
+            let (exit, _) = tokio::join!(
+                handle_updates(
+                    &context,
+                    &mut symbols,
+                    &mut stats,
+                    &mut commands,
+                    read,
+                    write
+                ),
+                server
+            );
+            assert!(matches!(exit, HandlerExit::Disconnected));
+            (snapshots.get(symbol).unwrap(), stats)
+        }
+    }
+
+    fn depth_frame(first: u64, last: u64, previous: u64, bids: &str, asks: &str) -> String {
+        format!(
+            r#"{{"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":{first},"u":{last},"pu":{previous},"b":{bids},"a":{asks}}}"#
+        )
+    }
+
+    #[tokio::test]
+    async fn mock_binance_sync_test() {
+        let binance = MockBinance::start(vec![
+            r#"{"lastUpdateId":100,"bids":[["10","1"]],"asks":[["11","1"]]}"#,
+        ])
+        .await;
+        let frames = vec![
+            // already in the full book
+            depth_frame(80, 90, 79, r#"[["10","5"]]"#, "[]"),
+            depth_frame(95, 105, 94, r#"[["10","2"]]"#, "[]"),
+            depth_frame(106, 110, 105, "[]", r#"[["11.5","3"]]"#),
+            depth_frame(111, 112, 110, r#"[["9","4"]]"#, r#"[["11","2"]]"#),
+            // removal by zero quantity
+            depth_frame(113, 114, 112, "[]", r#"[["11","0"]]"#),
+        ];
+
+        let (book, stats) = binance.watch("btcusdt", vec![(frames, 114)]).await;
+
+        let level = |price, quantity| Level {
+            price: number(price),
+            quantity: number(quantity),
+        };
+        assert_eq!(book.bids, vec![level("10.0", "2.0"), level("9.0", "4.0")]);
+        assert_eq!(book.asks, vec![level("11.5", "3.0")]);
+        assert_eq!(binance.depth_requests.load(Ordering::SeqCst), 1);
+        let stats = stats.get("btcusdt").unwrap();
+        assert_eq!((stats.resyncs, stats.gaps), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn mock_binance_resync_test() {
+        let binance = MockBinance::start(vec![
+            r#"{"lastUpdateId":100,"bids":[["10","1"]],"asks":[["11","1"]]}"#,
+            r#"{"lastUpdateId":200,"bids":[["8","1"]],"asks":[["12","1"]]}"#,
+        ])
+        .await;
+        let steps = vec![
+            (vec![depth_frame(95, 105, 94, r#"[["10","2"]]"#, "[]")], 105),
+            // 106..=149 are missed, the book is requested again
+            (
+                vec![
+                    depth_frame(150, 155, 149, r#"[["9","1"]]"#, "[]"),
+                    depth_frame(156, 160, 155, r#"[["9","2"]]"#, "[]"),
+                ],
+                200,
+            ),
+            (
+                vec![depth_frame(
+                    195,
+                    205,
+                    194,
+                    r#"[["8","3"]]"#,
+                    r#"[["12.5","1"]]"#,
+                )],
+                205,
+            ),
+        ];
+
+        let (book, stats) = binance.watch("btcusdt", steps).await;
+
+        let level = |price, quantity| Level {
+            price: number(price),
+            quantity: number(quantity),
+        };
+        assert_eq!(book.bids, vec![level("8.0", "3.0")]);
+        assert_eq!(book.asks, vec![level("12.0", "1.0"), level("12.5", "1.0")]);
+        assert_eq!(binance.depth_requests.load(Ordering::SeqCst), 2);
+        let stats = stats.get("btcusdt").unwrap();
+        assert_eq!((stats.resyncs, stats.gaps), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn mock_binance_partial_depth_test() {
+        let binance = MockBinance::start(vec![]).await;
+        let config = Config {
+            partial_depth: true,
+            ..binance.config()
+        };
+        assert_eq!(symbol_topics("btcusdt", &config), vec!["btcusdt@depth5"]);
+        let steps = vec![
+            (
+                vec![depth_frame(
+                    90,
+                    100,
+                    89,
+                    r#"[["10","1"],["9","2"]]"#,
+                    r#"[["11","1"]]"#,
+                )],
+                100,
+            ),
+            // ids of partial depth aren't chained, each payload is the book
+            (
+                vec![depth_frame(
+                    150,
+                    160,
+                    149,
+                    r#"[["10","3"]]"#,
+                    r#"[["10.5","1"],["11","4"]]"#,
+                )],
+                160,
+            ),
+        ];
+
+        let (book, stats) = binance.watch_with(config, "btcusdt", steps).await;
+
+        let level = |price, quantity| Level {
+            price: number(price),
+            quantity: number(quantity),
+        };
+        assert_eq!(book.bids, vec![level("10.0", "3.0")]);
+        assert_eq!(book.asks, vec![level("10.5", "1.0"), level("11.0", "4.0")]);
+        assert_eq!(binance.depth_requests.load(Ordering::SeqCst), 0);
+        let stats = stats.get("btcusdt").unwrap();
+        assert_eq!((stats.depth_updates, stats.resyncs, stats.gaps), (2, 0, 0));
+    }
+}
@@ -0,0 +1,62 @@
+use std::process::{Command, Output, Stdio};
+
+const RECORDING: &str = concat!(
+    r#"1000 {"e":"fullBook","s":"btcusdt","lastUpdateId":100,"bids":[["10","1"]],"asks":[["11","1"]]}"#,
+    "\n",
+    r#"1010 {"e":"depthUpdate","E":1,"T":1,"s":"BTCUSDT","U":101,"u":105,"pu":100,"b":[["10","2"]],"a":[]}"#,
+    "\n",
+);
+
+/// replay of a btcusdt recording by the binary, books printed with the format
+fn replay(format: &str) -> Output {
+    let path = std::env::temp_dir().join(format!(
+        "binance_watcher_output_{format}_{}.txt",
+        std::process::id()
+    ));
+    std::fs::write(&path, RECORDING).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_binance_watcher"))
+        .args([
+            "-i",
+            "btcusdt",
+            "-f",
+            format,
+            "replay",
+            path.to_str().unwrap(),
+        ])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+#[test]
+fn json_stdout_test() {
+    let output = replay("json");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.lines().count() > 0);
+    for line in stdout.lines() {
+        let book: serde_json::Value = serde_json::from_str(line).expect(line);
+        assert_eq!(book["symbol"], "btcusdt");
+    }
+    // parameters are still shown, apart from books
+    assert!(String::from_utf8_lossy(&output.stderr).contains("====START PARAMETERS===="));
+}
+
+#[test]
+fn csv_stdout_test() {
+    let stdout = String::from_utf8(replay("csv").stdout).unwrap();
+    let mut lines = stdout.lines();
+    let header = lines.next().unwrap();
+    assert!(header.starts_with("symbol,"));
+    assert!(lines.clone().count() > 0);
+    for line in lines {
+        assert!(line.starts_with("btcusdt,"), "{line}");
+        assert_eq!(line.split(',').count(), header.split(',').count(), "{line}");
+    }
+}